@@ -1,9 +1,15 @@
 global start
+global stack_top
+global gdt64_pointer
 extern long_mode_start
-section .text
+
+; the kernel is linked at this virtual base, but loaded at its physical address
+KERNEL_OFFSET equ 0xffffffff80000000
+
+section .boot.text progbits alloc exec nowrite align=16
 bits 32
 start:
-    mov esp, stack_top
+    mov esp, stack_top - KERNEL_OFFSET
     mov edi, ebx
     call check_multiboot
     call check_cpuid
@@ -11,7 +17,7 @@ start:
     call set_up_page_tables
     call enable_paging
     ; load the 64-bit GDT
-    lgdt [gdt64.pointer]
+    lgdt [gdt64.pointer - KERNEL_OFFSET]
 
     jmp gdt64.code:long_mode_start
     hlt
@@ -86,20 +92,32 @@ check_long_mode:
     jmp error
 
 set_up_page_tables:
-    ; map first P4 entry to P3 table
-    mov eax, p3_table
+    ; map first P4 entry to P3 table (temporary identity mapping)
+    mov eax, p3_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p4_table], eax
+    mov [p4_table - KERNEL_OFFSET], eax
 
-    ; map last p4 entry to P4 table
-    mov eax, p4_table
+    ; map P4 entry 256 to the same P3 table, so that the first GiB
+    ; is also reachable through the direct physical memory map
+    mov [p4_table - KERNEL_OFFSET + 256 * 8], eax
+
+    ; map P4 entry 510 to P4 table (recursive mapping)
+    mov eax, p4_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p4_table + 511 * 8], eax
+    mov [p4_table - KERNEL_OFFSET + 510 * 8], eax
+
+    ; map last P4 entry to the higher half P3 table
+    mov eax, p3_table_high - KERNEL_OFFSET
+    or eax, 0b11 ; present + writable
+    mov [p4_table - KERNEL_OFFSET + 511 * 8], eax
 
     ; map first P3 entry to P2 table
-    mov eax, p2_table
+    mov eax, p2_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p3_table], eax
+    mov [p3_table - KERNEL_OFFSET], eax
+
+    ; map P3 entry 510 of the higher half (0xffffffff80000000) to the same P2 table
+    mov [p3_table_high - KERNEL_OFFSET + 510 * 8], eax
     ; map each P2 entry to a huge 2MiB page
     mov ecx, 0         ; counter variable
 .map_p2_table:
@@ -107,7 +125,7 @@ set_up_page_tables:
     mov eax, 0x200000  ; 2MiB
     mul ecx            ; start address of ecx-th page
     or eax, 0b10000011 ; present + writable + huge
-    mov [p2_table + ecx * 8], eax ; map ecx-th entry
+    mov [p2_table - KERNEL_OFFSET + ecx * 8], eax ; map ecx-th entry
 
     inc ecx            ; increase counter
     cmp ecx, 512       ; if counter == 512, the whole P2 table is mapped
@@ -116,7 +134,7 @@ set_up_page_tables:
 
 enable_paging:
     ; load P4 to cr3 register (cpu uses this to access the P4 table)
-    mov eax, p4_table
+    mov eax, p4_table - KERNEL_OFFSET
     mov cr3, eax
 
     ; enable PAE-flag in cr4 (Physical Address Extension)
@@ -145,6 +163,8 @@ p3_table:
     resb 4096
 p2_table:
     resb 4096
+p3_table_high:
+    resb 4096
 stack_bottom:
     resb 4096 * 4
 stack_top:
@@ -154,6 +174,11 @@ gdt64:
     dq 0 ; zero entry
 .code: equ $ - gdt64
     dq (1<<43) | (1<<44) | (1<<47) | (1<<53) ; code segment
+.end:
 .pointer:
-    dw $ - gdt64 - 1
+    dw .end - gdt64 - 1
+    dq gdt64 - KERNEL_OFFSET
+; reloaded from the higher half once long mode is active
+gdt64_pointer:
+    dw gdt64.end - gdt64 - 1
     dq gdt64
\ No newline at end of file
@@ -1,6 +1,8 @@
 global long_mode_start
+extern stack_top
+extern gdt64_pointer
 
-section .text
+section .boot.text progbits alloc exec nowrite align=16
 bits 64
 long_mode_start:
     mov ax, 0
@@ -14,7 +16,21 @@ long_mode_start:
     ; mov rax, 0x2f592f412f4b2f4f
     ; mov qword [0xb8000], rax
 
+    ; jump to the higher half, the kernel is linked there
+    mov rax, higher_half_start
+    jmp rax
+
+section .text
+bits 64
+higher_half_start:
+    ; switch to the higher half addresses of the stack and the GDT
+    mov rsp, stack_top
+    lgdt [gdt64_pointer]
+
+    ; a zero frame pointer ends the backtrace
+    xor rbp, rbp
+
     ; call the rust main
     extern rust_main     ; new
     call rust_main       ; new
-    hlt
\ No newline at end of file
+    hlt
@@ -0,0 +1,232 @@
+use bitflags::bitflags;
+
+// ELF64文件头和程序头的解析，只支持x86_64的小端可执行文件
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const EM_X86_64: u16 = 62;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfType {
+    // 静态链接的可执行文件，按文件中的地址加载
+    Executable,
+    // 位置无关的可执行文件，加载时加上一个基址
+    SharedObject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    UnsupportedClass,
+    UnsupportedEncoding,
+    UnsupportedVersion,
+    UnsupportedMachine,
+    UnsupportedType,
+    BadProgramHeaders,
+    BadSegment,
+    // 需要动态链接器的程序
+    Interpreter,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Elf64Header {
+    pub e_ident: [u8; 16],
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentType {
+    Null = 0,
+    Load = 1,
+    Dynamic = 2,
+    Interp = 3,
+    Note = 4,
+    Phdr = 6,
+    Tls = 7,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SegmentFlags: u32 {
+        const PF_X = 0x1;
+        const PF_W = 0x2;
+        const PF_R = 0x4;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Elf64ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl Elf64ProgramHeader {
+    pub fn is_type(&self, segment_type: SegmentType) -> bool {
+        self.p_type == segment_type as u32
+    }
+
+    pub fn flags(&self) -> SegmentFlags {
+        SegmentFlags::from_bits_truncate(self.p_flags)
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.p_vaddr as usize
+    }
+
+    pub fn end_address(&self) -> usize {
+        self.start_address() + self.mem_size()
+    }
+
+    pub fn file_range(&self) -> core::ops::Range<usize> {
+        self.p_offset as usize..(self.p_offset + self.p_filesz) as usize
+    }
+
+    pub fn file_size(&self) -> usize {
+        self.p_filesz as usize
+    }
+
+    pub fn mem_size(&self) -> usize {
+        self.p_memsz as usize
+    }
+}
+
+// 验证过的ELF文件，程序头和PT_LOAD段都在文件范围内
+#[derive(Debug, Clone, Copy)]
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: Elf64Header,
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        let header: Elf64Header = read(data, 0).ok_or(ElfError::TooShort)?;
+        let ident = &header.e_ident;
+        if ident[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if ident[4] != ELFCLASS64 {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if ident[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedEncoding);
+        }
+        if ident[6] != EV_CURRENT || header.e_version != EV_CURRENT as u32 {
+            return Err(ElfError::UnsupportedVersion);
+        }
+        if header.e_machine != EM_X86_64 {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        let elf = ElfFile { data, header };
+        elf.elf_type()?;
+        if header.e_phentsize as usize != core::mem::size_of::<Elf64ProgramHeader>()
+            || header.e_phnum == 0
+        {
+            return Err(ElfError::BadProgramHeaders);
+        }
+        for index in 0..header.e_phnum as usize {
+            let program_header = elf
+                .program_header(index)
+                .ok_or(ElfError::BadProgramHeaders)?;
+            if program_header.is_type(SegmentType::Interp) {
+                return Err(ElfError::Interpreter);
+            }
+            if program_header.is_type(SegmentType::Load) {
+                check_segment(&program_header, data.len())?;
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn header(&self) -> &Elf64Header {
+        &self.header
+    }
+
+    pub fn elf_type(&self) -> Result<ElfType, ElfError> {
+        match self.header.e_type {
+            2 => Ok(ElfType::Executable),
+            3 => Ok(ElfType::SharedObject),
+            _ => Err(ElfError::UnsupportedType),
+        }
+    }
+
+    pub fn entry_point(&self) -> usize {
+        self.header.e_entry as usize
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.header.e_phnum as usize
+    }
+
+    pub fn program_header(&self, index: usize) -> Option<Elf64ProgramHeader> {
+        if index >= self.program_header_count() {
+            return None;
+        }
+        let offset = (self.header.e_phoff as usize)
+            .checked_add(index * core::mem::size_of::<Elf64ProgramHeader>())?;
+        read(self.data, offset)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = Elf64ProgramHeader> + '_ {
+        (0..self.program_header_count()).filter_map(|index| self.program_header(index))
+    }
+
+    // 需要加载到内存中的段
+    pub fn load_segments(&self) -> impl Iterator<Item = Elf64ProgramHeader> + '_ {
+        self.program_headers()
+            .filter(|header| header.is_type(SegmentType::Load))
+    }
+
+    // 段在文件中的内容，parse已经检查过范围
+    pub fn segment_data(&self, segment: &Elf64ProgramHeader) -> &'a [u8] {
+        &self.data[segment.file_range()]
+    }
+}
+
+// 文件中的内容不在内存中，也可能没有对齐，只能逐字节复制出来
+fn read<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(core::mem::size_of::<T>())?;
+    let bytes = data.get(offset..end)?;
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+fn check_segment(segment: &Elf64ProgramHeader, file_len: usize) -> Result<(), ElfError> {
+    let file_end = segment.p_offset.checked_add(segment.p_filesz);
+    let mem_end = segment.p_vaddr.checked_add(segment.p_memsz);
+    let aligned = match segment.p_align {
+        0 | 1 => true,
+        align => align.is_power_of_two() && segment.p_vaddr % align == segment.p_offset % align,
+    };
+    if file_end.is_none_or(|end| end > file_len as u64)
+        || mem_end.is_none()
+        || segment.p_filesz > segment.p_memsz
+        || !aligned
+    {
+        return Err(ElfError::BadSegment);
+    }
+    Ok(())
+}
@@ -1,15 +1,17 @@
 use crate::utils::x86_64_control::segmentation::{
-    Dtr, PrivilegeLevel, SegmentSelector, lidt, segment_register,
+    Dtr, IST_ENTRIES, PrivilegeLevel, SegmentSelector, lidt, segment_register,
 };
 
 pub type HandlerFunc = extern "C" fn() -> !;
 
+pub const IDT_ENTRIES: usize = 256;
+
 #[derive(Debug)]
-pub struct Idt([Entry; 16]);
+pub struct Idt([Entry; IDT_ENTRIES]);
 
 impl Idt {
     pub fn new() -> Self {
-        Idt([Entry::missing(); 16])
+        Idt([Entry::missing(); IDT_ENTRIES])
     }
 
     pub fn set_handler(&mut self, entry: u8, handler: HandlerFunc) -> &mut EntryOptions {
@@ -17,10 +19,14 @@ impl Idt {
         &mut self.0[entry as usize].options
     }
 
-    pub fn set_stack_index(&mut self, entry: u8, index: u16) {
-        self.0[entry as usize]
-            .options
-            .set_ist_bits(index as u8 & 0b111);
+    // index是TSS中interrupt_stack_table的下标
+    pub fn set_stack_index(&mut self, entry: u8, index: usize) {
+        self.0[entry as usize].options.set_stack_index(index);
+    }
+
+    // 没有使用IST时返回None
+    pub fn stack_index(&self, entry: u8) -> Option<usize> {
+        self.0[entry as usize].options.stack_index()
     }
 
     pub fn list(&self, index: usize) -> &Entry {
@@ -126,12 +132,25 @@ impl EntryOptions {
         self
     }
 
-    pub fn set_stack_index(&mut self, index: u16) -> &mut Self {
-        self.0 &= !0b111;
-        self.0 |= index & 0b111;
+    // IST编号从1开始，0表示不切换栈，所以写入index + 1
+    pub fn set_stack_index(&mut self, index: usize) -> &mut Self {
+        assert!(
+            index < IST_ENTRIES,
+            "IST index {} out of range 0..{}",
+            index,
+            IST_ENTRIES
+        );
+        self.set_ist_bits(index as u8 + 1);
         self
     }
 
+    pub fn stack_index(&self) -> Option<usize> {
+        match self.0 & 0b111 {
+            0 => None,
+            ist => Some(ist as usize - 1),
+        }
+    }
+
     fn new() -> Self {
         let mut options = Self::minimal();
         options.set_present(true).disable_interrupts(true);
@@ -1,18 +1,32 @@
-use core::arch::naked_asm;
+use core::{
+    arch::naked_asm,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use spin::{Lazy, Once};
+use spin::Once;
 
 use crate::{
-    memory::MemoryController,
-    println,
+    io_port::Port,
+    memory::{
+        MEMORY_CONTROLLER, MemoryController, PAGE_SIZE, PageFaultError, paging::VirtualAddress,
+        stack_allocator,
+    },
+    println, serial, syscall,
+    task::{
+        self, executor,
+        process::{self, KILLED_STATUS},
+    },
     utils::x86_64_control::{
         self,
         gdt::{Descriptor, Gdt},
-        segmentation::{SegmentSelector, TaskStateSegment, load_tss, set_cs},
+        segmentation::{PrivilegeLevel, SegmentSelector, TaskStateSegment, load_tss, set_cs},
     },
 };
 
 mod idt;
+pub mod pic;
+pub mod pit;
 
 macro_rules! handler {
     ($name: ident) => {{
@@ -97,46 +111,304 @@ macro_rules! handler_with_error_code {
     }}
 }
 
-static IDT: Lazy<idt::Idt> = Lazy::new(|| {
-    let mut idt = idt::Idt::new();
-    idt.set_handler(0, handler!(divide_by_zero_handler));
-    idt.set_handler(3, handler!(breakpoint_handler)); // new
-    idt.set_handler(6, handler!(invalid_opcode_handler));
-    idt.set_handler(8, handler_with_error_code!(double_fault_handler));
-    idt.set_stack_index(8, DOUBLE_FAULT_IST_INDEX as u16);
-    // idt.set_handler(14, handler_with_error_code!(page_fault_handler));
-    idt
-});
+// 保存所有通用寄存器，处理程序可以读取和修改被中断时的完整上下文。
+// 处理程序中可能切换到其他任务，返回时才恢复这些寄存器
+macro_rules! handler_with_context {
+    ($name: ident) => {{
+        #[unsafe(naked)]
+        extern "C" fn wrapper()->!{
+            naked_asm!(
+                    "push rax
+                    push rbx
+                    push rcx
+                    push rdx
+                    push rsi
+                    push rdi
+                    push rbp
+                    push r8
+                    push r9
+                    push r10
+                    push r11
+                    push r12
+                    push r13
+                    push r14
+                    push r15",
+
+                    // 15个寄存器加上5项的栈帧，调用时栈正好按16字节对齐
+                    "mov rdi, rsp",
+                    "call {handler}",
+
+                    "pop r15
+                    pop r14
+                    pop r13
+                    pop r12
+                    pop r11
+                    pop r10
+                    pop r9
+                    pop r8
+                    pop rbp
+                    pop rdi
+                    pop rsi
+                    pop rdx
+                    pop rcx
+                    pop rbx
+                    pop rax",
 
-static TSS: Once<TaskStateSegment> = Once::new();
+                    "iretq",
+                    handler = sym $name
+            );
+        }
+        wrapper
+    }}
+}
+
+// 缺页总是先进入专用栈，page_fault_stack返回0时留在专用栈上处理。
+// 否则把保存的寄存器和栈帧复制到返回的栈顶之下，在那个栈上调用处理程序，
+// 这样处理程序可以嵌套缺页，也可以打开中断等待或者结束当前任务
+#[unsafe(naked)]
+extern "C" fn page_fault_wrapper() -> ! {
+    naked_asm!(
+        "push rax
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push r8
+        push r9
+        push r10
+        push r11",
+
+        "mov rdi, rsp",
+        "add rdi, 10*8",
+        "sub rsp, 8",
+        "call {page_fault_stack}",
+        "add rsp, 8",
+
+        // 9个寄存器、错误码和5项的栈帧，共15项
+        "test rax, rax",
+        "jz 2f",
+        "and rax, -16",
+        "cld",
+        "mov rsi, rsp",
+        "lea rdi, [rax - 15*8]",
+        "mov rcx, 15",
+        "rep movsq",
+        "lea rsp, [rax - 15*8]",
+
+        "2:",
+        "mov rsi, [rsp + 9*8]",
+        "mov rdi, rsp",
+        "add rdi, 10*8",
+        "sub rsp, 8",
+        "call {handler}",
+        "add rsp, 8",
+
+        "pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rax",
+
+        "add rsp, 8",
+        "iretq",
+        page_fault_stack = sym page_fault_stack,
+        handler = sym page_fault_handler,
+    );
+}
+
+// 切换任务时要修改TSS中的内核栈，GDT中的TSS描述符又要求固定的地址
+struct TssCell(UnsafeCell<TaskStateSegment>);
+
+// 只有一个CPU，修改时关中断
+unsafe impl Sync for TssCell {}
+
+impl TssCell {
+    fn get(&self) -> &TaskStateSegment {
+        unsafe { &*self.0.get() }
+    }
+}
+
+// GDT中的段，顺序是SYSCALL/SYSRET要求的：内核数据段紧跟内核代码段，用户代码段紧跟用户数据段
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+static IDT: Once<idt::Idt> = Once::new();
+static TSS: Once<TssCell> = Once::new();
 static GDT: Once<Gdt> = Once::new();
+static SELECTORS: Once<Selectors> = Once::new();
 
-const DOUBLE_FAULT_IST_INDEX: usize = 1;
-pub fn init(memory_controller: &mut MemoryController) {
-    let double_fault_stack = memory_controller
-        .alloc_stack(1)
-        .expect("could not allocate double fault stack");
+// TSS中RSP0的副本。SYSCALL不会切换栈，入口处从这里加载内核栈
+pub(crate) static KERNEL_STACK_TOP: AtomicUsize = AtomicUsize::new(0);
 
+pub const NMI_VECTOR: u8 = 2;
+pub const DOUBLE_FAULT_VECTOR: u8 = 8;
+pub const GENERAL_PROTECTION_VECTOR: u8 = 13;
+pub const PAGE_FAULT_VECTOR: u8 = 14;
+pub const MACHINE_CHECK_VECTOR: u8 = 18;
+pub const TIMER_VECTOR: u8 = pic::PIC_1_OFFSET + pic::TIMER_IRQ;
+pub const KEYBOARD_VECTOR: u8 = pic::PIC_1_OFFSET + pic::KEYBOARD_IRQ;
+pub const COM1_VECTOR: u8 = pic::PIC_1_OFFSET + pic::COM1_IRQ;
+// 不支持SYSCALL时使用的系统调用门，Ring3可以直接用int触发
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+const KEYBOARD_DATA_PORT: u16 = 0x60;
+
+// 使用专用栈的异常，发生时当前的栈可能已经不能用了
+struct InterruptStack {
+    vector: u8,
+    ist_index: usize,
+    size_in_pages: usize,
+    owner: &'static str,
+}
+
+const INTERRUPT_STACKS: [InterruptStack; 4] = [
+    InterruptStack {
+        vector: NMI_VECTOR,
+        ist_index: 0,
+        size_in_pages: 1,
+        owner: "NMI handler",
+    },
+    InterruptStack {
+        vector: DOUBLE_FAULT_VECTOR,
+        ist_index: 1,
+        size_in_pages: 1,
+        owner: "double fault handler",
+    },
+    InterruptStack {
+        vector: MACHINE_CHECK_VECTOR,
+        ist_index: 2,
+        size_in_pages: 1,
+        owner: "machine check handler",
+    },
+    // 内核栈溢出时CPU还能压入栈帧，由缺页处理程序报告。
+    // 其他缺页在进入时切换回被中断的栈处理，见page_fault_wrapper
+    InterruptStack {
+        vector: PAGE_FAULT_VECTOR,
+        ist_index: 3,
+        size_in_pages: 4,
+        owner: "page fault handler",
+    },
+];
+
+// 从用户态进入内核时使用的栈
+const PRIVILEGE_STACK_PAGES: usize = 4;
+
+// 中断和异常使用的栈的数量，包括从用户态进入内核时使用的栈
+pub const INTERRUPT_STACK_COUNT: usize = INTERRUPT_STACKS.len() + 1;
+
+pub fn init(memory_controller: &mut MemoryController) {
     let tss = TSS.call_once(|| {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = double_fault_stack.top();
-        tss
+        for stack in &INTERRUPT_STACKS {
+            let top = memory_controller
+                .alloc_stack(stack.size_in_pages, stack.owner)
+                .expect("could not allocate interrupt stack")
+                .top();
+            tss.set_interrupt_stack(stack.ist_index, top);
+        }
+        let privilege_stack = memory_controller
+            .alloc_stack(PRIVILEGE_STACK_PAGES, "privilege level change")
+            .expect("could not allocate privilege stack");
+        tss.set_privilege_stack(PrivilegeLevel::Ring0, privilege_stack.top());
+        KERNEL_STACK_TOP.store(privilege_stack.top(), Ordering::SeqCst);
+        TssCell(UnsafeCell::new(tss))
     });
-    let mut code_selector = SegmentSelector::zero();
-    let mut tss_selector = SegmentSelector::zero();
     let gdt = GDT.call_once(|| {
         let mut gdt = Gdt::new();
-        code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        tss_selector = gdt.add_entry(Descriptor::tss_segment(&tss));
+        SELECTORS.call_once(|| Selectors {
+            kernel_code: gdt.add_entry(Descriptor::kernel_code_segment()),
+            kernel_data: gdt.add_entry(Descriptor::kernel_data_segment()),
+            user_data: gdt.add_entry(Descriptor::user_data_segment()),
+            user_code: gdt.add_entry(Descriptor::user_code_segment()),
+            tss: gdt.add_entry(Descriptor::tss_segment(tss.get())),
+        });
         gdt
     });
     gdt.load();
+    let selectors = selectors();
     unsafe {
-        set_cs(code_selector);
-        load_tss(tss_selector);
+        set_cs(selectors.kernel_code);
+        load_tss(selectors.tss);
     }
 
-    IDT.load();
+    let idt = IDT.call_once(|| {
+        let mut idt = idt::Idt::new();
+        idt.set_handler(0, handler!(divide_by_zero_handler));
+        idt.set_handler(NMI_VECTOR, handler!(nmi_handler));
+        idt.set_handler(3, handler!(breakpoint_handler)); // new
+        idt.set_handler(6, handler!(invalid_opcode_handler));
+        idt.set_handler(
+            GENERAL_PROTECTION_VECTOR,
+            handler_with_error_code!(general_protection_handler),
+        );
+        idt.set_handler(
+            DOUBLE_FAULT_VECTOR,
+            handler_with_error_code!(double_fault_handler),
+        );
+        idt.set_handler(PAGE_FAULT_VECTOR, page_fault_wrapper);
+        idt.set_handler(MACHINE_CHECK_VECTOR, handler!(machine_check_handler));
+        idt.set_handler(TIMER_VECTOR, handler_with_context!(timer_handler));
+        idt.set_handler(KEYBOARD_VECTOR, handler!(keyboard_handler));
+        idt.set_handler(COM1_VECTOR, handler!(com1_handler));
+        idt.set_handler(SYSCALL_VECTOR, handler_with_context!(syscall_handler))
+            .set_privilege_level(PrivilegeLevel::Ring3 as u16);
+        for stack in &INTERRUPT_STACKS {
+            idt.set_stack_index(stack.vector, stack.ist_index);
+        }
+        idt
+    });
+    idt.load();
+
+    pic::init();
+    pit::init();
+    pic::unmask(pic::TIMER_IRQ);
+    pic::unmask(pic::KEYBOARD_IRQ);
+    serial::enable_receive_interrupt();
+    pic::unmask(pic::COM1_IRQ);
+}
+
+// 在init和task::init之后调用
+pub fn enable() {
+    x86_64_control::interrupts::enable();
+}
+
+// vector使用的IST栈的栈顶，没有专用栈时返回None
+pub fn interrupt_stack_top(vector: u8) -> Option<VirtualAddress> {
+    let index = IDT.get()?.stack_index(vector)?;
+    Some(TSS.get()?.get().interrupt_stack(index))
+}
+
+pub fn selectors() -> &'static Selectors {
+    SELECTORS
+        .get()
+        .expect("interrupts::init has not been called")
+}
+
+// 从用户态进入内核时使用的栈，也就是TSS中的privilege_stack_table[0]
+pub fn kernel_stack() -> VirtualAddress {
+    TSS.get()
+        .unwrap()
+        .get()
+        .privilege_stack(PrivilegeLevel::Ring0)
+}
+
+// 切换到另一个任务时换成它的内核栈
+pub fn set_kernel_stack(stack_top: VirtualAddress) {
+    let tss = TSS.get().unwrap();
+    x86_64_control::interrupts::without_interrupts(|| unsafe {
+        (*tss.0.get()).set_privilege_stack(PrivilegeLevel::Ring0, stack_top);
+        KERNEL_STACK_TOP.store(stack_top, Ordering::SeqCst);
+    });
 }
 
 #[derive(Debug)]
@@ -149,6 +421,77 @@ struct ExceptionStackFrame {
     stack_segment: u64,
 }
 
+impl ExceptionStackFrame {
+    // 异常发生在用户态
+    fn is_user_mode(&self) -> bool {
+        self.code_segment & 0b11 == PrivilegeLevel::Ring3 as u64
+    }
+}
+
+// 用户态的错误只结束当前线程，进程以KILLED_STATUS退出
+fn kill_user_task(reason: &str, stack_frame: &ExceptionStackFrame) -> ! {
+    println!(
+        "\nuser task {} killed: {} at {:#x}",
+        task::current_name(),
+        reason,
+        stack_frame.instruction_pointer
+    );
+    // 从用户态进入，内核中没有持有锁，可以打开中断等待进程表的锁
+    x86_64_control::interrupts::enable();
+    process::exit(KILLED_STATUS)
+}
+
+// handler_with_context!保存的寄存器，顺序和压栈顺序相反
+#[derive(Debug, Default, Clone)]
+#[repr(C)]
+pub struct InterruptContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+extern "C" fn keyboard_handler(_stack_frame: *const ExceptionStackFrame) {
+    let scancode = Port::<u8>::new(KEYBOARD_DATA_PORT).read();
+    executor::keyboard::add_scancode(scancode);
+    pic::notify_end_of_interrupt(pic::KEYBOARD_IRQ);
+}
+
+extern "C" fn com1_handler(_stack_frame: *const ExceptionStackFrame) {
+    // FIFO中可能有多个字节
+    while let Some(byte) = serial::receive_byte() {
+        executor::serial::add_byte(byte);
+    }
+    pic::notify_end_of_interrupt(pic::COM1_IRQ);
+}
+
+extern "C" fn timer_handler(_context: *mut InterruptContext) {
+    pit::tick();
+    // 切换到其他任务之前发送EOI，否则之后的任务收不到时钟中断
+    pic::notify_end_of_interrupt(pic::TIMER_IRQ);
+    task::timer_tick();
+}
+
+extern "C" fn syscall_handler(context: *mut InterruptContext) {
+    syscall::dispatch(unsafe { &mut *context });
+}
+
 extern "C" fn divide_by_zero_handler(stack_frame: *const ExceptionStackFrame) {
     println!("\nEXCEPTION: DIVIDE BY ZERO\n{:#?}", unsafe {
         &*stack_frame
@@ -178,14 +521,140 @@ bitflags! {
     }
 }
 
+const INTERRUPT_FLAG: u64 = 1 << 9;
+
+// 等待其他任务释放MemoryController的最长时间
+const MEMORY_CONTROLLER_WAIT_TICKS: u64 = 2 * task::QUANTUM_TICKS;
+
+// 持有锁的任务可能被抢占了。出错的代码本来就可以被抢占，并且不在IST栈上时，
+// 打开中断等待持有锁的任务运行
+fn lock_memory_controller(
+    preemptible: bool,
+) -> Option<spin::MutexGuard<'static, MemoryController<'static>>> {
+    let controller = MEMORY_CONTROLLER.get()?;
+    if let Some(guard) = controller.try_lock() {
+        return Some(guard);
+    }
+    if !preemptible {
+        return None;
+    }
+    let deadline = pit::ticks() + MEMORY_CONTROLLER_WAIT_TICKS;
+    while pit::ticks() < deadline {
+        x86_64_control::interrupts::enable_and_hlt();
+        x86_64_control::interrupts::disable();
+        if let Some(guard) = controller.try_lock() {
+            return Some(guard);
+        }
+    }
+    None
+}
+
+extern "C" fn general_protection_handler(stack_frame: *const ExceptionStackFrame, error_code: u64) {
+    let stack_frame = unsafe { &*stack_frame };
+    if stack_frame.is_user_mode() {
+        kill_user_task("general protection fault", stack_frame);
+    }
+    println!(
+        "\nEXCEPTION: GENERAL PROTECTION FAULT with error code {:#x}\n{:#?}",
+        error_code, stack_frame
+    );
+    x86_64_control::interrupts::hlt_loop();
+}
+
+// 在缺页的专用栈上调用，返回处理缺页时使用的栈顶，0表示留在专用栈上
+extern "C" fn page_fault_stack(stack_frame: *const ExceptionStackFrame) -> usize {
+    let stack_frame = unsafe { &*stack_frame };
+    // 从用户态进入时当前任务的内核栈是空的
+    if stack_frame.is_user_mode() {
+        return kernel_stack();
+    }
+    // 被中断的栈本身缺页，比如溢出到保护页或者按需映射的栈还没有映射，不能再往上面压栈
+    let address = x86_64_control::cr2::read_cr2() as usize;
+    let stack_pointer = stack_frame.stack_pointer as usize;
+    if address.abs_diff(stack_pointer) < PAGE_SIZE
+        || stack_allocator::guard_page_owner(address).is_some()
+    {
+        return 0;
+    }
+    stack_pointer
+}
+
 extern "C" fn page_fault_handler(stack_frame: *const ExceptionStackFrame, error_code: u64) {
+    let address = x86_64_control::cr2::read_cr2() as usize;
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+
+    if let Some(owner) = stack_allocator::guard_page_owner(address) {
+        println!(
+            "\nEXCEPTION: PAGE FAULT while accessing {:#x}\
+            \nstack overflow in {}\n{:#?}",
+            address,
+            owner,
+            unsafe { &*stack_frame }
+        );
+        x86_64_control::interrupts::hlt_loop();
+    }
+
+    // 页不存在时尝试按需分配，写保护错误交给写时复制处理，其他保护错误直接报告
+    let protection_violation = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+    let caused_by_write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+    let result = if protection_violation && !caused_by_write {
+        None
+    } else {
+        // 留在专用栈上时不能等待，其他任务缺页会覆盖这个栈
+        let on_interrupt_stack = interrupt_stack_top(PAGE_FAULT_VECTOR)
+            == Some(stack_frame as usize + size_of::<ExceptionStackFrame>());
+        let preemptible =
+            unsafe { (*stack_frame).cpu_flags } & INTERRUPT_FLAG != 0 && !on_interrupt_stack;
+        lock_memory_controller(preemptible).map(|mut controller| {
+            if protection_violation {
+                controller.handle_copy_on_write(address)
+            } else {
+                controller.handle_page_fault(
+                    address,
+                    caused_by_write,
+                    error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH),
+                )
+            }
+        })
+    };
+
+    let reason = match result {
+        Some(Ok(())) => return,
+        Some(Err(PageFaultError::NoRegion)) => "address is not in any memory region",
+        Some(Err(PageFaultError::AccessViolation(_))) => "access not permitted by region",
+        Some(Err(PageFaultError::OutOfMemory)) => "out of physical memory",
+        Some(Err(PageFaultError::WriteProtected)) => "write to a read-only page",
+        None if protection_violation => "protection violation",
+        None => "memory controller unavailable",
+    };
+
+    if unsafe { &*stack_frame }.is_user_mode() {
+        println!("page fault while accessing {:#x}: {}", address, reason);
+        kill_user_task("page fault", unsafe { &*stack_frame });
+    }
+
     println!(
         "\nEXCEPTION: PAGE FAULT while accessing {:#x}\
-        \nerror code: {:?}\n{:#?}",
-        x86_64_control::cr2::read_cr2(),
-        PageFaultErrorCode::from_bits(error_code).unwrap(),
+        \nreason: {}\nerror code: {:?}\n{:#?}",
+        address,
+        reason,
+        error_code,
         unsafe { &*stack_frame }
     );
+    x86_64_control::interrupts::hlt_loop();
+}
+
+extern "C" fn nmi_handler(stack_frame: *const ExceptionStackFrame) {
+    println!("\nEXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}", unsafe {
+        &*stack_frame
+    });
+}
+
+extern "C" fn machine_check_handler(stack_frame: *const ExceptionStackFrame) {
+    println!("\nEXCEPTION: MACHINE CHECK\n{:#?}", unsafe {
+        &*stack_frame
+    });
+    x86_64_control::interrupts::hlt_loop();
 }
 
 extern "C" fn breakpoint_handler(stack_frame: *const ExceptionStackFrame) {
@@ -199,6 +668,11 @@ extern "C" fn breakpoint_handler(stack_frame: *const ExceptionStackFrame) {
 extern "C" fn double_fault_handler(stack_frame: *const ExceptionStackFrame, _error_code: u64) {
     let stack_frame = unsafe { &*stack_frame };
     println!("\nEXCEPTION: DOUBLE FAULT");
+    // 缺页处理程序的专用栈溢出时会变成双重错误，CR2仍然是保护页的地址
+    let address = x86_64_control::cr2::read_cr2() as usize;
+    if let Some(owner) = stack_allocator::guard_page_owner(address) {
+        println!("stack overflow in {} while accessing {:#x}", owner, address);
+    }
     println!("ExceptionStackFrame {{");
     println!(
         "    instruction_pointer: {},",
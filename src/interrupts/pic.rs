@@ -0,0 +1,109 @@
+use spin::Mutex;
+
+use crate::io_port::Port;
+
+// 两片8259 PIC的IRQ重新映射到CPU异常之后
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const COM1_IRQ: u8 = 4;
+
+const CMD_INIT: u8 = 0x11;
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+const MODE_8086: u8 = 0x01;
+
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    fn handles_interrupt(&self, vector: u8) -> bool {
+        (self.offset..self.offset + 8).contains(&vector)
+    }
+
+    fn end_of_interrupt(&mut self) {
+        self.command.write(CMD_END_OF_INTERRUPT);
+    }
+}
+
+pub struct ChainedPics {
+    pics: [Pic; 2],
+}
+
+impl ChainedPics {
+    const fn new(offset_1: u8, offset_2: u8) -> ChainedPics {
+        ChainedPics {
+            pics: [
+                Pic {
+                    offset: offset_1,
+                    command: Port::new(0x20),
+                    data: Port::new(0x21),
+                },
+                Pic {
+                    offset: offset_2,
+                    command: Port::new(0xa0),
+                    data: Port::new(0xa1),
+                },
+            ],
+        }
+    }
+
+    // 重新映射IRQ并屏蔽所有IRQ，需要的IRQ由unmask单独打开
+    fn init(&mut self) {
+        // 对一个没有用的端口写入，给旧的PIC留出处理时间
+        let mut wait_port = Port::<u8>::new(0x80);
+        let mut wait = || wait_port.write(0);
+
+        for pic in self.pics.iter_mut() {
+            pic.command.write(CMD_INIT);
+            wait();
+        }
+        for pic in self.pics.iter_mut() {
+            pic.data.write(pic.offset);
+            wait();
+        }
+        // 从片连接在主片的IRQ2上
+        self.pics[0].data.write(4);
+        wait();
+        self.pics[1].data.write(2);
+        wait();
+        for pic in self.pics.iter_mut() {
+            pic.data.write(MODE_8086);
+            wait();
+        }
+
+        self.pics[0].data.write(!(1 << 2));
+        self.pics[1].data.write(0xff);
+    }
+
+    fn unmask(&mut self, irq: u8) {
+        let pic = &mut self.pics[irq as usize / 8];
+        let mask = pic.data.read() & !(1 << (irq % 8));
+        pic.data.write(mask);
+    }
+
+    fn notify_end_of_interrupt(&mut self, vector: u8) {
+        if self.pics[1].handles_interrupt(vector) {
+            self.pics[1].end_of_interrupt();
+        }
+        self.pics[0].end_of_interrupt();
+    }
+}
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+
+pub fn init() {
+    PICS.lock().init();
+}
+
+pub fn unmask(irq: u8) {
+    PICS.lock().unmask(irq);
+}
+
+pub fn notify_end_of_interrupt(irq: u8) {
+    PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq);
+}
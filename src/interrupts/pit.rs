@@ -0,0 +1,34 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::io_port::Port;
+
+// 8253/8254 PIT的输入时钟频率
+const PIT_FREQUENCY: u32 = 1_193_182;
+// 通道0，先低后高字节，方式2（频率发生器）
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+pub const TIMER_HZ: u64 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TIMER_HZ as u32) as u16;
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel_0 = Port::<u8>::new(0x40);
+    command.write(CHANNEL_0_RATE_GENERATOR);
+    channel_0.write(divisor as u8);
+    channel_0.write((divisor >> 8) as u8);
+}
+
+// 启动以来的时钟中断次数
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub fn ticks_to_millis(ticks: u64) -> u64 {
+    ticks * 1000 / TIMER_HZ
+}
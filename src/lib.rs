@@ -1,10 +1,13 @@
 #![no_std]
 #![allow(dead_code)]
+mod elf;
 mod interrupts;
 mod io_port;
 mod memory;
 mod multiboot_info;
 mod serial;
+mod syscall;
+mod task;
 mod utils;
 mod vga_buffer;
 extern crate alloc;
@@ -18,7 +21,10 @@ use core::panic::PanicInfo;
 use utils::test_frameworks::*;
 
 #[cfg(feature = "use_test")]
-use crate::test::{test_allocator::*, test_exceptions::*};
+use crate::test::{
+    test_allocator::*, test_exceptions::*, test_executor::*, test_loader::*, test_paging::*,
+    test_process::*, test_sync::*, test_syscall::*, test_task::*, test_user::*,
+};
 
 #[unsafe(naked)]
 extern "C" fn naked_function_example() {
@@ -32,11 +38,28 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) -> ! {
     // test_paging(multiboot_information_address);
     // test_remap_the_kernel(multiboot_information_address);
 
-    let boot_info = crate::multiboot_info::MultibootInfo::new(multiboot_information_address);
+    let boot_info = multiboot_info::init(multiboot_information_address);
 
-    let mut memory_controller = memory::init(&boot_info);
+    let memory_controller = memory::init(boot_info);
 
-    interrupts::init(&mut memory_controller);
+    interrupts::init(&mut memory_controller.lock());
+    syscall::init();
+    task::init(boot_info);
+    interrupts::enable();
+
+    // 内核命令行中的init=<module>指定启动后运行的用户程序
+    if let Some(init) = boot_info
+        .get_command_line()
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("init="))
+        && let Err(error) = task::loader::spawn_module(init)
+    {
+        println!("could not start {}: {:?}", init, error);
+    }
+
+    if boot_info.has_boot_option(memory::paging::dump::DUMP_OPTION) {
+        memory::paging::dump::dump_active();
+    }
 
     // naked_function_example();
 
@@ -61,6 +84,7 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) -> ! {
 #[cfg(feature = "use_test")]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    memory::paging::dump::dump_on_panic();
     test_panic_handler(info)
 }
 
@@ -68,6 +92,7 @@ fn panic(info: &PanicInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    memory::paging::dump::dump_on_panic();
     loop {}
 }
 
@@ -88,5 +113,144 @@ fn panic(info: &PanicInfo) -> ! {
 #[cfg(feature = "use_test")]
 test_case!(breakpoint);
 
+#[cfg(feature = "use_test")]
+test_case!(interrupt_stacks);
+
+#[cfg(feature = "use_test")]
+test_case!(direct_map);
+
+#[cfg(feature = "use_test")]
+test_case!(demand_paging);
+
+#[cfg(feature = "use_test")]
+test_case!(address_space_split_merge);
+
+#[cfg(feature = "use_test")]
+test_case!(stack_reuse);
+
+#[cfg(feature = "use_test")]
+test_case!(stack_guards_full);
+
+#[cfg(feature = "use_test")]
+test_case!(page_table_lifecycle);
+
+#[cfg(feature = "use_test")]
+test_case!(page_table_dump);
+
+#[cfg(feature = "use_test")]
+test_case!(pcid_allocation);
+
+#[cfg(feature = "use_test")]
+test_case!(heap_growth);
+
+#[cfg(feature = "use_test")]
+test_case!(heap_growth_small_blocks);
+
+#[cfg(feature = "use_test")]
+test_case!(slab_cache);
+
+#[cfg(feature = "use_test")]
+test_case!(object_cache);
+
+#[cfg(feature = "use_test")]
+test_case!(allocator_stats);
+
+#[cfg(feature = "use_test")]
+test_case!(bump_backend);
+
+#[cfg(feature = "use_test")]
+test_case!(linked_list_backend);
+
+#[cfg(feature = "use_test")]
+test_case!(fixed_size_block_backend);
+
+#[cfg(feature = "use_test")]
+test_case!(slab_backend);
+
+#[cfg(feature = "use_test")]
+test_case!(linked_list_coalescing);
+
+#[cfg(feature = "use_test")]
+test_case!(linked_list_best_fit);
+
+#[cfg(feature = "use_test")]
+test_case!(linked_list_realloc_in_place);
+
+#[cfg(feature = "use_test")]
+test_case!(heap_corruption_detection);
+
+#[cfg(feature = "use_test")]
+test_case!(allocation_tracking);
+
+#[cfg(feature = "use_test")]
+test_case!(kernel_threads);
+
+#[cfg(feature = "use_test")]
+test_case!(thread_exit);
+
+#[cfg(feature = "use_test")]
+test_case!(preemption);
+
+#[cfg(feature = "use_test")]
+test_case!(block_and_wake);
+
+#[cfg(feature = "use_test")]
+test_case!(fixed_priority_policy);
+
+#[cfg(feature = "use_test")]
+test_case!(fair_policy_starvation_freedom);
+
+#[cfg(feature = "use_test")]
+test_case!(scheduling_policy_names);
+
+#[cfg(feature = "use_test")]
+test_case!(wait_queue_timeout);
+
+#[cfg(feature = "use_test")]
+test_case!(sleeping_mutex);
+
+#[cfg(feature = "use_test")]
+test_case!(semaphore_handoff);
+
+#[cfg(feature = "use_test")]
+test_case!(condvar_notify);
+
+#[cfg(feature = "use_test")]
+test_case!(rwlock_readers_and_writer);
+
+#[cfg(feature = "use_test")]
+test_case!(executor_runs_futures);
+
+#[cfg(feature = "use_test")]
+test_case!(interrupt_streams);
+
+#[cfg(feature = "use_test")]
+test_case!(user_mode);
+
+#[cfg(feature = "use_test")]
+test_case!(user_syscalls);
+
+#[cfg(feature = "use_test")]
+test_case!(syscall_dispatch);
+
+#[cfg(feature = "use_test")]
+test_case!(elf_validation);
+
+#[cfg(feature = "use_test")]
+test_case!(elf_loading);
+
+#[cfg(feature = "use_test")]
+test_case!(elf_program_runs);
+
+#[cfg(feature = "use_test")]
+test_case!(process_table);
+
+#[cfg(feature = "use_test")]
+test_case!(process_fork_and_wait);
+
+#[cfg(feature = "use_test")]
+test_case!(process_copy_on_write);
+
+// 访问没有登记的地址，缺页处理程序报告后停机，不会返回，所以放在最后
 #[cfg(feature = "use_test")]
 test_case!(page_fault);
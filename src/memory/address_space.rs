@@ -0,0 +1,210 @@
+use crate::{
+    memory::{
+        PAGE_SIZE,
+        paging::{EntryFlags, VirtualAddress},
+        region::{Backing, Region, RegionError, RegionKind},
+    },
+    utils::align_up,
+};
+
+pub const MAX_REGIONS: usize = 128;
+
+// 记录一段虚拟地址空间中已使用的区域，按起始地址排序
+pub struct AddressSpace {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    regions: [Option<Region>; MAX_REGIONS],
+    len: usize,
+}
+
+impl AddressSpace {
+    pub const fn new(start: VirtualAddress, end: VirtualAddress) -> AddressSpace {
+        AddressSpace {
+            start,
+            end,
+            regions: [None; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtualAddress {
+        self.end
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter().map(|r| r.as_ref().unwrap())
+    }
+
+    pub fn find(&self, address: VirtualAddress) -> Option<&Region> {
+        self.iter().find(|r| r.contains(address))
+    }
+
+    pub fn find_free_gap(&self, size: usize, align: usize) -> Option<VirtualAddress> {
+        let align = align.max(PAGE_SIZE);
+        let mut candidate = align_up(self.start, align);
+        for region in self.iter() {
+            if candidate.checked_add(size)? <= region.start() {
+                return Some(candidate);
+            }
+            candidate = candidate.max(align_up(region.end(), align));
+        }
+
+        if candidate.checked_add(size)? <= self.end {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        size: usize,
+        flags: EntryFlags,
+        kind: RegionKind,
+        backing: Backing,
+    ) -> Result<VirtualAddress, RegionError> {
+        if size == 0 {
+            return Err(RegionError::Empty);
+        }
+        let size = align_up(size, PAGE_SIZE);
+        let start = self
+            .find_free_gap(size, PAGE_SIZE)
+            .ok_or(RegionError::NoSpace)?;
+        self.insert(Region::new(start, size, flags, kind, backing))?;
+        Ok(start)
+    }
+
+    pub fn insert(&mut self, region: Region) -> Result<(), RegionError> {
+        if region.start() < self.start || region.end() > self.end {
+            return Err(RegionError::OutOfRange);
+        }
+        if self.iter().any(|r| r.overlaps(&region)) {
+            return Err(RegionError::Overlap);
+        }
+
+        let index = self
+            .iter()
+            .take_while(|r| r.start() < region.start())
+            .count();
+        self.insert_at(index, region)?;
+        self.merge_around(index);
+        Ok(())
+    }
+
+    // 移除[start, start + size)范围，部分覆盖的区域会被拆分
+    pub fn remove(&mut self, start: VirtualAddress, size: usize) -> Result<(), RegionError> {
+        let end = start + size;
+        self.split(start)?;
+        self.split(end)?;
+
+        let first = self.iter().take_while(|r| r.end() <= start).count();
+        let count = self.regions[first..self.len]
+            .iter()
+            .take_while(|r| r.unwrap().start() < end)
+            .count();
+        if count == 0 {
+            return Err(RegionError::NotFound);
+        }
+        for _ in 0..count {
+            self.remove_at(first);
+        }
+        Ok(())
+    }
+
+    // 在address处拆分所在的区域，address不在任何区域内部时什么都不做
+    pub fn split(&mut self, address: VirtualAddress) -> Result<(), RegionError> {
+        let index = match self
+            .iter()
+            .position(|r| r.start() < address && address < r.end())
+        {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let (lower, upper) = self.regions[index].unwrap().split_at(address);
+        self.insert_at(index + 1, upper)?;
+        self.regions[index] = Some(lower);
+        Ok(())
+    }
+
+    // 修改一段范围的权限，必要时拆分区域，之后再合并相同属性的邻居
+    pub fn protect(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: EntryFlags,
+    ) -> Result<(), RegionError> {
+        let end = start + size;
+        self.split(start)?;
+        self.split(end)?;
+
+        let mut found = false;
+        for slot in self.regions[..self.len].iter_mut() {
+            let region = slot.as_mut().unwrap();
+            if start <= region.start() && region.end() <= end {
+                region.set_flags(flags);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(RegionError::NotFound);
+        }
+
+        let mut index = 0;
+        while index < self.len {
+            self.merge_around(index);
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn insert_at(&mut self, index: usize, region: Region) -> Result<(), RegionError> {
+        if self.len == MAX_REGIONS {
+            return Err(RegionError::TooManyRegions);
+        }
+        self.regions[index..=self.len].rotate_right(1);
+        self.regions[index] = Some(region);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove_at(&mut self, index: usize) -> Region {
+        let region = self.regions[index].take().unwrap();
+        self.regions[index..self.len].rotate_left(1);
+        self.len -= 1;
+        region
+    }
+
+    fn merge_around(&mut self, mut index: usize) {
+        // 先和前一个合并，再和后一个合并
+        if index > 0 && self.try_merge(index - 1) {
+            index -= 1;
+        }
+        self.try_merge(index);
+    }
+
+    fn try_merge(&mut self, index: usize) -> bool {
+        if index + 1 >= self.len {
+            return false;
+        }
+        let region = self.regions[index].unwrap();
+        let next = self.regions[index + 1].unwrap();
+        if !region.can_merge(&next) {
+            return false;
+        }
+
+        self.remove_at(index + 1);
+        self.regions[index] = Some(Region::new(
+            region.start(),
+            region.size() + next.size(),
+            region.flags(),
+            region.kind(),
+            region.backing(),
+        ));
+        true
+    }
+}
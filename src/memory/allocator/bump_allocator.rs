@@ -2,7 +2,14 @@ use core::ptr::null_mut;
 
 use alloc::alloc::{GlobalAlloc, Layout};
 
-use crate::{memory::allocator::Locked, utils::align_up};
+use crate::{
+    memory::allocator::{
+        Locked,
+        stats::{AllocatorStats, HeapStats},
+        tracking,
+    },
+    utils::align_up,
+};
 
 #[derive(Debug)]
 pub struct BumpAllocator {
@@ -10,6 +17,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    stats: AllocatorStats,
 }
 
 impl BumpAllocator {
@@ -19,6 +27,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            stats: AllocatorStats::new(),
         }
     }
 
@@ -27,6 +36,22 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    pub fn owns(&self, address: usize) -> bool {
+        self.heap_start <= address && address < self.heap_end
+    }
+}
+
+impl HeapStats for BumpAllocator {
+    fn stats(&self) -> AllocatorStats {
+        let free = self.heap_end - self.next;
+        AllocatorStats {
+            heap_size: self.heap_end - self.heap_start,
+            free_bytes: free,
+            largest_free_block: free,
+            ..self.stats
+        }
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -35,21 +60,29 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
         let alloc_start = align_up(bump.next, layout.align());
         let alloc_end = match alloc_start.checked_add(layout.size()) {
             Some(end) => end,
-            None => return null_mut(),
+            None => {
+                bump.stats.record_failure();
+                return null_mut();
+            }
         };
 
         if alloc_end > bump.heap_end {
+            bump.stats.record_failure();
             null_mut()
         } else {
             bump.next = alloc_end;
             bump.allocations += 1;
+            bump.stats.record_alloc(layout.size());
+            tracking::track_alloc(alloc_start as *mut u8, layout.size());
             alloc_start as *mut u8
         }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
         bump.allocations -= 1;
+        bump.stats.record_dealloc(layout.size());
+        tracking::track_dealloc(ptr);
 
         if bump.allocations == 0 {
             bump.next = bump.heap_start;
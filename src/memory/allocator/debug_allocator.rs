@@ -0,0 +1,312 @@
+// 调试用的分配器外层，检测堆损坏：
+// 每块内存前后有红区，释放时检查；释放后的内存填充毒化字节，并在隔离区中停留一段时间，
+// 离开隔离区时检查是否被写过；头部记录状态用来发现重复释放；
+// 较大的分配可以放在保护页之前，越界访问立即触发缺页
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{
+    memory::{
+        MEMORY_CONTROLLER, PAGE_SIZE,
+        allocator::{DispatchAllocator, tracking},
+    },
+    serial_println,
+    utils::{align_down, align_up},
+};
+
+const RED_ZONE_SIZE: usize = 16;
+const RED_ZONE_BYTE: u8 = 0xfd;
+const POISON_BYTE: u8 = 0xdd;
+
+const ALLOCATED_MAGIC: u64 = 0xa110_ca7e_d0d0_a110;
+const FREED_MAGIC: u64 = 0xf7ee_d0d0_f7ee_d0d0;
+
+const QUARANTINE_SIZE: usize = 64;
+const MAX_GUARDED: usize = 64;
+
+// 不小于这个大小的分配使用保护页
+pub const GUARD_PAGE_THRESHOLD: usize = PAGE_SIZE;
+
+// 紧挨在前部红区之前
+#[repr(C)]
+struct BlockHeader {
+    magic: u64,
+    size: usize,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<BlockHeader>();
+
+#[derive(Clone, Copy)]
+struct GuardedAllocation {
+    address: usize,
+    start: usize,
+    pages: usize,
+}
+
+struct Quarantine {
+    blocks: [Option<(usize, Layout)>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+pub struct DebugAllocator {
+    inner: &'static DispatchAllocator,
+    enabled: AtomicBool,
+    guard_pages: AtomicBool,
+    errors: AtomicUsize,
+    quarantine: Mutex<Quarantine>,
+    guarded: Mutex<[Option<GuardedAllocation>; MAX_GUARDED]>,
+}
+
+impl DebugAllocator {
+    pub const fn new(inner: &'static DispatchAllocator) -> Self {
+        DebugAllocator {
+            inner,
+            enabled: AtomicBool::new(false),
+            guard_pages: AtomicBool::new(false),
+            errors: AtomicUsize::new(0),
+            quarantine: Mutex::new(Quarantine {
+                blocks: [None; QUARANTINE_SIZE],
+                next: 0,
+            }),
+            guarded: Mutex::new([None; MAX_GUARDED]),
+        }
+    }
+
+    // 必须在第一次分配之前调用，之前分配的内存没有头部和红区
+    pub fn enable(&self, guard_pages: bool) {
+        self.enabled.store(true, Ordering::SeqCst);
+        self.guard_pages.store(guard_pages, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    // 发现的损坏次数
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::SeqCst)
+    }
+
+    // 检查并释放隔离区中的所有内存
+    pub fn flush_quarantine(&self) {
+        loop {
+            let block = {
+                let mut quarantine = self.quarantine.lock();
+                let block = quarantine.blocks.iter_mut().find_map(|b| b.take());
+                match block {
+                    Some(block) => block,
+                    None => return,
+                }
+            };
+            unsafe { self.release(block.0, block.1) };
+        }
+    }
+
+    fn report(&self, what: &str, address: usize, layout: Layout) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+        serial_println!(
+            "heap corruption: {} at {:#x}, layout {:?}, caller {:#x?}",
+            what,
+            address,
+            layout,
+            tracking::backtrace()
+        );
+    }
+
+    // 用户数据前面是头部和前部红区，后面是后部红区
+    fn front_size(layout: Layout) -> usize {
+        align_up(HEADER_SIZE + RED_ZONE_SIZE, layout.align())
+    }
+
+    fn outer_layout(layout: Layout) -> Layout {
+        let size = Self::front_size(layout) + layout.size() + RED_ZONE_SIZE;
+        Layout::from_size_align(size, layout.align().max(HEADER_SIZE)).unwrap()
+    }
+
+    fn header(address: usize) -> *mut BlockHeader {
+        (address - RED_ZONE_SIZE - HEADER_SIZE) as *mut BlockHeader
+    }
+
+    fn is_filled(address: usize, size: usize, byte: u8) -> bool {
+        let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, size) };
+        bytes.iter().all(|&b| b == byte)
+    }
+
+    unsafe fn alloc_checked(&self, layout: Layout) -> *mut u8 {
+        let base = unsafe { self.inner.alloc(Self::outer_layout(layout)) };
+        if base.is_null() {
+            return base;
+        }
+
+        let address = base as usize + Self::front_size(layout);
+        unsafe {
+            Self::header(address).write(BlockHeader {
+                magic: ALLOCATED_MAGIC,
+                size: layout.size(),
+            });
+            ptr::write_bytes(
+                (address - RED_ZONE_SIZE) as *mut u8,
+                RED_ZONE_BYTE,
+                RED_ZONE_SIZE,
+            );
+            ptr::write_bytes(
+                (address + layout.size()) as *mut u8,
+                RED_ZONE_BYTE,
+                RED_ZONE_SIZE,
+            );
+        }
+        address as *mut u8
+    }
+
+    unsafe fn dealloc_checked(&self, address: usize, layout: Layout) {
+        if !self.inner.owns(address) {
+            self.report("free of a pointer not from the heap", address, layout);
+            return;
+        }
+
+        let header = unsafe { &mut *Self::header(address) };
+        match header.magic {
+            ALLOCATED_MAGIC => {}
+            FREED_MAGIC => {
+                self.report("double free", address, layout);
+                return;
+            }
+            _ => {
+                self.report("free of a corrupted or invalid block", address, layout);
+                return;
+            }
+        }
+        if header.size != layout.size() {
+            self.report("free with a different layout", address, layout);
+        }
+        if !Self::is_filled(address - RED_ZONE_SIZE, RED_ZONE_SIZE, RED_ZONE_BYTE) {
+            self.report("buffer underflow", address, layout);
+        }
+        if !Self::is_filled(address + layout.size(), RED_ZONE_SIZE, RED_ZONE_BYTE) {
+            self.report("buffer overflow", address, layout);
+        }
+
+        header.magic = FREED_MAGIC;
+        unsafe { ptr::write_bytes(address as *mut u8, POISON_BYTE, layout.size()) };
+
+        // 隔离区中的内存不算泄漏
+        let base = address - Self::front_size(layout);
+        tracking::track_dealloc(base as *mut u8);
+
+        let evicted = {
+            let mut quarantine = self.quarantine.lock();
+            let index = quarantine.next;
+            quarantine.next = (index + 1) % QUARANTINE_SIZE;
+            quarantine.blocks[index].replace((address, layout))
+        };
+        if let Some((address, layout)) = evicted {
+            unsafe { self.release(address, layout) };
+        }
+    }
+
+    // 离开隔离区时检查毒化字节，然后真正释放
+    unsafe fn release(&self, address: usize, layout: Layout) {
+        if !Self::is_filled(address, layout.size(), POISON_BYTE) {
+            self.report("use after free", address, layout);
+        }
+        let base = address - Self::front_size(layout);
+        unsafe {
+            self.inner
+                .dealloc(base as *mut u8, Self::outer_layout(layout))
+        };
+    }
+
+    // 数据放在最后一页的末尾，越过结尾就是保护页
+    fn alloc_guarded(&self, layout: Layout) -> *mut u8 {
+        let pages = align_up(layout.size(), PAGE_SIZE) / PAGE_SIZE;
+        let mut guarded = self.guarded.lock();
+        let slot = match guarded.iter_mut().find(|g| g.is_none()) {
+            Some(slot) => slot,
+            None => return ptr::null_mut(),
+        };
+        let start = match MEMORY_CONTROLLER
+            .get()
+            .and_then(|controller| controller.try_lock())
+            .and_then(|mut controller| controller.alloc_guarded(pages))
+        {
+            Some(start) => start,
+            None => return ptr::null_mut(),
+        };
+
+        let address = align_down(start + pages * PAGE_SIZE - layout.size(), layout.align());
+        *slot = Some(GuardedAllocation {
+            address,
+            start,
+            pages,
+        });
+        tracking::track_alloc(address as *mut u8, layout.size());
+        address as *mut u8
+    }
+
+    fn dealloc_guarded(&self, address: usize) -> bool {
+        let allocation = {
+            let mut guarded = self.guarded.lock();
+            match guarded
+                .iter_mut()
+                .find(|g| g.is_some_and(|g| g.address == address))
+            {
+                Some(slot) => slot.take().unwrap(),
+                None => return false,
+            }
+        };
+
+        tracking::track_dealloc(address as *mut u8);
+        MEMORY_CONTROLLER
+            .get()
+            .unwrap()
+            .lock()
+            .free_guarded(allocation.start, allocation.pages);
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for DebugAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !self.is_enabled() {
+            return unsafe { self.inner.alloc(layout) };
+        }
+        if self.guard_pages.load(Ordering::SeqCst) && layout.size() >= GUARD_PAGE_THRESHOLD {
+            let ptr = self.alloc_guarded(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+        unsafe { self.alloc_checked(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.is_enabled() {
+            return unsafe { self.inner.dealloc(ptr, layout) };
+        }
+        if !self.dealloc_guarded(ptr as usize) {
+            unsafe { self.dealloc_checked(ptr as usize, layout) };
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.is_enabled() {
+            return unsafe { self.inner.realloc(ptr, layout, new_size) };
+        }
+
+        // 调试模式下总是移动到新的内存，这样旧指针的使用也能被发现
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
@@ -5,7 +5,17 @@ use core::{
 
 use alloc::alloc::{GlobalAlloc, Layout};
 
-use crate::memory::allocator::Locked;
+use crate::{
+    memory::{
+        MEMORY_CONTROLLER, PAGE_SIZE,
+        allocator::{
+            HEAP_GROWTH_SIZE, HEAP_MAX_SIZE, HEAP_RELEASE_FREE_TAIL, Locked,
+            stats::{AllocatorStats, HeapStats},
+            tracking,
+        },
+    },
+    utils::align_up,
+};
 
 #[derive(Debug)]
 struct ListNode {
@@ -14,9 +24,16 @@ struct ListNode {
 
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+// 堆最多扩展的次数，每次扩展得到一段独立的内存。
+// 每次至少扩展HEAP_GROWTH_SIZE，这样总能扩展到HEAP_MAX_SIZE
+const MAX_HEAP_EXTENSIONS: usize = HEAP_MAX_SIZE / HEAP_GROWTH_SIZE;
+
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    extensions: [linked_list_allocator::Heap; MAX_HEAP_EXTENSIONS],
+    extension_count: usize,
+    stats: AllocatorStats,
 }
 
 impl FixedSizeBlockAllocator {
@@ -25,22 +42,189 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            extensions: [const { linked_list_allocator::Heap::empty() }; MAX_HEAP_EXTENSIONS],
+            extension_count: 0,
+            stats: AllocatorStats::new(),
+        }
+    }
+
+    // 当前堆的总大小，包括所有扩展区
+    pub fn heap_size(&self) -> usize {
+        self.fallback_allocator.size()
+            + self.extensions[..self.extension_count]
+                .iter()
+                .map(|heap| heap.size())
+                .sum::<usize>()
+    }
+
+    pub fn extension_count(&self) -> usize {
+        self.extension_count
+    }
+
+    // address是否在最后一个扩展区中
+    fn in_tail(&self, address: usize) -> bool {
+        self.extension_count > 0 && {
+            let heap = &self.extensions[self.extension_count - 1];
+            heap.bottom() <= address && address < heap.top()
         }
     }
 
+    pub fn owns(&self, address: usize) -> bool {
+        core::iter::once(&self.fallback_allocator)
+            .chain(self.extensions[..self.extension_count].iter())
+            .any(|heap| heap.bottom() <= address && address < heap.top())
+    }
+
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         unsafe {
-            self.fallback_allocator
-                .init(heap_start, heap_size);
+            self.fallback_allocator.init(heap_start, heap_size);
         }
     }
 
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        for heap in self.extensions[..self.extension_count].iter_mut() {
+            if let Ok(ptr) = heap.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+        }
+
+        // 空闲空间不够，向MemoryController申请新的页
+        if !self.grow(layout) {
+            return null_mut();
+        }
+        match self.extensions[self.extension_count - 1].allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => null_mut(),
         }
     }
+
+    fn fallback_dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let address = ptr.as_ptr() as usize;
+        let heap = &self.fallback_allocator;
+        if heap.bottom() <= address && address < heap.top() {
+            unsafe { self.fallback_allocator.deallocate(ptr, layout) };
+            return;
+        }
+
+        let index = self.extensions[..self.extension_count]
+            .iter()
+            .position(|heap| heap.bottom() <= address && address < heap.top())
+            .expect("deallocating memory that does not belong to the heap");
+        unsafe { self.extensions[index].deallocate(ptr, layout) };
+
+        if HEAP_RELEASE_FREE_TAIL && index == self.extension_count - 1 {
+            self.release_free_tail();
+        }
+    }
+
+    fn grow(&mut self, layout: Layout) -> bool {
+        if self.extension_count == MAX_HEAP_EXTENSIONS {
+            return false;
+        }
+        // 对齐可能浪费最多align字节
+        let size = align_up(
+            (layout.size() + layout.align()).max(HEAP_GROWTH_SIZE),
+            PAGE_SIZE,
+        );
+        if self.heap_size() + size > HEAP_MAX_SIZE {
+            return false;
+        }
+
+        // 持有MemoryController锁时如果申请堆内存，这里用try_lock失败返回而不是死锁
+        let start = match MEMORY_CONTROLLER
+            .get()
+            .and_then(|controller| controller.try_lock())
+            .and_then(|mut controller| controller.grow_heap(size))
+        {
+            Some(start) => start,
+            None => return false,
+        };
+
+        unsafe {
+            self.extensions[self.extension_count].init(start, size);
+        }
+        self.extension_count += 1;
+        true
+    }
+
+    // 从末尾开始把完全空闲的扩展区还给帧分配器
+    fn release_free_tail(&mut self) {
+        while self.extension_count > 0 {
+            let heap = &self.extensions[self.extension_count - 1];
+            if heap.used() != 0 {
+                break;
+            }
+            let (start, size) = (heap.bottom(), heap.size());
+
+            let mut controller = match MEMORY_CONTROLLER.get().and_then(|c| c.try_lock()) {
+                Some(controller) => controller,
+                None => break,
+            };
+            controller.shrink_heap(start, size);
+            self.extensions[self.extension_count - 1] = linked_list_allocator::Heap::empty();
+            self.extension_count -= 1;
+            drop(controller);
+            self.return_free_blocks_to_tail();
+        }
+    }
+
+    // 空闲链表中属于最后一个扩展区的块还给这个扩展区，它完全空闲时才能释放
+    fn return_free_blocks_to_tail(&mut self) {
+        if self.extension_count == 0 {
+            return;
+        }
+        let tail = self.extension_count - 1;
+        for (index, &block_size) in BLOCK_SIZES.iter().enumerate() {
+            let layout = Layout::from_size_align(block_size, block_size).unwrap();
+            let mut kept = None;
+            let mut current = self.list_heads[index].take();
+            while let Some(node) = current {
+                current = node.next.take();
+                let address = &*node as *const ListNode as usize;
+                if self.in_tail(address) {
+                    let ptr = NonNull::new(address as *mut u8).unwrap();
+                    unsafe { self.extensions[tail].deallocate(ptr, layout) };
+                } else {
+                    node.next = kept;
+                    kept = Some(node);
+                }
+            }
+            self.list_heads[index] = kept;
+        }
+    }
+}
+
+impl HeapStats for FixedSizeBlockAllocator {
+    fn stats(&self) -> AllocatorStats {
+        // linked_list_allocator不公开空洞链表，用每段堆的空闲大小近似最大空闲块
+        let heaps = core::iter::once(&self.fallback_allocator)
+            .chain(self.extensions[..self.extension_count].iter());
+        let mut free_bytes = 0;
+        let mut largest_free_block = 0;
+        for heap in heaps {
+            free_bytes += heap.free();
+            largest_free_block = largest_free_block.max(heap.free());
+        }
+
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut current = head;
+            while let Some(node) = current {
+                free_bytes += BLOCK_SIZES[index];
+                largest_free_block = largest_free_block.max(BLOCK_SIZES[index]);
+                current = &node.next;
+            }
+        }
+
+        AllocatorStats {
+            heap_size: self.heap_size(),
+            free_bytes,
+            largest_free_block,
+            ..self.stats
+        }
+    }
 }
 
 fn list_index(layout: &Layout) -> Option<usize> {
@@ -52,7 +236,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
 
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => match allocator.list_heads[index].take() {
                 Some(node) => {
                     allocator.list_heads[index] = node.next.take();
@@ -67,12 +251,28 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             },
             None => allocator.fallback_alloc(layout),
+        };
+
+        if ptr.is_null() {
+            allocator.stats.record_failure();
+        } else {
+            allocator.stats.record_alloc(layout.size());
+            tracking::track_alloc(ptr, layout.size());
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.stats.record_dealloc(layout.size());
+        tracking::track_dealloc(ptr);
         match list_index(&layout) {
+            // 最后一个扩展区中的块直接还给它，不放进空闲链表
+            Some(index) if HEAP_RELEASE_FREE_TAIL && allocator.in_tail(ptr as usize) => {
+                let block_size = BLOCK_SIZES[index];
+                let layout = Layout::from_size_align(block_size, block_size).unwrap();
+                allocator.fallback_dealloc(NonNull::new(ptr).unwrap(), layout);
+            }
             Some(index) => {
                 let new_node = ListNode {
                     next: allocator.list_heads[index].take(),
@@ -88,9 +288,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             }
             None => {
                 let ptr = NonNull::new(ptr).unwrap();
-                unsafe {
-                    allocator.fallback_allocator.deallocate(ptr, layout);
-                }
+                allocator.fallback_dealloc(ptr, layout);
             }
         }
     }
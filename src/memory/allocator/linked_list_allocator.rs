@@ -1,7 +1,14 @@
 use core::mem;
 use core::ptr::null_mut;
 
-use crate::{memory::allocator::Locked, utils::align_up};
+use crate::{
+    memory::allocator::{
+        Locked,
+        stats::{AllocatorStats, HeapStats},
+        tracking,
+    },
+    utils::align_up,
+};
 use alloc::alloc::GlobalAlloc;
 use alloc::alloc::Layout;
 
@@ -25,55 +32,147 @@ impl ListNode {
     }
 }
 
+// 查找空闲区域的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    FirstFit,
+    BestFit,
+}
+
+// 空闲链表按地址排序，释放时和相邻的空闲区域合并
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_start: usize,
+    heap_size: usize,
+    strategy: FitStrategy,
+    stats: AllocatorStats,
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
         LinkedListAllocator {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_size: 0,
+            strategy: FitStrategy::FirstFit,
+            stats: AllocatorStats::new(),
         }
     }
 
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_size = heap_size;
         unsafe {
             self.add_free_region(heap_start, heap_size);
         }
     }
 
+    pub fn owns(&self, address: usize) -> bool {
+        self.heap_start <= address && address < self.heap_start + self.heap_size
+    }
+
+    pub fn strategy(&self) -> FitStrategy {
+        self.strategy
+    }
+
+    pub fn set_strategy(&mut self, strategy: FitStrategy) {
+        self.strategy = strategy;
+    }
+
+    // 空闲区域的数量，完全合并后只剩一个
+    pub fn free_regions(&self) -> usize {
+        let mut count = 0;
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            count += 1;
+            current = &region.next;
+        }
+        count
+    }
+
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        // 找到地址在addr之前的最后一个区域
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
 
+        let mut size = size;
+        let mut next = current.next.take();
+        if let Some(region) = next.as_ref() {
+            assert!(addr + size <= region.start_addr(), "free region overlaps");
+        }
+        if let Some(region) = next.as_mut().filter(|r| r.start_addr() == addr + size) {
+            size += region.size;
+            next = region.next.take();
+        }
+
+        // head不在堆中，它的结束地址不会等于addr
+        if current.end_addr() == addr {
+            current.size += size;
+            current.next = next;
+            return;
+        }
+        assert!(
+            current.end_addr() <= addr || current.size == 0,
+            "free region overlaps"
+        );
+
+        let mut node = ListNode::new(size);
+        node.next = next;
         let node_ptr = addr as *mut ListNode;
         unsafe {
             node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr);
+            current.next = Some(&mut *node_ptr);
         }
     }
 
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let target = self.choose_region(size, align)?;
+
         let mut current = &mut self.head;
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                current = current.next.as_mut().unwrap();
-            }
+        while current.next.as_ref().unwrap().start_addr() != target {
+            current = current.next.as_mut().unwrap();
         }
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
 
-        None
+        let alloc_start = Self::alloc_from_region(region, size, align).unwrap();
+        Some((region, alloc_start))
+    }
+
+    // 按照当前策略选出区域的起始地址
+    fn choose_region(&self, size: usize, align: usize) -> Option<usize> {
+        let mut best: Option<&ListNode> = None;
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            if Self::alloc_from_region(region, size, align).is_ok() {
+                if self.strategy == FitStrategy::FirstFit {
+                    return Some(region.start_addr());
+                }
+                if best.is_none_or(|best| region.size < best.size) {
+                    best = Some(region);
+                }
+            }
+            current = &region.next;
+        }
+        best.map(|region| region.start_addr())
     }
 
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        // 对齐留下的前部空隙要能放下一个节点，否则向后挪
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -89,6 +188,49 @@ impl LinkedListAllocator {
         Ok(alloc_start)
     }
 
+    // 尝试在原地扩展或缩小分配，成功时返回true
+    unsafe fn resize_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let node_size = mem::size_of::<ListNode>();
+        if new_size <= old_size {
+            let excess = old_size - new_size;
+            if excess == 0 {
+                return true;
+            }
+            if excess < node_size {
+                return false;
+            }
+            unsafe { self.add_free_region(addr + new_size, excess) };
+            return true;
+        }
+
+        // 紧跟在后面的区域必须是空闲的，并且足够大
+        let end = addr + old_size;
+        let needed = new_size - old_size;
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < end)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+        let region = match current.next.as_mut() {
+            Some(region) if region.start_addr() == end && region.size >= needed => region,
+            _ => return false,
+        };
+        let remaining = region.size - needed;
+        if remaining > 0 && remaining < node_size {
+            return false;
+        }
+
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        if remaining > 0 {
+            unsafe { self.add_free_region(end + needed, remaining) };
+        }
+        true
+    }
+
     fn size_align(layout: Layout) -> (usize, usize) {
         let layout = layout
             .align_to(mem::align_of::<ListNode>())
@@ -99,6 +241,25 @@ impl LinkedListAllocator {
     }
 }
 
+impl HeapStats for LinkedListAllocator {
+    fn stats(&self) -> AllocatorStats {
+        let mut free_bytes = 0;
+        let mut largest_free_block = 0;
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            free_bytes += region.size;
+            largest_free_block = largest_free_block.max(region.size);
+            current = &region.next;
+        }
+        AllocatorStats {
+            heap_size: self.heap_size,
+            free_bytes,
+            largest_free_block,
+            ..self.stats
+        }
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // perform layout adjustments
@@ -106,21 +267,59 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         let mut allocator = self.lock();
 
         if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
+            // 对齐留下的前部空隙和多余的尾部都放回空闲链表
+            if alloc_start > region_start {
+                unsafe {
+                    allocator.add_free_region(region_start, alloc_start - region_start);
+                }
+            }
+            let excess_size = region_end - alloc_end;
             if excess_size > 0 {
                 unsafe {
                     allocator.add_free_region(alloc_end, excess_size);
                 }
             }
+            allocator.stats.record_alloc(layout.size());
+            tracking::track_alloc(alloc_start as *mut u8, layout.size());
             alloc_start as *mut u8
         } else {
+            allocator.stats.record_failure();
             null_mut()
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let (size, _) = LinkedListAllocator::size_align(layout);
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+        let mut allocator = self.lock();
+        allocator.stats.record_dealloc(layout.size());
+        tracking::track_dealloc(ptr);
+        unsafe { allocator.add_free_region(ptr as usize, size) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let (old_size, _) = LinkedListAllocator::size_align(layout);
+        let (size, _) = LinkedListAllocator::size_align(new_layout);
+
+        {
+            let mut allocator = self.lock();
+            if unsafe { allocator.resize_in_place(ptr as usize, old_size, size) } {
+                allocator.stats.record_realloc(layout.size(), new_size);
+                tracking::track_resize(ptr, new_size);
+                return ptr;
+            }
+        }
+
+        // 不能原地调整时分配新的内存并复制
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
     }
 }
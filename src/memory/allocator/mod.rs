@@ -1,8 +1,26 @@
-use crate::memory::allocator::fixed_size_block_allocator::FixedSizeBlockAllocator;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use alloc::alloc::{GlobalAlloc, Layout};
+
+use crate::memory::{
+    MEMORY_CONTROLLER,
+    allocator::{
+        bump_allocator::BumpAllocator,
+        debug_allocator::DebugAllocator,
+        fixed_size_block_allocator::FixedSizeBlockAllocator,
+        linked_list_allocator::LinkedListAllocator,
+        slab_allocator::SlabAllocator,
+        stats::{AllocatorStats, HeapStats},
+    },
+};
 
 pub mod bump_allocator;
+pub mod debug_allocator;
 pub mod fixed_size_block_allocator;
 pub mod linked_list_allocator;
+pub mod slab_allocator;
+pub mod stats;
+pub mod tracking;
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -19,9 +37,234 @@ impl<A> Locked<A> {
     }
 }
 
-pub const HEAP_START: usize = 0o_000_001_000_000_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
+// 堆空间不足时每次至少扩展的大小，以及堆的总大小上限
+pub const HEAP_GROWTH_SIZE: usize = 64 * 1024; // 64 KiB
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+// 是否把完全空闲的末尾扩展区还给帧分配器
+pub const HEAP_RELEASE_FREE_TAIL: bool = true;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AllocatorBackend {
+    Bump = 0,
+    LinkedList = 1,
+    FixedSizeBlock = 2,
+    Slab = 3,
+}
+
+pub const ALLOCATOR_BACKENDS: [AllocatorBackend; 4] = [
+    AllocatorBackend::Bump,
+    AllocatorBackend::LinkedList,
+    AllocatorBackend::FixedSizeBlock,
+    AllocatorBackend::Slab,
+];
+
+impl AllocatorBackend {
+    // 编译时通过cargo feature选择，默认使用FixedSizeBlockAllocator
+    pub const fn default() -> AllocatorBackend {
+        if cfg!(feature = "alloc_bump") {
+            AllocatorBackend::Bump
+        } else if cfg!(feature = "alloc_linked_list") {
+            AllocatorBackend::LinkedList
+        } else if cfg!(feature = "alloc_slab") {
+            AllocatorBackend::Slab
+        } else {
+            AllocatorBackend::FixedSizeBlock
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AllocatorBackend> {
+        ALLOCATOR_BACKENDS.into_iter().find(|b| b.name() == name)
+    }
+
+    // 内核命令行中的allocator=<name>
+    pub fn from_command_line(command_line: &str) -> Option<AllocatorBackend> {
+        command_line
+            .split_whitespace()
+            .find_map(|arg| arg.strip_prefix("allocator="))
+            .and_then(AllocatorBackend::from_name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AllocatorBackend::Bump => "bump",
+            AllocatorBackend::LinkedList => "linked_list",
+            AllocatorBackend::FixedSizeBlock => "fixed_size_block",
+            AllocatorBackend::Slab => "slab",
+        }
+    }
+}
+
+// 把分配转发给当前选择的后端，释放时转发给拥有这块内存的后端，
+// 这样切换后端之前分配的内存也能正确释放
+pub struct DispatchAllocator {
+    backend: AtomicU8,
+    // 已经初始化的后端，每个后端一位
+    initialized: AtomicU8,
+    bump: Locked<BumpAllocator>,
+    linked_list: Locked<LinkedListAllocator>,
+    fixed_size_block: Locked<FixedSizeBlockAllocator>,
+    slab: Locked<SlabAllocator>,
+}
+
+impl DispatchAllocator {
+    pub const fn new() -> Self {
+        DispatchAllocator {
+            backend: AtomicU8::new(AllocatorBackend::default() as u8),
+            initialized: AtomicU8::new(0),
+            bump: Locked::new(BumpAllocator::new()),
+            linked_list: Locked::new(LinkedListAllocator::new()),
+            fixed_size_block: Locked::new(FixedSizeBlockAllocator::new()),
+            slab: Locked::new(SlabAllocator::new()),
+        }
+    }
+
+    pub fn backend(&self) -> AllocatorBackend {
+        ALLOCATOR_BACKENDS[self.backend.load(Ordering::SeqCst) as usize]
+    }
+
+    pub fn is_initialized(&self, backend: AllocatorBackend) -> bool {
+        self.initialized.load(Ordering::SeqCst) & (1 << backend as u8) != 0
+    }
+
+    pub unsafe fn init(&self, backend: AllocatorBackend, heap_start: usize, heap_size: usize) {
+        assert!(
+            !self.is_initialized(backend),
+            "allocator backend initialized twice"
+        );
+        unsafe {
+            match backend {
+                AllocatorBackend::Bump => self.bump.lock().init(heap_start, heap_size),
+                AllocatorBackend::LinkedList => self.linked_list.lock().init(heap_start, heap_size),
+                AllocatorBackend::FixedSizeBlock => {
+                    self.fixed_size_block.lock().init(heap_start, heap_size)
+                }
+                AllocatorBackend::Slab => self.slab.lock().init(heap_start, heap_size),
+            }
+        }
+        self.initialized
+            .fetch_or(1 << backend as u8, Ordering::SeqCst);
+    }
+
+    pub fn select(&self, backend: AllocatorBackend) {
+        assert!(
+            self.is_initialized(backend),
+            "allocator backend is not initialized"
+        );
+        self.backend.store(backend as u8, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        match self.backend() {
+            AllocatorBackend::Bump => self.bump.lock().stats(),
+            AllocatorBackend::LinkedList => self.linked_list.lock().stats(),
+            AllocatorBackend::FixedSizeBlock => self.fixed_size_block.lock().stats(),
+            AllocatorBackend::Slab => self.slab.lock().stats(),
+        }
+    }
+
+    pub fn fixed_size_block(&self) -> &Locked<FixedSizeBlockAllocator> {
+        &self.fixed_size_block
+    }
+
+    pub fn linked_list(&self) -> &Locked<LinkedListAllocator> {
+        &self.linked_list
+    }
+
+    pub fn slab(&self) -> &Locked<SlabAllocator> {
+        &self.slab
+    }
+
+    pub fn owns(&self, address: usize) -> bool {
+        self.find_owner(address).is_some()
+    }
+
+    fn find_owner(&self, address: usize) -> Option<AllocatorBackend> {
+        ALLOCATOR_BACKENDS
+            .into_iter()
+            .filter(|&backend| self.is_initialized(backend))
+            .find(|backend| match backend {
+                AllocatorBackend::Bump => self.bump.lock().owns(address),
+                AllocatorBackend::LinkedList => self.linked_list.lock().owns(address),
+                AllocatorBackend::FixedSizeBlock => self.fixed_size_block.lock().owns(address),
+                AllocatorBackend::Slab => self.slab.lock().owns(address),
+            })
+    }
+
+    fn owner(&self, ptr: *mut u8) -> AllocatorBackend {
+        self.find_owner(ptr as usize)
+            .expect("deallocating memory that does not belong to any allocator")
+    }
+}
+
+unsafe impl GlobalAlloc for DispatchAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe {
+            match self.backend() {
+                AllocatorBackend::Bump => self.bump.alloc(layout),
+                AllocatorBackend::LinkedList => self.linked_list.alloc(layout),
+                AllocatorBackend::FixedSizeBlock => self.fixed_size_block.alloc(layout),
+                AllocatorBackend::Slab => self.slab.alloc(layout),
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            match self.owner(ptr) {
+                AllocatorBackend::Bump => self.bump.dealloc(ptr, layout),
+                AllocatorBackend::LinkedList => self.linked_list.dealloc(ptr, layout),
+                AllocatorBackend::FixedSizeBlock => self.fixed_size_block.dealloc(ptr, layout),
+                AllocatorBackend::Slab => self.slab.dealloc(ptr, layout),
+            }
+        }
+    }
+
+    // 原来的后端仍是当前后端时由它处理，否则在当前后端中重新分配
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let backend = self.backend();
+        if self.owner(ptr) == backend {
+            return unsafe {
+                match backend {
+                    AllocatorBackend::Bump => self.bump.realloc(ptr, layout, new_size),
+                    AllocatorBackend::LinkedList => self.linked_list.realloc(ptr, layout, new_size),
+                    AllocatorBackend::FixedSizeBlock => {
+                        self.fixed_size_block.realloc(ptr, layout, new_size)
+                    }
+                    AllocatorBackend::Slab => self.slab.realloc(ptr, layout, new_size),
+                }
+            };
+        }
+
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+pub static HEAP_ALLOCATOR: DispatchAllocator = DispatchAllocator::new();
+
+// 调试模式没有打开时直接转发给HEAP_ALLOCATOR
 #[global_allocator]
-pub static HEAP_ALLOCATOR: Locked<FixedSizeBlockAllocator> =
-    Locked::new(FixedSizeBlockAllocator::new());
+pub static GLOBAL_ALLOCATOR: DebugAllocator = DebugAllocator::new(&HEAP_ALLOCATOR);
+
+// 切换全局分配器的后端，后端第一次使用时向MemoryController申请堆内存。
+// 返回之前的后端
+pub fn select_backend(backend: AllocatorBackend) -> Option<AllocatorBackend> {
+    if !HEAP_ALLOCATOR.is_initialized(backend) {
+        let heap_start = MEMORY_CONTROLLER.get()?.lock().grow_heap(HEAP_SIZE)?;
+        unsafe { HEAP_ALLOCATOR.init(backend, heap_start, HEAP_SIZE) };
+    }
+    let previous = HEAP_ALLOCATOR.backend();
+    HEAP_ALLOCATOR.select(backend);
+    Some(previous)
+}
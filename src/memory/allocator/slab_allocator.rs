@@ -0,0 +1,529 @@
+use core::{
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull, null_mut},
+};
+
+use alloc::alloc::{GlobalAlloc, Layout};
+
+use crate::memory::{
+    Frame, FrameAllocator, MEMORY_CONTROLLER, PAGE_SIZE,
+    allocator::{
+        Locked,
+        stats::{AllocatorStats, HeapStats},
+        tracking,
+    },
+    paging::{PHYS_MEM_MAX, PHYS_MEM_OFFSET, VirtualAddress, phys_to_virt, virt_to_phys},
+};
+
+// 每个slab占一页物理帧，通过直接映射访问。页首是slab的管理信息，后面是对象
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    free_list: *mut FreeObject,
+    in_use: usize,
+}
+
+// 空闲链表的指针放在对象之后，不会破坏构造函数初始化好的对象
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+struct SlabList {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl SlabList {
+    const fn new() -> SlabList {
+        SlabList {
+            head: null_mut(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = null_mut();
+            (*slab).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = slab;
+            }
+        }
+        self.head = slab;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.len -= 1;
+    }
+
+    fn contains(&self, slab: *mut Slab) -> bool {
+        let mut current = self.head;
+        while !current.is_null() {
+            if current == slab {
+                return true;
+            }
+            current = unsafe { (*current).next };
+        }
+        false
+    }
+
+    fn pop(&mut self) -> Option<*mut Slab> {
+        let slab = self.head;
+        if slab.is_null() {
+            return None;
+        }
+        self.remove(slab);
+        Some(slab)
+    }
+}
+
+const fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+// 同一种大小对象的缓存。对象只在slab创建时构造一次，
+// 释放时调用者需要把对象恢复到构造后的状态，下次分配直接复用
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    link_offset: usize,
+    slot_size: usize,
+    first_object: usize,
+    objects_per_slab: usize,
+    constructor: Option<fn(*mut u8)>,
+    partial: SlabList,
+    full: SlabList,
+    empty: SlabList,
+    objects_in_use: usize,
+}
+
+// 只通过外层的锁访问
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(
+        name: &'static str,
+        object_size: usize,
+        align: usize,
+        constructor: Option<fn(*mut u8)>,
+    ) -> SlabCache {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let align = max(align, mem::align_of::<FreeObject>());
+        let link_offset = round_up(object_size, mem::align_of::<FreeObject>());
+        let slot_size = round_up(link_offset + mem::size_of::<FreeObject>(), align);
+        let first_object = round_up(mem::size_of::<Slab>(), align);
+        assert!(
+            first_object + slot_size <= PAGE_SIZE,
+            "object too large for a slab"
+        );
+
+        SlabCache {
+            name,
+            object_size,
+            link_offset,
+            slot_size,
+            first_object,
+            objects_per_slab: (PAGE_SIZE - first_object) / slot_size,
+            constructor,
+            partial: SlabList::new(),
+            full: SlabList::new(),
+            empty: SlabList::new(),
+            objects_in_use: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    pub fn objects_per_slab(&self) -> usize {
+        self.objects_per_slab
+    }
+
+    pub fn objects_in_use(&self) -> usize {
+        self.objects_in_use
+    }
+
+    // (partial, full, empty)
+    pub fn slab_counts(&self) -> (usize, usize, usize) {
+        (self.partial.len, self.full.len, self.empty.len)
+    }
+
+    // address是否在这个缓存的某个slab中。只比较slab的地址，不读取其他页的内容
+    pub fn owns(&self, address: VirtualAddress) -> bool {
+        let slab = (address & !(PAGE_SIZE - 1)) as *mut Slab;
+        [&self.partial, &self.full, &self.empty]
+            .iter()
+            .any(|list| list.contains(slab))
+    }
+
+    pub fn alloc<A>(&mut self, allocator: &mut A) -> Option<NonNull<u8>>
+    where
+        A: FrameAllocator,
+    {
+        // 优先使用部分使用的slab，其次是空slab，最后才申请新的页
+        if self.partial.head.is_null() {
+            let slab = match self.empty.pop() {
+                Some(slab) => slab,
+                None => self.new_slab(allocator)?,
+            };
+            self.partial.push(slab);
+        }
+
+        let slab = unsafe { &mut *self.partial.head };
+        let free = slab.free_list;
+        slab.free_list = unsafe { (*free).next };
+        slab.in_use += 1;
+        if slab.in_use == self.objects_per_slab {
+            self.partial.remove(slab);
+            self.full.push(slab);
+        }
+
+        self.objects_in_use += 1;
+        NonNull::new(self.object_of(free))
+    }
+
+    pub unsafe fn free(&mut self, object: NonNull<u8>) {
+        let address = object.as_ptr() as VirtualAddress;
+        let slab_address = address & !(PAGE_SIZE - 1);
+        let offset = address - slab_address;
+        assert!(
+            offset >= self.first_object
+                && (offset - self.first_object).is_multiple_of(self.slot_size),
+            "{}: freeing an invalid object 0x{:x}",
+            self.name,
+            address
+        );
+
+        let slab = unsafe { &mut *(slab_address as *mut Slab) };
+        let free = (address + self.link_offset) as *mut FreeObject;
+        unsafe {
+            (*free).next = slab.free_list;
+        }
+        slab.free_list = free;
+
+        if slab.in_use == self.objects_per_slab {
+            self.full.remove(slab);
+            self.partial.push(slab);
+        }
+        slab.in_use -= 1;
+        if slab.in_use == 0 {
+            self.partial.remove(slab);
+            self.empty.push(slab);
+        }
+        self.objects_in_use -= 1;
+    }
+
+    // 把所有空slab的帧还给帧分配器，返回释放的slab数量
+    pub fn reclaim<A>(&mut self, allocator: &mut A) -> usize
+    where
+        A: FrameAllocator,
+    {
+        let mut count = 0;
+        while let Some(slab) = self.empty.pop() {
+            allocator.deallocate_frame(Frame::containing_address(virt_to_phys(slab as usize)));
+            count += 1;
+        }
+        count
+    }
+
+    fn new_slab<A>(&mut self, allocator: &mut A) -> Option<*mut Slab>
+    where
+        A: FrameAllocator,
+    {
+        let frame = allocator.allocate_frame()?;
+        let start = phys_to_virt(frame.start_address());
+
+        // 从后往前串起空闲链表，这样分配顺序和地址顺序一致
+        let mut free_list = null_mut();
+        for i in (0..self.objects_per_slab).rev() {
+            let object = start + self.first_object + i * self.slot_size;
+            if let Some(constructor) = self.constructor {
+                constructor(object as *mut u8);
+            }
+            let free = (object + self.link_offset) as *mut FreeObject;
+            unsafe {
+                (*free).next = free_list;
+            }
+            free_list = free;
+        }
+
+        let slab = start as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                prev: null_mut(),
+                next: null_mut(),
+                free_list,
+                in_use: 0,
+            });
+        }
+        Some(slab)
+    }
+
+    fn object_of(&self, free: *mut FreeObject) -> *mut u8 {
+        (free as usize - self.link_offset) as *mut u8
+    }
+}
+
+// 一种类型专用的对象缓存，对象由SlabBox持有，丢弃时回到缓存
+pub struct ObjectCache<T> {
+    cache: spin::Mutex<SlabCache>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ObjectCache<T> {
+    pub const fn new(name: &'static str) -> ObjectCache<T> {
+        ObjectCache {
+            cache: spin::Mutex::new(SlabCache::new(
+                name,
+                mem::size_of::<T>(),
+                mem::align_of::<T>(),
+                None,
+            )),
+            _marker: PhantomData,
+        }
+    }
+
+    // 把value放进缓存，需要新的slab但申请不到帧时把value还给调用者
+    pub fn alloc<A>(&'static self, value: T, allocator: &mut A) -> Result<SlabBox<T>, T>
+    where
+        A: FrameAllocator,
+    {
+        let Some(object) = self.cache.lock().alloc(allocator) else {
+            return Err(value);
+        };
+        let object = object.cast::<T>();
+        unsafe { object.write(value) };
+        Ok(SlabBox {
+            object,
+            cache: self,
+        })
+    }
+
+    pub fn objects_in_use(&self) -> usize {
+        self.cache.lock().objects_in_use()
+    }
+
+    // (partial, full, empty)
+    pub fn slab_counts(&self) -> (usize, usize, usize) {
+        self.cache.lock().slab_counts()
+    }
+
+    // 归还对象的槽位，空slab过多时尝试把它们还给帧分配器。
+    // 调用者可能持有MemoryController的锁，所以只try_lock
+    unsafe fn free(&self, object: NonNull<T>) {
+        let mut cache = self.cache.lock();
+        unsafe { cache.free(object.cast()) };
+        if cache.slab_counts().2 > MAX_EMPTY_SLABS
+            && let Some(mut controller) = MEMORY_CONTROLLER.get().and_then(|c| c.try_lock())
+        {
+            cache.reclaim(&mut *controller);
+        }
+    }
+}
+
+// 指向ObjectCache中对象的指针，用法和Box相同
+pub struct SlabBox<T: 'static> {
+    object: NonNull<T>,
+    cache: &'static ObjectCache<T>,
+}
+
+// 和Box<T>一样，对象只属于这一个SlabBox
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> SlabBox<T> {
+    // 取出对象并归还槽位
+    pub fn into_inner(this: SlabBox<T>) -> T {
+        let this = mem::ManuallyDrop::new(this);
+        let value = unsafe { this.object.read() };
+        unsafe { this.cache.free(this.object) };
+        value
+    }
+}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            self.cache.free(self.object);
+        }
+    }
+}
+
+// 通用的slab分配器，每个大小类别一个缓存，更大或对齐要求更高的分配使用后备堆
+const SLAB_ALIGN: usize = 16;
+const SLAB_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024];
+
+// 每个缓存保留的空slab数量，超过时把空slab还给帧分配器
+const MAX_EMPTY_SLABS: usize = 1;
+
+pub struct SlabAllocator {
+    caches: [SlabCache; SLAB_SIZES.len()],
+    fallback_allocator: linked_list_allocator::Heap,
+    stats: AllocatorStats,
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            caches: [
+                SlabCache::new("kmalloc-8", 8, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-16", 16, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-32", 32, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-64", 64, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-128", 128, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-256", 256, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-512", 512, SLAB_ALIGN, None),
+                SlabCache::new("kmalloc-1024", 1024, SLAB_ALIGN, None),
+            ],
+            fallback_allocator: linked_list_allocator::Heap::empty(),
+            stats: AllocatorStats::new(),
+        }
+    }
+
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe {
+            self.fallback_allocator.init(heap_start, heap_size);
+        }
+    }
+
+    pub fn caches(&self) -> &[SlabCache] {
+        &self.caches
+    }
+
+    // slab都在物理内存的直接映射中，但直接映射中的其他地址不属于这里
+    pub fn owns(&self, address: VirtualAddress) -> bool {
+        let heap = &self.fallback_allocator;
+        if heap.bottom() <= address && address < heap.top() {
+            return true;
+        }
+        (PHYS_MEM_OFFSET..PHYS_MEM_OFFSET + PHYS_MEM_MAX).contains(&address)
+            && self.caches.iter().any(|cache| cache.owns(address))
+    }
+
+    fn cache_index(layout: &Layout) -> Option<usize> {
+        if layout.align() > SLAB_ALIGN {
+            return None;
+        }
+        SLAB_SIZES.iter().position(|&s| s >= layout.size())
+    }
+
+    fn alloc_from_cache(&mut self, index: usize) -> Option<NonNull<u8>> {
+        // 持有MemoryController锁时分配会失败，改用后备堆
+        let mut controller = MEMORY_CONTROLLER.get()?.try_lock()?;
+        self.caches[index].alloc(&mut *controller)
+    }
+
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        match self.fallback_allocator.allocate_first_fit(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+}
+
+impl HeapStats for SlabAllocator {
+    fn stats(&self) -> AllocatorStats {
+        let heap = &self.fallback_allocator;
+        let mut heap_size = heap.size();
+        let mut free_bytes = heap.free();
+        let mut largest_free_block = heap.free();
+        for cache in self.caches.iter() {
+            let (partial, full, empty) = cache.slab_counts();
+            let slabs = partial + full + empty;
+            let free_objects = slabs * cache.objects_per_slab() - cache.objects_in_use();
+            heap_size += slabs * PAGE_SIZE;
+            free_bytes += free_objects * cache.object_size();
+            if free_objects > 0 {
+                largest_free_block = largest_free_block.max(cache.object_size());
+            }
+        }
+
+        AllocatorStats {
+            heap_size,
+            free_bytes,
+            largest_free_block,
+            ..self.stats
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = match SlabAllocator::cache_index(&layout) {
+            Some(index) => match allocator.alloc_from_cache(index) {
+                Some(ptr) => ptr.as_ptr(),
+                None => allocator.fallback_alloc(layout),
+            },
+            None => allocator.fallback_alloc(layout),
+        };
+
+        if ptr.is_null() {
+            allocator.stats.record_failure();
+        } else {
+            allocator.stats.record_alloc(layout.size());
+            tracking::track_alloc(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.stats.record_dealloc(layout.size());
+        tracking::track_dealloc(ptr);
+
+        let address = ptr as VirtualAddress;
+        let heap = &allocator.fallback_allocator;
+        let ptr = NonNull::new(ptr).unwrap();
+        if heap.bottom() <= address && address < heap.top() {
+            unsafe { allocator.fallback_allocator.deallocate(ptr, layout) };
+            return;
+        }
+
+        let cache = &mut allocator.caches[SlabAllocator::cache_index(&layout).unwrap()];
+        unsafe { cache.free(ptr) };
+        if cache.slab_counts().2 > MAX_EMPTY_SLABS
+            && let Some(mut controller) = MEMORY_CONTROLLER.get().and_then(|c| c.try_lock())
+        {
+            cache.reclaim(&mut *controller);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// 按2的幂划分的大小类别：8, 16, ..., 2048，以及更大的分配
+pub const SIZE_CLASSES: usize = 10;
+
+pub fn size_class(size: usize) -> usize {
+    let class = size.max(8).next_power_of_two().trailing_zeros() as usize - 3;
+    class.min(SIZE_CLASSES - 1)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    pub heap_size: usize,
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub free_bytes: usize,
+    pub largest_free_block: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failed_allocations: usize,
+    // 每个大小类别中还未释放的分配数
+    pub live_by_size_class: [usize; SIZE_CLASSES],
+}
+
+impl AllocatorStats {
+    pub const fn new() -> AllocatorStats {
+        AllocatorStats {
+            heap_size: 0,
+            bytes_in_use: 0,
+            peak_bytes_in_use: 0,
+            free_bytes: 0,
+            largest_free_block: 0,
+            allocations: 0,
+            deallocations: 0,
+            failed_allocations: 0,
+            live_by_size_class: [0; SIZE_CLASSES],
+        }
+    }
+
+    pub fn record_alloc(&mut self, size: usize) {
+        self.allocations += 1;
+        self.bytes_in_use += size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+        self.live_by_size_class[size_class(size)] += 1;
+    }
+
+    pub fn record_dealloc(&mut self, size: usize) {
+        self.deallocations += 1;
+        self.bytes_in_use -= size;
+        self.live_by_size_class[size_class(size)] -= 1;
+    }
+
+    // 原地调整大小，不计入分配和释放次数
+    pub fn record_realloc(&mut self, old_size: usize, new_size: usize) {
+        self.bytes_in_use = self.bytes_in_use - old_size + new_size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+        self.live_by_size_class[size_class(old_size)] -= 1;
+        self.live_by_size_class[size_class(new_size)] += 1;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failed_allocations += 1;
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+
+    // 碎片率(百分比)：空闲空间中不属于最大空闲块的部分
+    pub fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        100 - self.largest_free_block * 100 / self.free_bytes
+    }
+}
+
+// 各个分配器都提供的统计接口，heap_size/free_bytes/largest_free_block在调用时计算
+pub trait HeapStats {
+    fn stats(&self) -> AllocatorStats;
+}
@@ -0,0 +1,134 @@
+// 记录每个未释放的分配以及分配时的调用栈，用来在测试之后发现内存泄漏。
+// 需要帧指针来回溯调用栈，目标配置中打开了frame-pointer
+use spin::Mutex;
+
+use crate::{memory::KERNEL_SPACE_START, serial_println, utils::x86_64_control::read_rbp};
+
+pub const MAX_TRACKED: usize = 512;
+pub const TRACE_DEPTH: usize = 8;
+
+// 跳过backtrace和track_alloc自己的栈帧
+const SKIPPED_FRAMES: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationRecord {
+    pub id: u64,
+    pub address: usize,
+    pub size: usize,
+    // 调用者的返回地址，由近到远
+    pub callers: [usize; TRACE_DEPTH],
+}
+
+struct AllocationTracker {
+    records: [Option<AllocationRecord>; MAX_TRACKED],
+    next_id: u64,
+    // 记录表满了之后没能记录的分配数
+    untracked: usize,
+}
+
+static TRACKER: Mutex<AllocationTracker> = Mutex::new(AllocationTracker {
+    records: [None; MAX_TRACKED],
+    next_id: 0,
+    untracked: 0,
+});
+
+// 沿着rbp链取返回地址，栈帧必须在内核空间中。结果不包括调用者自己
+pub fn backtrace() -> [usize; TRACE_DEPTH] {
+    let mut callers = [0; TRACE_DEPTH];
+    let mut rbp = read_rbp() as usize;
+    for depth in 0..SKIPPED_FRAMES + TRACE_DEPTH {
+        if rbp < KERNEL_SPACE_START || !rbp.is_multiple_of(8) {
+            break;
+        }
+        unsafe {
+            if depth >= SKIPPED_FRAMES {
+                callers[depth - SKIPPED_FRAMES] = *((rbp + 8) as *const usize);
+            }
+            rbp = *(rbp as *const usize);
+        }
+    }
+    callers
+}
+
+pub fn track_alloc(address: *mut u8, size: usize) {
+    if !cfg!(feature = "alloc_tracking") || address.is_null() {
+        return;
+    }
+    let callers = backtrace();
+
+    let mut tracker = TRACKER.lock();
+    let id = tracker.next_id;
+    tracker.next_id += 1;
+    match tracker.records.iter_mut().find(|r| r.is_none()) {
+        Some(slot) => {
+            *slot = Some(AllocationRecord {
+                id,
+                address: address as usize,
+                size,
+                callers,
+            })
+        }
+        None => tracker.untracked += 1,
+    }
+}
+
+pub fn track_dealloc(address: *mut u8) {
+    if !cfg!(feature = "alloc_tracking") {
+        return;
+    }
+    let mut tracker = TRACKER.lock();
+    if let Some(slot) = tracker
+        .records
+        .iter_mut()
+        .find(|r| r.is_some_and(|r| r.address == address as usize))
+    {
+        *slot = None;
+    }
+}
+
+// 原地调整大小的分配保留原来的记录
+pub fn track_resize(address: *mut u8, size: usize) {
+    if !cfg!(feature = "alloc_tracking") {
+        return;
+    }
+    let mut tracker = TRACKER.lock();
+    if let Some(record) = tracker
+        .records
+        .iter_mut()
+        .flatten()
+        .find(|r| r.address == address as usize)
+    {
+        record.size = size;
+    }
+}
+
+// 之后的分配id都不小于返回值
+pub fn mark() -> u64 {
+    TRACKER.lock().next_id
+}
+
+pub fn leaks_since(mark: u64) -> usize {
+    TRACKER
+        .lock()
+        .records
+        .iter()
+        .flatten()
+        .filter(|r| r.id >= mark)
+        .count()
+}
+
+// 通过串口打印mark之后还没有释放的分配，返回数量
+pub fn report_leaks_since(mark: u64) -> usize {
+    let tracker = TRACKER.lock();
+    let mut count = 0;
+    for record in tracker.records.iter().flatten().filter(|r| r.id >= mark) {
+        serial_println!(
+            "leak: {} bytes at {:#x}, allocated from {:#x?}",
+            record.size,
+            record.address,
+            record.callers
+        );
+        count += 1;
+    }
+    count
+}
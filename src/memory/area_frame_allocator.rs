@@ -1,6 +1,8 @@
 use core::panic;
 
-use crate::multiboot_info::{MultibootAddressSection, MultibootInfo, MultibootMemMapEntry};
+use crate::multiboot_info::{
+    MemoryMapEntryType, MultibootAddressSection, MultibootInfo, MultibootMemMapEntry,
+};
 
 use super::{Frame, FrameAllocator};
 
@@ -16,6 +18,8 @@ pub struct AreaFrameAllocator<'a> {
     kernel_end: Frame,
     multiboot_start: Frame,
     multiboot_end: Frame,
+    // 启动模块在加载程序之前不能被覆盖
+    modules: Option<(Frame, Frame)>,
     free_count: usize, // 当前 free_list 中帧数量
 }
 
@@ -49,6 +53,14 @@ impl<'a> FrameAllocator for AreaFrameAllocator<'a> {
                 self.next_free_frame = Frame {
                     number: self.multiboot_end.number + 1,
                 };
+            } else if let Some((_, modules_end)) = self
+                .modules
+                .as_ref()
+                .filter(|(start, end)| frame >= *start && frame <= *end)
+            {
+                self.next_free_frame = Frame {
+                    number: modules_end.number + 1,
+                };
             } else {
                 self.next_free_frame = Frame {
                     number: frame.number + 1,
@@ -76,6 +88,7 @@ impl<'a> AreaFrameAllocator<'a> {
         self.current_area = self
             .areas
             .iter()
+            .filter(|area| area.entry_type == MemoryMapEntryType::Available as u32)
             .filter(|area| {
                 let address = area.base_addr as usize + area.length as usize - 1;
                 Frame::containing_address(address) >= self.next_free_frame
@@ -95,6 +108,7 @@ impl<'a> AreaFrameAllocator<'a> {
         kernel_end: usize,
         multiboot_start: usize,
         multiboot_end: usize,
+        modules: Option<(usize, usize)>,
         memory_areas: &'a [MultibootMemMapEntry],
     ) -> Self {
         let mut allocator = AreaFrameAllocator::<'a> {
@@ -105,6 +119,12 @@ impl<'a> AreaFrameAllocator<'a> {
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
             multiboot_end: Frame::containing_address(multiboot_end),
+            modules: modules.map(|(start, end)| {
+                (
+                    Frame::containing_address(start),
+                    Frame::containing_address(end),
+                )
+            }),
             free_count: 0,
         };
         allocator.choose_next_area();
@@ -120,6 +140,7 @@ impl<'a> AreaFrameAllocator<'a> {
             multiboot_address_sections.kernel_end,
             multiboot_address_sections.multiboot_start,
             multiboot_address_sections.multiboot_end,
+            multiboot_address_sections.modules,
             memory_areas,
         )
     }
@@ -132,6 +153,7 @@ impl<'a> AreaFrameAllocator<'a> {
             address_sections.kernel_end,
             address_sections.multiboot_start,
             address_sections.multiboot_end,
+            address_sections.modules,
             memory_entries,
         )
     }
@@ -1,16 +1,27 @@
+use spin::{Mutex, Once};
+
 use crate::{
     assert_has_not_been_called,
     memory::{
-        allocator::{HEAP_ALLOCATOR, HEAP_SIZE, HEAP_START},
+        address_space::AddressSpace,
+        allocator::{AllocatorBackend, GLOBAL_ALLOCATOR, HEAP_ALLOCATOR, HEAP_SIZE},
         area_frame_allocator::AreaFrameAllocator,
-        paging::{EntryFlags, Page, PhysicalAddress},
+        paging::{
+            EntryFlags, InactivePageTable, KERNEL_OFFSET, Page, PhysicalAddress, VirtualAddress,
+            mapper::Mapper, phys_to_virt, temporary_page::TemporaryPage, walker::Mappings,
+        },
+        region::{Backing, Region, RegionError, RegionKind},
+        shared_frames::SHARED_FRAMES,
     },
     multiboot_info::MultibootInfo,
 };
 
+pub mod address_space;
 pub mod allocator;
 pub mod area_frame_allocator;
 pub mod paging;
+pub mod region;
+pub mod shared_frames;
 pub mod stack_allocator;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -34,7 +45,7 @@ impl Frame {
         }
     }
 
-    fn start_address(&self) -> PhysicalAddress {
+    pub fn start_address(&self) -> PhysicalAddress {
         self.number * PAGE_SIZE
     }
 
@@ -81,7 +92,19 @@ pub trait FrameAllocator {
     fn deallocate_frame(&mut self, frame: Frame);
 }
 
-pub fn init<'a>(boot_info: &'a MultibootInfo) -> MemoryController<'a> {
+// 缺页处理程序也需要访问，所以放在全局
+pub static MEMORY_CONTROLLER: Once<Mutex<MemoryController<'static>>> = Once::new();
+
+// 内核动态分配的虚拟地址范围：堆、栈和MMIO窗口等，
+// 位于物理内存直接映射之后、递归映射之前
+pub const KERNEL_SPACE_START: VirtualAddress = 0xffff_c000_0000_0000;
+pub const KERNEL_SPACE_END: VirtualAddress = 0xffff_ff00_0000_0000;
+
+// 用户程序可以使用的虚拟地址范围，第一个4MiB不映射，空指针访问会缺页
+pub const USER_SPACE_START: VirtualAddress = 0x40_0000;
+pub const USER_SPACE_END: VirtualAddress = 0x0000_8000_0000_0000;
+
+pub fn init(boot_info: &'static MultibootInfo) -> &'static Mutex<MemoryController<'static>> {
     assert_has_not_been_called!("memory::init must be called only once");
 
     use crate::{memory::paging::remap_the_kernel, utils::x86_64_control};
@@ -91,49 +114,597 @@ pub fn init<'a>(boot_info: &'a MultibootInfo) -> MemoryController<'a> {
     x86_64_control::enable_nxe_bit();
     x86_64_control::enable_write_protect_bit();
     let mut active_table = remap_the_kernel(&mut frame_allocator, boot_info);
+    // 在remap_the_kernel之后打开，内核页表使用PCID 0
+    x86_64_control::enable_pcid();
+
+    let mut kernel_space = AddressSpace::new(KERNEL_SPACE_START, KERNEL_SPACE_END);
+
+    // 和remap_the_kernel使用同一个临时页，之后用来访问其他页表
+    let temporary_page = TemporaryPage::new(
+        Page::containing_address(KERNEL_OFFSET - PAGE_SIZE),
+        &mut frame_allocator,
+    );
 
     // Initialize the heap
-    let heap_start_page = Page::containing_address(HEAP_START);
-    let heap_end_page = Page::containing_address(HEAP_START + HEAP_SIZE - 1);
+    let heap_flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+    let heap_start = kernel_space
+        .allocate(HEAP_SIZE, heap_flags, RegionKind::Heap, Backing::Eager)
+        .expect("could not reserve the kernel heap");
+    let heap_start_page = Page::containing_address(heap_start);
+    let heap_end_page = Page::containing_address(heap_start + HEAP_SIZE - 1);
 
     for page in Page::range_inclusive(heap_start_page, heap_end_page) {
-        active_table.map(page, EntryFlags::WRITABLE, &mut frame_allocator);
+        active_table.map(page, heap_flags, &mut frame_allocator);
     }
 
-    // Initialize the heap allocator
+    // Initialize the heap allocator, the command line overrides the cargo feature
+    let command_line = boot_info.get_command_line();
+    let backend =
+        AllocatorBackend::from_command_line(command_line).unwrap_or(AllocatorBackend::default());
+    let guard_pages =
+        cfg!(feature = "heap_guard_pages") || boot_info.has_boot_option("heap_guard_pages");
+    if cfg!(feature = "heap_debug") || boot_info.has_boot_option("heap_debug") || guard_pages {
+        GLOBAL_ALLOCATOR.enable(guard_pages);
+    }
     unsafe {
-        HEAP_ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        HEAP_ALLOCATOR.init(backend, heap_start, HEAP_SIZE);
     };
+    HEAP_ALLOCATOR.select(backend);
 
-    let stack_allocator = {
-        let stack_alloc_start = heap_end_page + 1;
-        let stack_alloc_end = stack_alloc_start + 100;
-        let stack_alloc_range = Page::range_inclusive(stack_alloc_start, stack_alloc_end);
-        stack_allocator::StackAllocator::new(stack_alloc_range)
-    };
-
-    MemoryController {
-        active_table: active_table,
-        frame_allocator: frame_allocator,
-        stack_allocator: stack_allocator,
-    }
+    MEMORY_CONTROLLER.call_once(|| {
+        Mutex::new(MemoryController {
+            active_table,
+            frame_allocator,
+            stack_allocator: stack_allocator::StackAllocator::new(),
+            kernel_space,
+            temporary_page,
+        })
+    })
 }
 
 pub use self::stack_allocator::Stack;
 
+fn map_zeroed_user_pages<A, F>(
+    mapper: &mut Mapper,
+    allocator: &mut A,
+    start: VirtualAddress,
+    size_in_pages: usize,
+    flags: EntryFlags,
+    mut fill: F,
+) -> Option<()>
+where
+    A: FrameAllocator,
+    F: FnMut(VirtualAddress, &mut [u8]),
+{
+    assert!(
+        start >= USER_SPACE_START && start + size_in_pages * PAGE_SIZE <= USER_SPACE_END,
+        "{:#x} is not in user space",
+        start
+    );
+    let first = Page::containing_address(start);
+    for i in 0..size_in_pages {
+        let Some(frame) = allocator.allocate_frame() else {
+            for page in (0..i).map(|j| first + j) {
+                mapper.unmap(page, allocator);
+            }
+            return None;
+        };
+        let data = unsafe {
+            core::slice::from_raw_parts_mut(
+                phys_to_virt(frame.start_address()) as *mut u8,
+                PAGE_SIZE,
+            )
+        };
+        data.fill(0);
+        fill((first + i).start_address(), data);
+        mapper.map_to(
+            first + i,
+            frame,
+            flags | EntryFlags::USER_ACCESSIBLE,
+            allocator,
+        );
+    }
+    Some(())
+}
+
 pub struct MemoryController<'a> {
     active_table: paging::ActivePageTable,
     frame_allocator: area_frame_allocator::AreaFrameAllocator<'a>,
     stack_allocator: stack_allocator::StackAllocator,
+    kernel_space: AddressSpace,
+    temporary_page: TemporaryPage,
+}
+
+// 让slab缓存等直接从MemoryController申请物理帧
+impl<'a> FrameAllocator for MemoryController<'a> {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        self.frame_allocator.allocate_frame()
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.frame_allocator.deallocate_frame(frame)
+    }
+}
+
+#[derive(Debug)]
+pub enum PageFaultError {
+    NoRegion,
+    AccessViolation(RegionKind),
+    OutOfMemory,
+    // 写入只读的页，并且不是写时复制的页
+    WriteProtected,
 }
 
 impl<'a> MemoryController<'a> {
-    pub fn alloc_stack(&mut self, size_in_pages: usize) -> Option<Stack> {
+    pub fn kernel_space(&self) -> &AddressSpace {
+        &self.kernel_space
+    }
+
+    pub fn alloc_stack(&mut self, size_in_pages: usize, owner: &'static str) -> Option<Stack> {
         let &mut MemoryController {
             ref mut active_table,
             ref mut frame_allocator,
             ref mut stack_allocator,
+            ref mut kernel_space,
+            ..
+        } = self;
+        stack_allocator.alloc_stack(
+            kernel_space,
+            active_table,
+            frame_allocator,
+            size_in_pages,
+            owner,
+        )
+    }
+
+    // 只保留虚拟地址范围，栈页在第一次访问时才映射
+    pub fn alloc_lazy_stack(&mut self, size_in_pages: usize, owner: &'static str) -> Option<Stack> {
+        self.stack_allocator
+            .alloc_lazy_stack(&mut self.kernel_space, size_in_pages, owner)
+    }
+
+    // 栈先缓存起来复用，缓存满了才取消映射并释放地址范围，包括保护页
+    pub fn free_stack(&mut self, stack: Stack) {
+        if let Some(stack) = self.stack_allocator.recycle(stack) {
+            let guard_page = stack.guard_page();
+            self.unmap(guard_page, stack.top() - guard_page)
+                .expect("stack is not registered in the kernel space");
+        }
+    }
+
+    // 新建一个只包含内核映射的页表
+    pub fn new_page_table(&mut self) -> Option<InactivePageTable> {
+        let frame = self.frame_allocator.allocate_frame()?;
+        Some(InactivePageTable::new_with_kernel_mappings(
+            frame,
+            &mut self.active_table,
+            &mut self.temporary_page,
+        ))
+    }
+
+    // 临时把table作为递归映射的目标，在f中修改或遍历它
+    pub fn with_page_table<F>(&mut self, table: &mut InactivePageTable, f: F)
+    where
+        F: FnOnce(&mut Mapper, &mut AreaFrameAllocator<'a>),
+    {
+        let &mut MemoryController {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ..
         } = self;
-        stack_allocator.alloc_stack(active_table, frame_allocator, size_in_pages)
+        active_table.with(table, temporary_page, |mapper| f(mapper, frame_allocator));
+    }
+
+    // 输出一个不活动页表的所有映射
+    pub fn dump_page_table(&mut self, table: &mut InactivePageTable) {
+        self.with_page_table(table, |mapper, _| paging::dump::dump(mapper));
+    }
+
+    // 把一段物理地址映射到内核空间，返回对应的虚拟地址
+    pub fn map_mmio(
+        &mut self,
+        physical_address: PhysicalAddress,
+        size: usize,
+    ) -> Result<VirtualAddress, RegionError> {
+        let offset = physical_address % PAGE_SIZE;
+        let physical_start = physical_address - offset;
+        let flags = EntryFlags::WRITABLE
+            | EntryFlags::WRITE_THROUGH
+            | EntryFlags::NO_CACHE
+            | EntryFlags::NO_EXECUTE;
+
+        let start = self.kernel_space.allocate(
+            offset + size,
+            flags,
+            RegionKind::Mmio,
+            Backing::Mmio(physical_start),
+        )?;
+        let region = *self.kernel_space.find(start).unwrap();
+
+        for page in Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + offset + size - 1),
+        ) {
+            let frame =
+                Frame::containing_address(physical_start + (page.start_address() - region.start()));
+            self.active_table
+                .map_to(page, frame, flags, &mut self.frame_allocator);
+        }
+        Ok(start + offset)
+    }
+
+    pub fn map_anonymous(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: EntryFlags,
+    ) -> Result<(), RegionError> {
+        self.kernel_space.insert(Region::new(
+            start,
+            size,
+            flags,
+            RegionKind::Anonymous,
+            Backing::Demand,
+        ))
+    }
+
+    pub fn alloc_anonymous(
+        &mut self,
+        size: usize,
+        flags: EntryFlags,
+    ) -> Result<VirtualAddress, RegionError> {
+        self.kernel_space
+            .allocate(size, flags, RegionKind::Anonymous, Backing::Demand)
+    }
+
+    // 在当前页表的用户空间映射新分配并清零的页，物理帧不够时回滚并返回None
+    pub fn map_user_pages(
+        &mut self,
+        start: VirtualAddress,
+        size_in_pages: usize,
+        flags: EntryFlags,
+    ) -> Option<()> {
+        map_zeroed_user_pages(
+            &mut self.active_table,
+            &mut self.frame_allocator,
+            start,
+            size_in_pages,
+            flags,
+            |_, _| {},
+        )
+    }
+
+    // 在不活动的页表中映射用户页，fill通过直接映射初始化每一页清零后的内容，
+    // 参数是页的起始地址和页的内容
+    pub fn map_user_pages_in<F>(
+        &mut self,
+        table: &mut InactivePageTable,
+        start: VirtualAddress,
+        size_in_pages: usize,
+        flags: EntryFlags,
+        fill: F,
+    ) -> Option<()>
+    where
+        F: FnMut(VirtualAddress, &mut [u8]),
+    {
+        let mut result = None;
+        self.with_page_table(table, |mapper, allocator| {
+            result = map_zeroed_user_pages(mapper, allocator, start, size_in_pages, flags, fill);
+        });
+        result
+    }
+
+    // 让child共享当前页表用户空间中的所有页。可写的页在两边都改为只读并标记COPY_ON_WRITE，
+    // 写入时再复制；共享帧的记录满了时直接复制。
+    // 物理帧不够时返回None，已经映射的页留在child中，由调用者释放。
+    // 调用者持有MemoryController的锁，这时堆不能扩展，所以两次遍历页表而不申请堆内存
+    pub fn share_user_pages(&mut self, child: &mut InactivePageTable) -> Option<()> {
+        // 先去掉当前页表中共享页的写权限，child之后使用相同的标志
+        for (page, frame, flags) in user_mappings(self.active_table.direct_mappings()) {
+            let shared = SHARED_FRAMES.lock().share(&frame);
+            if shared && flags.contains(EntryFlags::WRITABLE) {
+                let flags = (flags - EntryFlags::WRITABLE) | EntryFlags::COPY_ON_WRITE;
+                self.active_table.update_flags(page, flags);
+            }
+        }
+
+        // 记录满了的帧没有被共享，数量仍然是1
+        let mut result = Some(());
+        let parent = self.active_table.direct_mappings();
+        self.with_page_table(child, |mapper, allocator| {
+            let mut pages = user_mappings(parent);
+            for (page, frame, flags) in pages.by_ref() {
+                let frame = if SHARED_FRAMES.lock().is_shared(&frame) {
+                    frame
+                } else if let Some(copy) = allocator.allocate_frame() {
+                    copy_frame(&frame, &copy);
+                    copy
+                } else {
+                    result = None;
+                    break;
+                };
+                mapper.map_to(page, frame, flags, allocator);
+            }
+            // 没有映射到child中的页不算共享
+            let mut shared_frames = SHARED_FRAMES.lock();
+            for (_, frame, _) in pages {
+                shared_frames.unshare(&frame);
+            }
+        });
+        result
+    }
+
+    // [start, start + size)中的页是否都已经映射并且用户态可以访问，writable时还要求可写
+    pub fn is_user_accessible(&self, start: VirtualAddress, size: usize, writable: bool) -> bool {
+        let Some(end) = start.checked_add(size) else {
+            return false;
+        };
+        if start < USER_SPACE_START || end > USER_SPACE_END {
+            return false;
+        }
+        if size == 0 {
+            return true;
+        }
+        // 写时复制的页在写入时由缺页处理复制
+        let writable_flags = EntryFlags::WRITABLE | EntryFlags::COPY_ON_WRITE;
+        Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(end - 1),
+        )
+        .all(|page| {
+            self.active_table.page_flags(page).is_some_and(|flags| {
+                flags.contains(EntryFlags::USER_ACCESSIBLE)
+                    && (!writable || flags.intersects(writable_flags))
+            })
+        })
+    }
+
+    // 用户空间中的页是否都没有映射
+    pub fn is_user_range_free(&self, start: VirtualAddress, size_in_pages: usize) -> bool {
+        let first = Page::containing_address(start);
+        (0..size_in_pages).all(|i| self.active_table.translate_page(first + i).is_none())
+    }
+
+    // 从from开始找size_in_pages个连续的没有映射的用户页，返回起始地址。
+    // 遇到已经映射的页时从它的下一页重新开始，不会重复检查
+    pub fn find_free_user_range(
+        &self,
+        from: VirtualAddress,
+        size_in_pages: usize,
+    ) -> Option<VirtualAddress> {
+        let mut start = Page::containing_address(from.max(USER_SPACE_START)).start_address();
+        let mut free = 0;
+        while free < size_in_pages {
+            let address = start + free * PAGE_SIZE;
+            if address >= USER_SPACE_END {
+                return None;
+            }
+            if self
+                .active_table
+                .translate_page(Page::containing_address(address))
+                .is_some()
+            {
+                start = address + PAGE_SIZE;
+                free = 0;
+            } else {
+                free += 1;
+            }
+        }
+        Some(start)
+    }
+
+    // 取消映射用户空间的页并释放物理帧，没有映射的页跳过，共享的帧只减少共享数量
+    pub fn unmap_user_pages(&mut self, start: VirtualAddress, size_in_pages: usize) {
+        let first = Page::containing_address(start);
+        for i in 0..size_in_pages {
+            if self.active_table.translate_page(first + i).is_some() {
+                let frame = self.active_table.unmap_frame(first + i);
+                if !SHARED_FRAMES.lock().unshare(&frame) {
+                    self.frame_allocator.deallocate_frame(frame);
+                }
+            }
+        }
+    }
+
+    // 取消映射一段范围内已经映射的页，并从地址空间中移除
+    pub fn unmap(&mut self, start: VirtualAddress, size: usize) -> Result<(), RegionError> {
+        for page in Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + size - 1),
+        ) {
+            let backing = match self.kernel_space.find(page.start_address()) {
+                Some(region) => region.backing(),
+                None => continue,
+            };
+            if self.active_table.translate_page(page).is_none() {
+                continue;
+            }
+
+            match backing {
+                Backing::Mmio(_) => {
+                    self.active_table.unmap_frame(page);
+                }
+                _ => self.active_table.unmap(page, &mut self.frame_allocator),
+            }
+        }
+
+        self.kernel_space.remove(start, size)
+    }
+
+    // 为堆分配一段新的内存并立即映射，帧不够时回滚并返回None。
+    // 调用时持有堆分配器的锁，这里不能再申请堆内存
+    pub fn grow_heap(&mut self, size: usize) -> Option<VirtualAddress> {
+        let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+        let start = self
+            .kernel_space
+            .allocate(size, flags, RegionKind::Heap, Backing::Eager)
+            .ok()?;
+
+        for page in Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + size - 1),
+        ) {
+            match self.frame_allocator.allocate_frame() {
+                Some(frame) => {
+                    self.active_table
+                        .map_to(page, frame, flags, &mut self.frame_allocator)
+                }
+                None => {
+                    self.unmap(start, size).unwrap();
+                    return None;
+                }
+            }
+        }
+        Some(start)
+    }
+
+    // 分配前后各有一个保护页的堆内存，越界访问会立即触发缺页
+    pub fn alloc_guarded(&mut self, size_in_pages: usize) -> Option<VirtualAddress> {
+        let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+        let size = size_in_pages * PAGE_SIZE;
+        let guard_below = self
+            .kernel_space
+            .find_free_gap(size + 2 * PAGE_SIZE, PAGE_SIZE)?;
+        let start = guard_below + PAGE_SIZE;
+        let regions = [
+            Region::new(
+                guard_below,
+                PAGE_SIZE,
+                EntryFlags::empty(),
+                RegionKind::Heap,
+                Backing::Guard,
+            ),
+            Region::new(start, size, flags, RegionKind::Heap, Backing::Eager),
+            Region::new(
+                start + size,
+                PAGE_SIZE,
+                EntryFlags::empty(),
+                RegionKind::Heap,
+                Backing::Guard,
+            ),
+        ];
+        for region in regions {
+            if self.kernel_space.insert(region).is_err() {
+                self.free_guarded(start, size_in_pages);
+                return None;
+            }
+        }
+
+        for page in Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + size - 1),
+        ) {
+            match self.frame_allocator.allocate_frame() {
+                Some(frame) => {
+                    self.active_table
+                        .map_to(page, frame, flags, &mut self.frame_allocator)
+                }
+                None => {
+                    self.free_guarded(start, size_in_pages);
+                    return None;
+                }
+            }
+        }
+        Some(start)
+    }
+
+    pub fn free_guarded(&mut self, start: VirtualAddress, size_in_pages: usize) {
+        // 保护页没有映射，unmap会跳过它们
+        let _ = self.unmap(start - PAGE_SIZE, (size_in_pages + 2) * PAGE_SIZE);
+    }
+
+    // 把完全空闲的堆内存还给帧分配器
+    pub fn shrink_heap(&mut self, start: VirtualAddress, size: usize) {
+        self.unmap(start, size)
+            .expect("heap memory is not registered in the kernel space");
+    }
+
+    pub fn handle_page_fault(
+        &mut self,
+        address: VirtualAddress,
+        caused_by_write: bool,
+        instruction_fetch: bool,
+    ) -> Result<(), PageFaultError> {
+        let region = *self
+            .kernel_space
+            .find(address)
+            .ok_or(PageFaultError::NoRegion)?;
+
+        let flags = region.flags();
+        if region.backing() != Backing::Demand
+            || (caused_by_write && !flags.contains(EntryFlags::WRITABLE))
+            || (instruction_fetch && flags.contains(EntryFlags::NO_EXECUTE))
+        {
+            return Err(PageFaultError::AccessViolation(region.kind()));
+        }
+
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(PageFaultError::OutOfMemory)?;
+
+        // 先通过直接映射把帧清零，再映射到缺页的地址
+        unsafe {
+            core::ptr::write_bytes(phys_to_virt(frame.start_address()) as *mut u8, 0, PAGE_SIZE);
+        }
+
+        let page = Page::containing_address(address);
+        self.active_table
+            .map_to(page, frame, flags, &mut self.frame_allocator);
+        Ok(())
+    }
+
+    // 写入写时复制的页时调用。帧还被其他页表共享时复制一份，否则直接恢复写权限
+    pub fn handle_copy_on_write(&mut self, address: VirtualAddress) -> Result<(), PageFaultError> {
+        let page = Page::containing_address(address);
+        let flags = self
+            .active_table
+            .page_flags(page)
+            .filter(|flags| flags.contains(EntryFlags::COPY_ON_WRITE))
+            .ok_or(PageFaultError::WriteProtected)?;
+        let flags = (flags - EntryFlags::COPY_ON_WRITE - EntryFlags::ACCESSED - EntryFlags::DIRTY)
+            | EntryFlags::WRITABLE;
+        let frame = self.active_table.translate_page(page).unwrap();
+        if !SHARED_FRAMES.lock().is_shared(&frame) {
+            self.active_table.update_flags(page, flags);
+            return Ok(());
+        }
+
+        let copy = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(PageFaultError::OutOfMemory)?;
+        copy_frame(&frame, &copy);
+        self.active_table.unmap_frame(page);
+        SHARED_FRAMES.lock().unshare(&frame);
+        self.active_table
+            .map_to(page, copy, flags, &mut self.frame_allocator);
+        Ok(())
+    }
+}
+
+// 用户空间中用户态可以访问的页，标志去掉ACCESSED和DIRTY
+fn user_mappings(mappings: Mappings<'_>) -> impl Iterator<Item = (Page, Frame, EntryFlags)> + '_ {
+    mappings
+        .take_while(|(page, _, _)| page.start_address() < USER_SPACE_END)
+        .filter(|(_, _, flags)| flags.contains(EntryFlags::USER_ACCESSIBLE))
+        .map(|(page, frame, flags)| {
+            (
+                page,
+                frame,
+                flags - (EntryFlags::ACCESSED | EntryFlags::DIRTY),
+            )
+        })
+}
+
+// 通过直接映射复制整个帧的内容
+fn copy_frame(source: &Frame, destination: &Frame) {
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(source.start_address()) as *const u8,
+            phys_to_virt(destination.start_address()) as *mut u8,
+            PAGE_SIZE,
+        );
     }
 }
@@ -0,0 +1,153 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    memory::{
+        PAGE_SIZE,
+        paging::{
+            ActivePageTable, ENTRY_COUNT, EntryFlags, PhysicalAddress, VirtualAddress,
+            mapper::Mapper,
+        },
+    },
+    multiboot_info, serial_println,
+};
+
+// 这个命令行参数打开启动时和panic时的页表输出
+pub const DUMP_OPTION: &str = "dump_page_tables";
+
+// 合并时忽略由CPU设置的标志
+const IGNORED_FLAGS: EntryFlags = EntryFlags::ACCESSED.union(EntryFlags::DIRTY);
+
+// 一段连续的映射：虚拟地址和物理地址都连续，页大小和标志都相同
+struct MappedRange {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    physical_start: PhysicalAddress,
+    page_size: usize,
+    flags: EntryFlags,
+}
+
+impl MappedRange {
+    fn continues_with(
+        &self,
+        start: VirtualAddress,
+        physical_start: PhysicalAddress,
+        page_size: usize,
+        flags: EntryFlags,
+    ) -> bool {
+        start == self.end
+            && physical_start == self.physical_start + (self.end - self.start)
+            && page_size == self.page_size
+            && flags == self.flags
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = if self.page_size == PAGE_SIZE {
+            "4K"
+        } else {
+            "2M"
+        };
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x} {} x{:<6} {}",
+            self.start,
+            self.end - 1,
+            self.physical_start,
+            size,
+            (self.end - self.start) / self.page_size,
+            FlagsDisplay(self.flags)
+        )
+    }
+}
+
+// 固定宽度的标志：可写、用户、可执行、全局、禁用缓存、直写
+struct FlagsDisplay(EntryFlags);
+
+impl fmt::Display for FlagsDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = self.0;
+        let columns = [
+            (flags.contains(EntryFlags::WRITABLE), 'W'),
+            (flags.contains(EntryFlags::USER_ACCESSIBLE), 'U'),
+            (!flags.contains(EntryFlags::NO_EXECUTE), 'X'),
+            (flags.contains(EntryFlags::GLOBAL), 'G'),
+            (flags.contains(EntryFlags::NO_CACHE), 'C'),
+            (flags.contains(EntryFlags::WRITE_THROUGH), 'T'),
+        ];
+        for (set, c) in columns {
+            write!(f, "{}", if set { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+// 输出mapper当前递归映射的P4，不使用堆，panic时也可以调用
+pub fn dump(mapper: &Mapper) {
+    serial_println!(
+        "{:<41}{:<15}{:<11}{}",
+        "virtual range",
+        "physical",
+        "pages",
+        "flags"
+    );
+
+    let mut current: Option<MappedRange> = None;
+    let mut ranges = 0;
+    let mut mapped = 0;
+    for (page, frame, flags) in mapper.mappings() {
+        let page_size = if flags.contains(EntryFlags::HUGE_PAGE) {
+            PAGE_SIZE * ENTRY_COUNT
+        } else {
+            PAGE_SIZE
+        };
+        let flags = flags - IGNORED_FLAGS - EntryFlags::HUGE_PAGE;
+        let start = page.start_address();
+        let physical_start = frame.start_address();
+        mapped += page_size;
+
+        match current {
+            Some(ref mut range)
+                if range.continues_with(start, physical_start, page_size, flags) =>
+            {
+                range.end += page_size;
+            }
+            _ => {
+                if let Some(range) = current.take() {
+                    serial_println!("{}", range);
+                    ranges += 1;
+                }
+                current = Some(MappedRange {
+                    start,
+                    end: start + page_size,
+                    physical_start,
+                    page_size,
+                    flags,
+                });
+            }
+        }
+    }
+    if let Some(range) = current {
+        serial_println!("{}", range);
+        ranges += 1;
+    }
+    serial_println!("{} ranges, {} KiB mapped", ranges, mapped / 1024);
+}
+
+pub fn dump_active() {
+    dump(&ActivePageTable::new());
+}
+
+// panic时按命令行参数决定是否输出，输出过程中再次panic时不会重复输出
+pub fn dump_on_panic() {
+    static DUMPING: AtomicBool = AtomicBool::new(false);
+
+    let enabled = multiboot_info::boot_info().is_some_and(|info| info.has_boot_option(DUMP_OPTION));
+    if enabled && !DUMPING.swap(true, Ordering::SeqCst) {
+        serial_println!("page tables at panic:");
+        dump_active();
+    }
+}
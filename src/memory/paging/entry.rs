@@ -32,16 +32,43 @@ impl Entry {
         self.0 = (frame.start_address() as u64) | flags.bits();
     }
 
+    pub fn insert_flags(&mut self, flags: EntryFlags) {
+        self.0 |= flags.bits();
+    }
+
 
     pub fn from_elf_section_flags(section:&Elf64SectionHeader)-> EntryFlags{
+        let section_flags = section.flags();
+        let mut flags = Self::from_elf_permissions(
+            section_flags.contains(SectionFlags::SHF_ALLOC),
+            section_flags.contains(SectionFlags::SHF_WRITE),
+            section_flags.contains(SectionFlags::SHF_EXECINSTR),
+        );
+        // 高半部分的内核映射在所有地址空间中共享
+        if section.start_address() >= KERNEL_OFFSET {
+            flags |= EntryFlags::GLOBAL;
+        }
+        flags
+    }
+
+    // 用户程序的PT_LOAD段，段总是可读的
+    pub fn from_elf_segment_flags(segment_flags: SegmentFlags) -> EntryFlags {
+        Self::from_elf_permissions(
+            true,
+            segment_flags.contains(SegmentFlags::PF_W),
+            segment_flags.contains(SegmentFlags::PF_X),
+        ) | EntryFlags::USER_ACCESSIBLE
+    }
+
+    fn from_elf_permissions(allocated: bool, writable: bool, executable: bool) -> EntryFlags {
         let mut flags = EntryFlags::empty();
-        if section.flags().contains(SectionFlags::SHF_ALLOC){
+        if allocated {
             flags |= EntryFlags::PRESENT;
         }
-        if section.flags().contains(SectionFlags::SHF_WRITE){
+        if writable {
             flags |= EntryFlags::WRITABLE;
         }
-        if !section.flags().contains(SectionFlags::SHF_EXECINSTR){
+        if !executable {
             flags |= EntryFlags::NO_EXECUTE;
         }
         flags
@@ -49,7 +76,11 @@ impl Entry {
 }
 use bitflags::bitflags;
 
-use crate::{memory::Frame, multiboot_info::{Elf64SectionHeader, SectionFlags}};
+use crate::{
+    elf::SegmentFlags,
+    memory::{Frame, paging::KERNEL_OFFSET},
+    multiboot_info::{Elf64SectionHeader, SectionFlags},
+};
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
     pub struct EntryFlags: u64 {
@@ -62,6 +93,8 @@ bitflags! {
         const DIRTY =           1 << 6;
         const HUGE_PAGE =       1 << 7;
         const GLOBAL =          1 << 8;
+        // 9-11位由操作系统使用。写时复制的页去掉了WRITABLE，写入时复制帧
+        const COPY_ON_WRITE =   1 << 9;
         const NO_EXECUTE =      1 << 63;
     }
 }
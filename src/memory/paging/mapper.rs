@@ -1,19 +1,22 @@
-
 use crate::{
     memory::{
         Frame, FrameAllocator, PAGE_SIZE,
         paging::{
-            ENTRY_COUNT, EntryFlags, Page, PhysicalAddress, VirtualAddress, entry,
+            ENTRY_COUNT, EntryFlags, KERNEL_P4_START, Page, PhysicalAddress, VirtualAddress, entry,
             table::{self, Level4, Table},
+            walker::Mappings,
         },
     },
-    utils::x86_64_control::tlb,
+    utils::x86_64_control::{self, tlb},
 };
 
 pub struct Mapper {
     p4: *mut Table<Level4>,
 }
 
+// 手动实现 Send trait，P4始终通过递归映射访问
+unsafe impl Send for Mapper {}
+
 impl Mapper {
     pub fn new() -> Mapper {
         Mapper { p4: table::P4 }
@@ -27,6 +30,11 @@ impl Mapper {
         unsafe { self.p4.as_mut().unwrap() }
     }
 
+    // 遍历当前通过递归映射访问的P4中所有已映射的页
+    pub fn mappings(&self) -> Mappings<'_> {
+        Mappings::new(self.p4())
+    }
+
     pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
         let offset = virtual_address % PAGE_SIZE;
         self.translate_page(Page::containing_address(virtual_address))
@@ -73,19 +81,52 @@ impl Mapper {
             .or_else(huge_page)
     }
 
+    // 映射page的4KiB页表项的标志，没有映射或者在大页中时返回None
+    pub fn page_flags(&self, page: Page) -> Option<EntryFlags> {
+        let entry = &self
+            .p4()
+            .next_table(page.p4_index())?
+            .next_table(page.p3_index())?
+            .next_table(page.p2_index())?[page.p1_index()];
+        entry.pointed_frame().map(|_| entry.flags())
+    }
+
     pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
     where
         A: FrameAllocator,
     {
+        let table_flags = flags & EntryFlags::USER_ACCESSIBLE;
         let p4 = self.p4_mut();
-        let p3 = p4.next_table_create(page.p4_index(), allocator);
+        let p3 = p4.next_table_create_with(page.p4_index(), table_flags, allocator);
 
-        let p2 = p3.next_table_create(page.p3_index(), allocator);
-        let p1 = p2.next_table_create(page.p2_index(), allocator);
+        let p2 = p3.next_table_create_with(page.p3_index(), table_flags, allocator);
+        let p1 = p2.next_table_create_with(page.p2_index(), table_flags, allocator);
         assert!(p1[page.p1_index()].is_unused());
         p1[page.p1_index()].set(frame, flags | entry::EntryFlags::PRESENT);
     }
 
+    pub fn map_to_huge<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        // 2MiB页要求虚拟页和物理帧都按2MiB对齐
+        assert!(page.p1_index() == 0, "huge page must be 2MiB aligned");
+        assert!(
+            frame.number.is_multiple_of(ENTRY_COUNT),
+            "huge frame must be 2MiB aligned"
+        );
+
+        let table_flags = flags & EntryFlags::USER_ACCESSIBLE;
+        let p4 = self.p4_mut();
+        let p3 = p4.next_table_create_with(page.p4_index(), table_flags, allocator);
+        let p2 = p3.next_table_create_with(page.p3_index(), table_flags, allocator);
+        assert!(p2[page.p2_index()].is_unused());
+        p2[page.p2_index()].set(
+            frame,
+            flags | entry::EntryFlags::PRESENT | entry::EntryFlags::HUGE_PAGE,
+        );
+    }
+
     pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A)
     where
         A: FrameAllocator,
@@ -106,6 +147,27 @@ impl Mapper {
     where
         A: FrameAllocator,
     {
+        let frame = self.unmap_frame(page);
+        allocator.deallocate_frame(frame);
+    }
+
+    // 修改已经映射的4KiB页的标志，帧不变
+    pub fn update_flags(&mut self, page: Page, flags: EntryFlags) {
+        let p1 = self
+            .p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            .expect("mapping code does not support huge pages");
+        let frame = p1[page.p1_index()]
+            .pointed_frame()
+            .expect("page is not mapped");
+        p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+        tlb::tlb_flush(page.start_address() as u64);
+    }
+
+    // 取消映射但不释放物理帧，用于MMIO等不属于帧分配器的帧
+    pub fn unmap_frame(&mut self, page: Page) -> Frame {
         assert!(self.translate(page.start_address()).is_some());
 
         let p1 = self
@@ -117,11 +179,14 @@ impl Mapper {
         let frame = p1[page.p1_index()].pointed_frame().unwrap();
         p1[page.p1_index()].set_unused();
 
-        // 清理TLB缓存
+        // 清理TLB缓存，内核部分在所有页表中共享，其他PCID中也可能有它的TLB项
         tlb::tlb_flush(page.start_address() as u64);
+        if page.p4_index() >= KERNEL_P4_START && x86_64_control::pcid_enabled() {
+            tlb::tlb_flush_all_contexts();
+        }
 
         // TODO free p(1,2,3) table if empty
 
-        allocator.deallocate_frame(frame);
+        frame
     }
 }
@@ -1,30 +1,71 @@
+pub mod dump;
 pub mod entry;
 pub mod mapper;
+pub mod pcid;
 pub mod table;
 pub mod temporary_page;
+pub mod walker;
 
 use core::ops::{Add, Deref, DerefMut};
 
 pub use self::entry::*;
 use crate::{
     memory::{
-        Frame, FrameAllocator, PAGE_SIZE,
+        Frame, FrameAllocator, MEMORY_CONTROLLER, PAGE_SIZE,
         paging::{
             mapper::Mapper,
-            table::{Level4, Table},
+            pcid::{KERNEL_PCID, alloc_pcid, free_pcid},
+            table::{Level1, Level4, Table},
             temporary_page::TemporaryPage,
+            walker::Mappings,
         },
+        shared_frames::SHARED_FRAMES,
+        stack_allocator,
     },
-    multiboot_info::MultibootInfo,
+    multiboot_info::{MemoryMapEntryType, MultibootInfo},
     println,
     utils::x86_64_control::{cr3, tlb},
 };
 
 const ENTRY_COUNT: usize = 512;
 
+// P4的第511项被内核镜像占用，递归映射改用第510项
+pub const RECURSIVE_INDEX: usize = 510;
+
+// P4中从这一项开始是内核的高半部分，在所有页表中共享
+pub const KERNEL_P4_START: usize = ENTRY_COUNT / 2;
+
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
+// 内核链接在最高的2GiB，物理地址 = 虚拟地址 - KERNEL_OFFSET
+pub const KERNEL_OFFSET: VirtualAddress = 0xffff_ffff_8000_0000;
+
+// 所有可用物理内存都映射到内核高半部分的这个偏移处
+pub const PHYS_MEM_OFFSET: VirtualAddress = 0xffff_8000_0000_0000;
+pub const PHYS_MEM_MAX: usize = 0x0000_4000_0000_0000; // 64 TiB
+
+pub fn phys_to_virt(address: PhysicalAddress) -> VirtualAddress {
+    assert!(
+        address < PHYS_MEM_MAX,
+        "physical address out of direct map: 0x{:x}",
+        address
+    );
+    address + PHYS_MEM_OFFSET
+}
+
+pub fn virt_to_phys(address: VirtualAddress) -> PhysicalAddress {
+    if address >= KERNEL_OFFSET {
+        return address - KERNEL_OFFSET;
+    }
+    assert!(
+        address >= PHYS_MEM_OFFSET && address - PHYS_MEM_OFFSET < PHYS_MEM_MAX,
+        "virtual address not in direct map: 0x{:x}",
+        address
+    );
+    address - PHYS_MEM_OFFSET
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Page {
     number: usize,
@@ -111,8 +152,14 @@ impl Iterator for PageIter {
     }
 }
 
+// CR3中PCID所在的低12位
+const CR3_PCID_MASK: u64 = 0xfff;
+// 写CR3时保留新PCID的TLB项
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
 pub struct InactivePageTable {
     p4_frame: Frame,
+    pcid: u16,
 }
 
 impl InactivePageTable {
@@ -124,10 +171,154 @@ impl InactivePageTable {
         {
             let table = temporary_page.map_table_frame(frame.clone(), active_table);
             table.zero();
-            table[511].set(frame.clone(), EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            table[RECURSIVE_INDEX].set(frame.clone(), EntryFlags::PRESENT | EntryFlags::WRITABLE);
+        }
+        temporary_page.unmap(active_table);
+        InactivePageTable {
+            p4_frame: frame,
+            pcid: alloc_pcid(),
+        }
+    }
+
+    // 复制当前页表的内核部分，内核的P3表在启动时已经全部创建，之后的内核映射对所有页表可见
+    pub fn new_with_kernel_mappings(
+        frame: Frame,
+        active_table: &mut ActivePageTable,
+        temporary_page: &mut TemporaryPage,
+    ) -> InactivePageTable {
+        {
+            let table = temporary_page.map_table_frame(frame.clone(), active_table);
+            table.zero();
+            for index in KERNEL_P4_START..ENTRY_COUNT {
+                if index == RECURSIVE_INDEX {
+                    continue;
+                }
+                let entry = &active_table.p4()[index];
+                if let Some(p3_frame) = entry.pointed_frame() {
+                    table[index].set(p3_frame, entry.flags());
+                }
+            }
+            table[RECURSIVE_INDEX].set(frame.clone(), EntryFlags::PRESENT | EntryFlags::WRITABLE);
         }
         temporary_page.unmap(active_table);
-        InactivePageTable { p4_frame: frame }
+        InactivePageTable {
+            p4_frame: frame,
+            pcid: alloc_pcid(),
+        }
+    }
+
+    pub fn p4_frame(&self) -> &Frame {
+        &self.p4_frame
+    }
+
+    pub fn pcid(&self) -> u16 {
+        self.pcid
+    }
+
+    // 有自己的PCID时切换过去不需要刷新TLB
+    pub fn cr3_value(&self) -> u64 {
+        let value = self.p4_frame.start_address() as u64 | self.pcid as u64;
+        if self.pcid == KERNEL_PCID {
+            value
+        } else {
+            value | CR3_NO_FLUSH
+        }
+    }
+
+    // 页表通过直接映射访问，不需要切换递归映射
+    fn p4_mut(&mut self) -> &mut Table<Level4> {
+        unsafe { &mut *(phys_to_virt(self.p4_frame.start_address()) as *mut Table<Level4>) }
+    }
+
+    // 释放用户部分的P3/P2/P1表和P4本身，映射的帧由各自的区域负责释放
+    fn free_tables<A>(&mut self, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let p4 = self.p4_mut();
+        for p4_index in 0..KERNEL_P4_START {
+            let Some(p3) = p4.next_table_direct(p4_index) else {
+                continue;
+            };
+            for p3_index in 0..ENTRY_COUNT {
+                let Some(p2) = p3.next_table_direct(p3_index) else {
+                    continue;
+                };
+                for p2_index in 0..ENTRY_COUNT {
+                    if let Some(p1_frame) = p2.next_table_frame(p2_index) {
+                        allocator.deallocate_frame(p1_frame);
+                    }
+                }
+                allocator.deallocate_frame(p3.next_table_frame(p3_index).unwrap());
+            }
+            allocator.deallocate_frame(p4.next_table_frame(p4_index).unwrap());
+        }
+        allocator.deallocate_frame(self.p4_frame.clone());
+    }
+
+    // 释放用户空间中映射的所有帧，页表本身在丢弃时释放。
+    // 还被其他页表共享的帧只减少共享的数量，
+    // table不能是活动页表，否则TLB中还有这些帧的映射
+    pub fn free_user_frames<A>(&mut self, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let p4 = self.p4_mut();
+        for p4_index in 0..KERNEL_P4_START {
+            let Some(p3) = p4.next_table_direct_mut(p4_index) else {
+                continue;
+            };
+            for p3_index in 0..ENTRY_COUNT {
+                let Some(p2) = p3.next_table_direct_mut(p3_index) else {
+                    continue;
+                };
+                for p2_index in 0..ENTRY_COUNT {
+                    if let Some(p1) = p2.next_table_direct_mut(p2_index) {
+                        free_mapped_frames(p1, allocator);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 释放P1表中映射的帧，表项清空
+fn free_mapped_frames<A>(p1: &mut Table<Level1>, allocator: &mut A)
+where
+    A: FrameAllocator,
+{
+    for index in 0..ENTRY_COUNT {
+        let entry = &mut p1[index];
+        let Some(frame) = entry.pointed_frame() else {
+            continue;
+        };
+        if !SHARED_FRAMES.lock().unshare(&frame) {
+            allocator.deallocate_frame(frame);
+        }
+        entry.set_unused();
+    }
+}
+
+// 当前CR3的值，包括PCID
+pub fn active_cr3() -> u64 {
+    cr3::read_cr3()
+}
+
+// 切换任务时加载它的页表，已经是当前页表时不写CR3
+pub fn load_cr3(value: u64) {
+    if cr3::read_cr3() != value & !CR3_NO_FLUSH {
+        cr3::write_cr3(value);
+    }
+}
+
+// 丢弃时锁住MEMORY_CONTROLLER，所以不能在持有它的锁时丢弃页表。
+// 启动时的页表在MemoryController初始化之前就不再使用了，不会经过这里
+impl Drop for InactivePageTable {
+    fn drop(&mut self) {
+        if let Some(controller) = MEMORY_CONTROLLER.get() {
+            self.free_tables(&mut *controller.lock());
+        }
+        free_pcid(self.pcid);
     }
 }
 
@@ -164,6 +355,12 @@ impl ActivePageTable {
         self.mapper.p4_mut()
     }
 
+    // 通过直接映射遍历活动页表，with临时改变递归映射时也能使用
+    pub fn direct_mappings(&self) -> Mappings<'static> {
+        let p4_frame = Frame::containing_address(cr3::read_cr3() as usize);
+        Mappings::new(unsafe { &*(phys_to_virt(p4_frame.start_address()) as *const Table<Level4>) })
+    }
+
     pub fn with<F>(
         &mut self,
         table: &mut InactivePageTable,
@@ -177,7 +374,7 @@ impl ActivePageTable {
 
             let p4_table = temporary_page.map_table_frame(backup.clone(), self);
 
-            self.p4_mut()[511].set(
+            self.p4_mut()[RECURSIVE_INDEX].set(
                 table.p4_frame.clone(),
                 EntryFlags::PRESENT | EntryFlags::WRITABLE,
             );
@@ -185,18 +382,27 @@ impl ActivePageTable {
 
             f(self);
 
-            p4_table[511].set(backup, EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            p4_table[RECURSIVE_INDEX].set(backup, EntryFlags::PRESENT | EntryFlags::WRITABLE);
             tlb::tlb_flush_all();
+
+            // 修改时只刷新了当前PCID，切换到table时还可能用到它自己的旧TLB项
+            if table.pcid != KERNEL_PCID {
+                tlb::tlb_flush_pcid(table.pcid);
+            }
         }
 
         temporary_page.unmap(self);
     }
 
     pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
+        let value = cr3::read_cr3();
         let old_table = InactivePageTable {
-            p4_frame: Frame::containing_address(cr3::read_cr3() as usize),
+            p4_frame: Frame::containing_address(value as usize),
+            pcid: (value & CR3_PCID_MASK) as u16,
         };
-        cr3::write_cr3(new_table.p4_frame.start_address() as u64);
+        cr3::write_cr3(new_table.cr3_value());
+        // 新的页表已经是活动页表了，不能在这里释放
+        core::mem::forget(new_table);
         old_table
     }
 }
@@ -205,7 +411,11 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &MultibootInfo) -> Acti
 where
     A: FrameAllocator,
 {
-    let mut temporary_page = TemporaryPage::new(Page { number: 0xcafebabe }, allocator);
+    // 临时页放在内核镜像下方，低半部分留给用户地址空间
+    let mut temporary_page = TemporaryPage::new(
+        Page::containing_address(KERNEL_OFFSET - PAGE_SIZE),
+        allocator,
+    );
 
     let mut active_table = ActivePageTable::new();
 
@@ -215,6 +425,13 @@ where
     };
 
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
+        // 预先创建内核部分所有的P3表，新的页表复制P4的高半部分就能共享内核映射
+        for index in KERNEL_P4_START..ENTRY_COUNT {
+            if index != RECURSIVE_INDEX {
+                mapper.p4_mut().next_table_create(index, allocator);
+            }
+        }
+
         let elf_sections = boot_info.get_elf_sections();
 
         for section in elf_sections.iter().filter(|section| section.size() > 0) {
@@ -222,6 +439,11 @@ where
                 continue;
             }
 
+            // 启动代码只在进入高半部分之前使用，不再映射
+            if section.start_address() < KERNEL_OFFSET {
+                continue;
+            }
+
             assert!(
                 section.start_address() % PAGE_SIZE == 0,
                 "sections need to be page aligned"
@@ -230,27 +452,31 @@ where
             #[cfg(feature = "use_test")]
             println!(
                 "mapping section at addr: {:#x}, size: {:#x}",
-                section.sh_addr, section.sh_size
+                section.start_address(),
+                section.size()
             );
 
             let flags = Entry::from_elf_section_flags(section);
 
-            let start_frame = Frame::containing_address(section.start_address());
-            let end_frame = Frame::containing_address(section.end_address() - 1);
-            for frame in Frame::range_inclusive(start_frame, end_frame) {
-                mapper.identity_map(frame, flags, allocator);
+            let start_page = Page::containing_address(section.start_address());
+            let end_page = Page::containing_address(section.end_address() - 1);
+            for page in Page::range_inclusive(start_page, end_page) {
+                let frame = Frame::containing_address(virt_to_phys(page.start_address()));
+                mapper.map_to(page, frame, flags, allocator);
             }
         }
 
-        // identity map the VGA text buffer
-        let vga_buffer_frame = Frame::containing_address(0xb8000); // new
-        mapper.identity_map(vga_buffer_frame, EntryFlags::WRITABLE, allocator); // new
+        map_physical_memory(mapper, boot_info, allocator);
 
-        // identity map the multiboot info structure
-        let multiboot_start = Frame::containing_address(boot_info.start_address());
-        let multiboot_end = Frame::containing_address(boot_info.end_address() - 1);
+        // map the VGA text buffer into the direct map
+        let vga_buffer_frame = Frame::containing_address(0xb8000);
+        direct_map_frame(mapper, vga_buffer_frame, EntryFlags::WRITABLE, allocator);
+
+        // map the multiboot info structure into the direct map
+        let multiboot_start = Frame::containing_address(virt_to_phys(boot_info.start_address()));
+        let multiboot_end = Frame::containing_address(virt_to_phys(boot_info.end_address() - 1));
         for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
-            mapper.identity_map(frame, EntryFlags::PRESENT, allocator);
+            direct_map_frame(mapper, frame, EntryFlags::PRESENT, allocator);
         }
     });
 
@@ -259,11 +485,69 @@ where
     #[cfg(feature = "use_test")]
     println!("NEW TABLE!!!");
 
-    let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
+    // 启动时的P4表位于内核的.bss段中
+    let old_p4_page = Page::containing_address(old_table.p4_frame.start_address() + KERNEL_OFFSET);
+    // 它和下面的页表都在内核镜像中，不能还给帧分配器
+    core::mem::forget(old_table);
     active_table.unmap(old_p4_page, allocator);
+    // 它下面是启动时的栈，溢出时先经过旧的页表，再碰到这个保护页
+    stack_allocator::register_guard_page(old_p4_page.start_address(), "boot")
+        .expect("too many stacks");
 
     #[cfg(feature = "use_test")]
     println!("guard page at {:#x}", old_p4_page.start_address());
 
     active_table
 }
+
+fn map_physical_memory<A>(mapper: &mut Mapper, boot_info: &MultibootInfo, allocator: &mut A)
+where
+    A: FrameAllocator,
+{
+    let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+
+    let areas = boot_info
+        .get_memory_entries()
+        .iter()
+        .filter(|area| area.entry_type == MemoryMapEntryType::Available as u32)
+        // 长度为0的区域没有需要映射的帧
+        .filter(|area| area.length > 0);
+
+    for area in areas {
+        let start_frame = Frame::containing_address(area.base_addr as usize);
+        let end_frame = Frame::containing_address((area.base_addr + area.length - 1) as usize);
+
+        #[cfg(feature = "use_test")]
+        println!(
+            "direct mapping area at addr: {:#x}, size: {:#x}",
+            area.base_addr, area.length
+        );
+
+        // 能用2MiB大页的地方尽量用大页，其余部分用4KiB页
+        let mut frame = start_frame;
+        while frame <= end_frame {
+            let page = Page::containing_address(phys_to_virt(frame.start_address()));
+            let step = if frame.number.is_multiple_of(ENTRY_COUNT)
+                && frame.number + ENTRY_COUNT - 1 <= end_frame.number
+            {
+                mapper.map_to_huge(page, frame.clone(), flags, allocator);
+                ENTRY_COUNT
+            } else {
+                mapper.map_to(page, frame.clone(), flags, allocator);
+                1
+            };
+            frame.number += step;
+        }
+    }
+}
+
+fn direct_map_frame<A>(mapper: &mut Mapper, frame: Frame, flags: EntryFlags, allocator: &mut A)
+where
+    A: FrameAllocator,
+{
+    // 可用内存区域已经在直接映射中了，这里只补充其余的帧
+    let page = Page::containing_address(phys_to_virt(frame.start_address()));
+    if mapper.translate_page(page).is_none() {
+        mapper.map_to(page, frame, flags, allocator);
+    }
+}
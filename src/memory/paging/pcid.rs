@@ -0,0 +1,41 @@
+use spin::Mutex;
+
+use crate::utils::x86_64_control::{self, tlb};
+
+// PCID有12位，0留给内核页表和分配不到PCID的页表
+pub const PCID_COUNT: usize = 4096;
+pub const KERNEL_PCID: u16 = 0;
+
+// 每一位表示一个PCID是否已经分配
+static PCIDS: Mutex<[u64; PCID_COUNT / 64]> = Mutex::new(reserved_kernel_pcid());
+
+const fn reserved_kernel_pcid() -> [u64; PCID_COUNT / 64] {
+    let mut bitmap = [0; PCID_COUNT / 64];
+    bitmap[0] = 1 << KERNEL_PCID;
+    bitmap
+}
+
+// 没有打开PCID或者已经用完时返回KERNEL_PCID，这样的页表每次切换都会刷新TLB
+pub fn alloc_pcid() -> u16 {
+    if !x86_64_control::pcid_enabled() {
+        return KERNEL_PCID;
+    }
+    let mut pcids = PCIDS.lock();
+    for (index, word) in pcids.iter_mut().enumerate() {
+        if *word != u64::MAX {
+            let bit = word.trailing_ones() as usize;
+            *word |= 1 << bit;
+            return (index * 64 + bit) as u16;
+        }
+    }
+    KERNEL_PCID
+}
+
+// 下一个使用这个PCID的页表不能看到旧的TLB项
+pub fn free_pcid(pcid: u16) {
+    if pcid == KERNEL_PCID {
+        return;
+    }
+    tlb::tlb_flush_pcid(pcid);
+    PCIDS.lock()[pcid as usize / 64] &= !(1 << (pcid % 64));
+}
@@ -37,9 +37,9 @@ where
 
 use core::ops::{Index, IndexMut};
 
-use crate::memory::FrameAllocator;
-use crate::memory::paging::ENTRY_COUNT;
 use crate::memory::paging::entry::{Entry, EntryFlags};
+use crate::memory::paging::{ENTRY_COUNT, phys_to_virt};
+use crate::memory::{Frame, FrameAllocator};
 
 impl<L> Index<usize> for Table<L>
 where
@@ -81,7 +81,9 @@ where
         if entry_flags.contains(EntryFlags::PRESENT) && !entry_flags.contains(EntryFlags::HUGE_PAGE)
         {
             let table_address = self as *const _ as usize;
-            Some((table_address << 9) | (index << 12))
+            let address = (table_address << 9) | (index << 12);
+            // 按第47位做符号扩展，保证得到规范地址
+            Some(((address << 16) as isize >> 16) as usize)
         } else {
             None
         }
@@ -97,11 +99,44 @@ where
             .map(|address| unsafe { &mut *(address as *mut _) })
     }
 
+    // 下一级页表所在的帧，大页和不存在的项返回None
+    pub fn next_table_frame(&self, index: usize) -> Option<Frame> {
+        if self[index].flags().contains(EntryFlags::HUGE_PAGE) {
+            return None;
+        }
+        self[index].pointed_frame()
+    }
+
+    // 通过物理内存的直接映射访问下一级页表，这个页表不需要是活动页表
+    pub fn next_table_direct(&self, index: usize) -> Option<&Table<L::NextLevel>> {
+        self.next_table_frame(index)
+            .map(|frame| unsafe { &*(phys_to_virt(frame.start_address()) as *const _) })
+    }
+
+    pub fn next_table_direct_mut(&mut self, index: usize) -> Option<&mut Table<L::NextLevel>> {
+        self.next_table_frame(index)
+            .map(|frame| unsafe { &mut *(phys_to_virt(frame.start_address()) as *mut _) })
+    }
+
     pub fn next_table_create<A>(
         &mut self,
         index: usize,
         allocator: &mut A,
     ) -> &mut Table<L::NextLevel>
+    where
+        A: FrameAllocator,
+    {
+        self.next_table_create_with(index, EntryFlags::empty(), allocator)
+    }
+
+    // 在指向下一级页表的项上再加上flags，
+    // 用户态能访问的页要求每一级的表项都带USER_ACCESSIBLE
+    pub fn next_table_create_with<A>(
+        &mut self,
+        index: usize,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> &mut Table<L::NextLevel>
     where
         A: FrameAllocator,
     {
@@ -114,9 +149,10 @@ where
             self.entries[index].set(frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
             self.next_table_mut(index).unwrap().zero();
         }
+        self.entries[index].insert_flags(flags);
         self.next_table_mut(index).unwrap()
     }
-
 }
 
-pub const P4: *mut Table<Level4> = 0xffffffff_fffff000 as *mut _;
+// 递归映射位于P4的第510项
+pub const P4: *mut Table<Level4> = 0xffffff7f_bfdfe000 as *mut _;
@@ -0,0 +1,108 @@
+use crate::memory::{
+    Frame,
+    paging::{
+        ENTRY_COUNT, EntryFlags, Page, RECURSIVE_INDEX,
+        table::{Level4, Table},
+    },
+};
+
+// 按地址顺序遍历一个P4中所有已映射的页，得到(页, 帧, 标志)。
+// 带HUGE_PAGE标志的项总是2MiB的页，1GiB的页拆成512个2MiB的页。
+// 下一级页表通过直接映射访问，P4不需要是活动页表
+pub struct Mappings<'a> {
+    p4: &'a Table<Level4>,
+    // 下一个要检查的表项在P4, P3, P2, P1中的下标
+    indices: [usize; 4],
+}
+
+impl<'a> Mappings<'a> {
+    pub fn new(p4: &'a Table<Level4>) -> Mappings<'a> {
+        Mappings {
+            p4,
+            indices: [0; 4],
+        }
+    }
+
+    // 跳到level这一级的下一个表项，更低级的下标清零
+    fn advance(&mut self, level: usize) {
+        self.indices[level] += 1;
+        for index in self.indices[level + 1..].iter_mut() {
+            *index = 0;
+        }
+    }
+}
+
+fn page_at(p4_index: usize, p3_index: usize, p2_index: usize, p1_index: usize) -> Page {
+    let address = (p4_index << 39) | (p3_index << 30) | (p2_index << 21) | (p1_index << 12);
+    // 按第47位做符号扩展
+    Page::containing_address(((address << 16) as isize >> 16) as usize)
+}
+
+impl Iterator for Mappings<'_> {
+    type Item = (Page, Frame, EntryFlags);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let [i4, i3, i2, i1] = self.indices;
+            if i4 >= ENTRY_COUNT {
+                return None;
+            }
+
+            // 递归映射项指向P4自己，不是真正的映射
+            let p3 = match self.p4.next_table_direct(i4) {
+                Some(p3) if i4 != RECURSIVE_INDEX && i3 < ENTRY_COUNT => p3,
+                _ => {
+                    self.advance(0);
+                    continue;
+                }
+            };
+            if i2 >= ENTRY_COUNT {
+                self.advance(1);
+                continue;
+            }
+
+            let p3_entry = &p3[i3];
+            if p3_entry.flags().contains(EntryFlags::HUGE_PAGE)
+                && let Some(start_frame) = p3_entry.pointed_frame()
+            {
+                self.advance(2);
+                let frame = Frame {
+                    number: start_frame.number + i2 * ENTRY_COUNT,
+                };
+                return Some((page_at(i4, i3, i2, 0), frame, p3_entry.flags()));
+            }
+            let p2 = match p3.next_table_direct(i3) {
+                Some(p2) if i1 < ENTRY_COUNT => p2,
+                Some(_) => {
+                    self.advance(2);
+                    continue;
+                }
+                None => {
+                    self.advance(1);
+                    continue;
+                }
+            };
+
+            let p2_entry = &p2[i2];
+            if p2_entry.flags().contains(EntryFlags::HUGE_PAGE)
+                && let Some(frame) = p2_entry.pointed_frame()
+            {
+                self.advance(2);
+                return Some((page_at(i4, i3, i2, 0), frame, p2_entry.flags()));
+            }
+            let p1 = match p2.next_table_direct(i2) {
+                Some(p1) => p1,
+                None => {
+                    self.advance(2);
+                    continue;
+                }
+            };
+
+            self.advance(3);
+            let p1_entry = &p1[i1];
+            if let Some(frame) = p1_entry.pointed_frame() {
+                return Some((page_at(i4, i3, i2, i1), frame, p1_entry.flags()));
+            }
+        }
+    }
+}
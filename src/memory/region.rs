@@ -0,0 +1,152 @@
+use crate::memory::{
+    PAGE_SIZE,
+    paging::{EntryFlags, Page, PageIter, PhysicalAddress, VirtualAddress},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    Stack,
+    Mmio,
+    Anonymous,
+}
+
+// 区域中的页由什么来提供
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    // 分配时就映射好物理帧
+    Eager,
+    // 缺页时才映射清零的物理帧
+    Demand,
+    // 映射到固定的物理地址
+    Mmio(PhysicalAddress),
+    // 永远不映射，访问即出错
+    Guard,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    flags: EntryFlags,
+    kind: RegionKind,
+    backing: Backing,
+}
+
+impl Region {
+    pub fn new(
+        start: VirtualAddress,
+        size: usize,
+        flags: EntryFlags,
+        kind: RegionKind,
+        backing: Backing,
+    ) -> Region {
+        assert!(
+            start.is_multiple_of(PAGE_SIZE),
+            "region start must be page aligned"
+        );
+        assert!(
+            size > 0 && size.is_multiple_of(PAGE_SIZE),
+            "region size must be page aligned"
+        );
+        Region {
+            start,
+            end: start + size,
+            flags,
+            kind,
+            backing,
+        }
+    }
+
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtualAddress {
+        self.end
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn flags(&self) -> EntryFlags {
+        self.flags
+    }
+
+    pub fn kind(&self) -> RegionKind {
+        self.kind
+    }
+
+    pub fn backing(&self) -> Backing {
+        self.backing
+    }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    pub fn pages(&self) -> PageIter {
+        Page::range_inclusive(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end - 1),
+        )
+    }
+
+    pub fn set_flags(&mut self, flags: EntryFlags) {
+        self.flags = flags;
+    }
+
+    // 在address处把区域分成两部分，MMIO区域的物理地址随之偏移
+    pub fn split_at(self, address: VirtualAddress) -> (Region, Region) {
+        assert!(
+            self.start < address && address < self.end,
+            "split address must be inside the region"
+        );
+        assert!(address.is_multiple_of(PAGE_SIZE));
+
+        let upper_backing = match self.backing {
+            Backing::Mmio(physical) => Backing::Mmio(physical + (address - self.start)),
+            backing => backing,
+        };
+        let lower = Region {
+            end: address,
+            ..self
+        };
+        let upper = Region {
+            start: address,
+            backing: upper_backing,
+            ..self
+        };
+        (lower, upper)
+    }
+
+    // 相邻且属性相同的区域可以合并，栈和保护页保持独立
+    pub fn can_merge(&self, next: &Region) -> bool {
+        if self.end != next.start || self.flags != next.flags || self.kind != next.kind {
+            return false;
+        }
+        match (self.backing, next.backing) {
+            (Backing::Mmio(physical), Backing::Mmio(next_physical)) => {
+                physical + self.size() == next_physical
+            }
+            (Backing::Guard, _) | (_, Backing::Guard) => false,
+            (backing, next_backing) => backing == next_backing && self.kind != RegionKind::Stack,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RegionError {
+    Overlap,
+    OutOfRange,
+    NoSpace,
+    NotFound,
+    TooManyRegions,
+    // 大小为0
+    Empty,
+}
@@ -0,0 +1,71 @@
+use spin::Mutex;
+
+use crate::memory::Frame;
+
+// 最多同时记录的共享帧数量，记录满了之后fork直接复制页
+pub const MAX_SHARED_FRAMES: usize = 4096;
+
+// 被多个页表映射的帧和映射它的页表数量，按帧号排序。
+// 只记录数量不少于2的帧，没有记录的帧只属于一个页表。
+// 不使用堆内存，在缺页处理中也可以修改
+pub struct SharedFrames {
+    frames: [(usize, usize); MAX_SHARED_FRAMES],
+    len: usize,
+}
+
+// 总是在持有MemoryController的锁时使用
+pub static SHARED_FRAMES: Mutex<SharedFrames> = Mutex::new(SharedFrames {
+    frames: [(0, 0); MAX_SHARED_FRAMES],
+    len: 0,
+});
+
+impl SharedFrames {
+    fn position(&self, frame: &Frame) -> Result<usize, usize> {
+        self.frames[..self.len].binary_search_by_key(&frame.number, |&(number, _)| number)
+    }
+
+    // 映射这个帧的页表数量
+    pub fn count(&self, frame: &Frame) -> usize {
+        self.position(frame).map_or(1, |i| self.frames[i].1)
+    }
+
+    pub fn is_shared(&self, frame: &Frame) -> bool {
+        self.count(frame) > 1
+    }
+
+    // 又有一个页表映射了这个帧，记录满了时返回false
+    pub fn share(&mut self, frame: &Frame) -> bool {
+        match self.position(frame) {
+            Ok(i) => self.frames[i].1 += 1,
+            Err(_) if self.len == MAX_SHARED_FRAMES => return false,
+            Err(i) => {
+                self.frames[i..=self.len].rotate_right(1);
+                self.frames[i] = (frame.number, 2);
+                self.len += 1;
+            }
+        }
+        true
+    }
+
+    // 一个页表不再映射这个帧，还有其他页表映射它时返回true，否则调用者释放这个帧
+    pub fn unshare(&mut self, frame: &Frame) -> bool {
+        let Ok(i) = self.position(frame) else {
+            return false;
+        };
+        self.frames[i].1 -= 1;
+        if self.frames[i].1 == 1 {
+            self.frames[i..self.len].rotate_left(1);
+            self.len -= 1;
+        }
+        true
+    }
+
+    // 当前被共享的帧的数量
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -1,24 +1,79 @@
-use crate::memory::{
-    FrameAllocator, PAGE_SIZE,
-    paging::{ActivePageTable, EntryFlags, Page, PageIter},
+use spin::Mutex;
+
+use crate::{
+    interrupts::INTERRUPT_STACK_COUNT,
+    memory::{
+        FrameAllocator, PAGE_SIZE,
+        address_space::AddressSpace,
+        paging::{ActivePageTable, EntryFlags, Page, VirtualAddress},
+        region::{Backing, Region, RegionKind},
+    },
+    task::MAX_TASKS,
 };
 
+const STACK_FLAGS: EntryFlags = EntryFlags::WRITABLE.union(EntryFlags::NO_EXECUTE);
+
+// 最多同时记录的栈数量：每个任务一个，中断和异常使用的栈，再加上启动时的栈。
+// 任务表满时新任务的栈会先分配再被拒绝，启动时的任务没有自己的栈，正好留出这一个
+const MAX_STACKS: usize = MAX_TASKS + INTERRUPT_STACK_COUNT + 1;
+// 释放后保留下来供复用的栈数量
+const MAX_FREE_STACKS: usize = 8;
+
+// 保护页地址和栈的使用者，缺页处理程序用它识别栈溢出。
+// 单独加锁，这样持有MemoryController锁时溢出也能报告
+static STACK_GUARDS: Mutex<[Option<(VirtualAddress, &'static str)>; MAX_STACKS]> =
+    Mutex::new([None; MAX_STACKS]);
+
+// address落在某个栈的保护页中时返回这个栈的使用者
+pub fn guard_page_owner(address: VirtualAddress) -> Option<&'static str> {
+    let guard_page = address & !(PAGE_SIZE - 1);
+    STACK_GUARDS
+        .try_lock()?
+        .iter()
+        .flatten()
+        .find(|(guard, _)| *guard == guard_page)
+        .map(|(_, owner)| *owner)
+}
+
+// 登记不是由StackAllocator分配的栈，比如启动时的栈。记录满了时返回None
+pub fn register_guard_page(guard_page: VirtualAddress, owner: &'static str) -> Option<()> {
+    let mut guards = STACK_GUARDS.lock();
+    let slot = guards.iter_mut().find(|g| g.is_none())?;
+    *slot = Some((guard_page, owner));
+    Some(())
+}
+
+fn unregister_guard_page(guard_page: VirtualAddress) {
+    let mut guards = STACK_GUARDS.lock();
+    if let Some(slot) = guards
+        .iter_mut()
+        .find(|g| g.is_some_and(|(guard, _)| guard == guard_page))
+    {
+        *slot = None;
+    }
+}
+
 pub struct StackAllocator {
-    range: PageIter,
+    // 已经映射好的空闲栈
+    free_stacks: [Option<Stack>; MAX_FREE_STACKS],
 }
 
 #[derive(Debug)]
 pub struct Stack {
     top: usize,
     bottom: usize,
+    owner: &'static str,
+    lazy: bool,
 }
 
 impl Stack {
-    fn new(top: usize, bottom: usize) -> Stack {
+    fn new(top: usize, bottom: usize, owner: &'static str, lazy: bool) -> Stack {
         assert!(top > bottom);
         Stack {
-            top: top,
-            bottom: bottom,
+            top,
+            bottom,
+            owner,
+            lazy,
         }
     }
 
@@ -29,47 +84,143 @@ impl Stack {
     pub fn bottom(&self) -> usize {
         self.bottom
     }
+
+    pub fn owner(&self) -> &'static str {
+        self.owner
+    }
+
+    pub fn size_in_pages(&self) -> usize {
+        (self.top - self.bottom) / PAGE_SIZE
+    }
+
+    pub fn guard_page(&self) -> VirtualAddress {
+        self.bottom - PAGE_SIZE
+    }
 }
 
 impl StackAllocator {
-    pub fn new(page_range: PageIter) -> StackAllocator {
-        StackAllocator { range: page_range }
+    pub const fn new() -> StackAllocator {
+        StackAllocator {
+            free_stacks: [const { None }; MAX_FREE_STACKS],
+        }
     }
 
     pub fn alloc_stack<FA: FrameAllocator>(
         &mut self,
+        address_space: &mut AddressSpace,
         active_table: &mut ActivePageTable,
         frame_allocator: &mut FA,
         size_in_pages: usize,
+        owner: &'static str,
     ) -> Option<Stack> {
-        if size_in_pages == 0 {
-            return None;
+        if let Some(stack) = self.reuse(size_in_pages, owner) {
+            return Some(stack);
         }
 
-        let mut range = self.range.clone();
+        let (start, end) =
+            self.reserve_pages(address_space, size_in_pages, Backing::Eager, owner)?;
 
-        let guard_page = range.next();
-        let stack_start = range.next();
+        for page in Page::range_inclusive(start, end) {
+            active_table.map(page, STACK_FLAGS, frame_allocator);
+        }
 
-        let stack_end = if size_in_pages == 1 {
-            stack_start
-        } else {
-            range.nth(size_in_pages - 2)
-        };
+        let top_of_stack = end.start_address() + PAGE_SIZE;
+        Some(Stack::new(
+            top_of_stack,
+            start.start_address(),
+            owner,
+            false,
+        ))
+    }
 
-        match (guard_page, stack_start, stack_end) {
-            (Some(_), Some(start), Some(end)) => {
-                self.range = range;
+    pub fn alloc_lazy_stack(
+        &mut self,
+        address_space: &mut AddressSpace,
+        size_in_pages: usize,
+        owner: &'static str,
+    ) -> Option<Stack> {
+        let (start, end) =
+            self.reserve_pages(address_space, size_in_pages, Backing::Demand, owner)?;
 
-                for page in Page::range_inclusive(start, end) {
-                    active_table.map(page, EntryFlags::WRITABLE, frame_allocator);
-                }
+        let top_of_stack = end.start_address() + PAGE_SIZE;
+        Some(Stack::new(top_of_stack, start.start_address(), owner, true))
+    }
 
-                let top_of_stack = end.start_address() + PAGE_SIZE;
-                Some(Stack::new(top_of_stack, start.start_address()))
+    // 映射好的栈先留着给下次分配，放不下或者是按需映射的栈就返回给调用者释放
+    pub fn recycle(&mut self, stack: Stack) -> Option<Stack> {
+        unregister_guard_page(stack.guard_page());
+        if stack.lazy {
+            return Some(stack);
+        }
+        match self.free_stacks.iter_mut().find(|s| s.is_none()) {
+            Some(slot) => {
+                *slot = Some(stack);
+                None
             }
-            _ => None,
+            None => Some(stack),
         }
     }
-}
 
+    // 保护页登记不下时栈留在缓存中
+    fn reuse(&mut self, size_in_pages: usize, owner: &'static str) -> Option<Stack> {
+        let slot = self.free_stacks.iter_mut().find(|s| {
+            s.as_ref()
+                .is_some_and(|s| s.size_in_pages() == size_in_pages)
+        })?;
+        register_guard_page(slot.as_ref()?.guard_page(), owner)?;
+        let stack = slot.take()?;
+        Some(Stack { owner, ..stack })
+    }
+
+    // 栈的下方保留一个不映射的保护页，并登记到STACK_GUARDS中
+    fn reserve_pages(
+        &mut self,
+        address_space: &mut AddressSpace,
+        size_in_pages: usize,
+        backing: Backing,
+        owner: &'static str,
+    ) -> Option<(Page, Page)> {
+        if size_in_pages == 0 {
+            return None;
+        }
+
+        let guard_page = address_space.find_free_gap((size_in_pages + 1) * PAGE_SIZE, PAGE_SIZE)?;
+        let stack_start = guard_page + PAGE_SIZE;
+        let stack_size = size_in_pages * PAGE_SIZE;
+
+        address_space
+            .insert(Region::new(
+                guard_page,
+                PAGE_SIZE,
+                EntryFlags::empty(),
+                RegionKind::Stack,
+                Backing::Guard,
+            ))
+            .ok()?;
+        let stack = address_space.insert(Region::new(
+            stack_start,
+            stack_size,
+            STACK_FLAGS,
+            RegionKind::Stack,
+            backing,
+        ));
+        if stack.is_err() {
+            // 已经登记的保护页也要撤销
+            address_space
+                .remove(guard_page, PAGE_SIZE)
+                .expect("guard page is not registered");
+            return None;
+        }
+        if register_guard_page(guard_page, owner).is_none() {
+            address_space
+                .remove(guard_page, PAGE_SIZE + stack_size)
+                .expect("stack is not registered");
+            return None;
+        }
+
+        Some((
+            Page::containing_address(stack_start),
+            Page::containing_address(stack_start + stack_size - 1),
+        ))
+    }
+}
@@ -1,9 +1,16 @@
-use crate::utils::align_up;
+use spin::Once;
+
+use crate::{
+    memory::paging::{KERNEL_OFFSET, phys_to_virt, virt_to_phys},
+    utils::align_up,
+};
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MultibootTagType {
     End = 0,
+    CommandLine = 1,
+    Module = 3,
     MemoryMap = 6,
     ElfSections = 9,
     LoadBaseAddr = 21,
@@ -55,7 +62,7 @@ pub struct Elf64SectionHeader {
     pub sh_flags: u32,
     _reserved2: u32,
     pub sh_addr: u32,
-    _reserved3: u32,
+    pub sh_addr_high: u32,
     pub sh_offset: u32,
     _reserved4: u32,
     pub sh_size: u32,
@@ -92,7 +99,21 @@ impl Elf64SectionHeader {
     }
 
     pub fn start_address(&self) -> usize {
-        self.sh_addr as usize
+        (self.sh_addr_high as usize) << 32 | self.sh_addr as usize
+    }
+
+    // 高半部分的段被加载到 虚拟地址 - KERNEL_OFFSET 的物理地址处
+    pub fn physical_start_address(&self) -> usize {
+        let address = self.start_address();
+        if address >= KERNEL_OFFSET {
+            address - KERNEL_OFFSET
+        } else {
+            address
+        }
+    }
+
+    pub fn physical_end_address(&self) -> usize {
+        self.physical_start_address() + self.size()
     }
 
     pub fn size(&self) -> usize {
@@ -100,7 +121,7 @@ impl Elf64SectionHeader {
     }
 
     pub fn end_address(&self) -> usize {
-        self.sh_addr as usize + self.sh_size as usize
+        self.start_address() + self.size()
     }
 
     pub fn flags(&self) -> SectionFlags {
@@ -108,6 +129,62 @@ impl Elf64SectionHeader {
     }
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct MultibootCommandLineTag {
+    pub header: MultibootTagHeader,
+    pub string: [u8; 0],
+}
+
+impl MultibootCommandLineTag {
+    // 以0结尾的UTF-8字符串
+    pub fn command_line(&self) -> &str {
+        let len = self.header.size as usize - core::mem::size_of::<MultibootTagHeader>();
+        let bytes = unsafe { core::slice::from_raw_parts(self.string.as_ptr(), len) };
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        core::str::from_utf8(&bytes[..end]).unwrap_or("")
+    }
+}
+
+// GRUB的module2命令加载的文件，命令行的第一个词作为模块名
+#[repr(C)]
+#[derive(Debug)]
+pub struct MultibootModuleTag {
+    pub header: MultibootTagHeader,
+    pub mod_start: u32,
+    pub mod_end: u32,
+    pub string: [u8; 0],
+}
+
+impl MultibootModuleTag {
+    pub fn physical_start_address(&self) -> usize {
+        self.mod_start as usize
+    }
+
+    pub fn physical_end_address(&self) -> usize {
+        self.mod_end as usize
+    }
+
+    // 以0结尾的UTF-8字符串
+    pub fn command_line(&self) -> &str {
+        let len = self.header.size as usize - core::mem::size_of::<MultibootModuleTag>();
+        let bytes = unsafe { core::slice::from_raw_parts(self.string.as_ptr(), len) };
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        core::str::from_utf8(&bytes[..end]).unwrap_or("")
+    }
+
+    pub fn name(&self) -> &str {
+        self.command_line().split_whitespace().next().unwrap_or("")
+    }
+
+    // 模块的内容，通过直接映射访问
+    pub fn data(&self) -> &'static [u8] {
+        let start = phys_to_virt(self.physical_start_address());
+        let len = self.physical_end_address() - self.physical_start_address();
+        unsafe { core::slice::from_raw_parts(start as *const u8, len) }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct MultibootMemMapTag {
@@ -158,12 +235,26 @@ pub struct MultibootInfo {
     base_address: usize,
 }
 
+static BOOT_INFO: Once<MultibootInfo> = Once::new();
+
+// multiboot传入的是物理地址，通过直接映射访问
+pub fn init(multiboot_information_address: usize) -> &'static MultibootInfo {
+    BOOT_INFO.call_once(|| MultibootInfo::new(phys_to_virt(multiboot_information_address)))
+}
+
+// 在init之前返回None，panic处理程序也会用到
+pub fn boot_info() -> Option<&'static MultibootInfo> {
+    BOOT_INFO.get()
+}
+
 #[derive(Debug)]
 pub struct MultibootAddressSection {
     pub kernel_start: usize,
     pub kernel_end: usize,
     pub multiboot_start: usize,
     pub multiboot_end: usize,
+    // 所有启动模块占用的物理地址范围，没有模块时为None
+    pub modules: Option<(usize, usize)>,
 }
 
 impl MultibootInfo {
@@ -189,17 +280,36 @@ impl MultibootInfo {
     }
 
     pub fn get_tag<T: Sized>(&self, tag_type: MultibootTagType) -> Option<&T> {
+        self.get_tags(tag_type).next()
+    }
+
+    // 同一种类型的tag可能有多个，比如启动模块
+    pub fn get_tags<'a, T: Sized + 'a>(
+        &'a self,
+        tag_type: MultibootTagType,
+    ) -> impl Iterator<Item = &'a T> + 'a {
         let mut tag_base_address = self.base_address + 4 * 2;
-        loop {
-            let tag = unsafe { &*(tag_base_address as *const MultibootTagHeader) };
-            if tag.tag_type == MultibootTagType::End as u32 {
-                return None;
-            }
-            if tag.tag_type == tag_type as u32 {
-                return Some(unsafe { &*(tag_base_address as *const T) });
+        core::iter::from_fn(move || {
+            loop {
+                let tag = unsafe { &*(tag_base_address as *const MultibootTagHeader) };
+                if tag.tag_type == MultibootTagType::End as u32 {
+                    return None;
+                }
+                let address = tag_base_address;
+                tag_base_address += align_up(tag.size as usize, 8);
+                if tag.tag_type == tag_type as u32 {
+                    return Some(unsafe { &*(address as *const T) });
+                }
             }
-            tag_base_address += align_up(tag.size as usize, 8);
-        }
+        })
+    }
+
+    pub fn get_modules(&self) -> impl Iterator<Item = &MultibootModuleTag> {
+        self.get_tags::<MultibootModuleTag>(MultibootTagType::Module)
+    }
+
+    pub fn get_module(&self, name: &str) -> Option<&MultibootModuleTag> {
+        self.get_modules().find(|module| module.name() == name)
     }
 
     pub fn get_memory_entries(&self) -> &[MultibootMemMapEntry] {
@@ -210,6 +320,18 @@ impl MultibootInfo {
         mem_map_tag.entries()
     }
 
+    pub fn get_command_line(&self) -> &str {
+        self.get_tag::<MultibootCommandLineTag>(MultibootTagType::CommandLine)
+            .map_or("", |tag| tag.command_line())
+    }
+
+    // 命令行中是否有单独的option参数
+    pub fn has_boot_option(&self, option: &str) -> bool {
+        self.get_command_line()
+            .split_whitespace()
+            .any(|arg| arg == option)
+    }
+
     pub fn get_elf_sections(&self) -> &[Elf64SectionHeader] {
         let elf_section_tag = self
             .get_tag::<MultibootElfSymbolsTag>(MultibootTagType::ElfSections)
@@ -226,26 +348,41 @@ impl MultibootInfo {
         let kernel_start = elf_section_tag
             .sections()
             .iter()
-            .filter(|s| s.sh_size > 0)
-            .map(|s| s.sh_addr as usize)
+            .filter(|s| s.sh_size > 0 && s.is_allocated())
+            .map(|s| s.physical_start_address())
             .min()
             .expect("No ELF sections found");
 
         let kernel_end = elf_section_tag
             .sections()
             .iter()
-            .map(|s| (s.sh_addr + s.sh_size) as usize)
+            .filter(|s| s.is_allocated())
+            .map(|s| s.physical_end_address())
             .max()
             .expect("No ELF sections found");
 
-        let multiboot_start = self.get_boot_info_base_address();
+        // 帧分配器需要的是物理地址
+        let multiboot_start = virt_to_phys(self.get_boot_info_base_address());
         let multiboot_end = multiboot_start + self.get_boot_info_total_size();
 
+        let modules = self
+            .get_modules()
+            .map(|module| {
+                (
+                    module.physical_start_address(),
+                    module.physical_end_address(),
+                )
+            })
+            .reduce(|(start, end), (module_start, module_end)| {
+                (start.min(module_start), end.max(module_end))
+            });
+
         MultibootAddressSection {
             kernel_start,
             kernel_end,
             multiboot_start,
             multiboot_end,
+            modules,
         }
     }
 }
@@ -35,6 +35,23 @@ impl SerialPort {
         self.modem_control.write(0x0B);
     }
 
+    // 收到数据时产生IRQ4
+    pub fn enable_receive_interrupt(&mut self) {
+        self.interrupt.write(0x01);
+    }
+
+    fn is_data_ready(&self) -> bool {
+        self.line_status.read() & 0x01 != 0
+    }
+
+    pub fn receive_byte(&mut self) -> Option<u8> {
+        if self.is_data_ready() {
+            Some(self.data.read())
+        } else {
+            None
+        }
+    }
+
     fn is_transmit_empty(&self) -> bool {
         self.line_status.read() & 0x20 != 0
     }
@@ -75,6 +92,17 @@ fn init_serial() {
     });
 }
 
+// 在中断处理程序中读取COM1，不使用SERIAL的锁，被中断的代码可能正持有它
+pub fn receive_byte() -> Option<u8> {
+    init_serial();
+    SerialPort::new(COM1).receive_byte()
+}
+
+pub fn enable_receive_interrupt() {
+    init_serial();
+    SERIAL.lock().enable_receive_interrupt();
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
@@ -0,0 +1,136 @@
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    interrupts::{self, InterruptContext, KERNEL_STACK_TOP},
+    memory::USER_SPACE_END,
+    println, syscall,
+    task::{
+        self,
+        process::{self, KILLED_STATUS},
+    },
+    utils::x86_64_control::{
+        self,
+        msr::{IA32_FMASK, IA32_LSTAR, IA32_STAR, wrmsr},
+    },
+};
+
+// SYSCALL时清除TF、IF、DF和AC，切换到内核栈之前不能被中断
+const SYSCALL_FLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
+
+// 进入时的用户栈指针，切换到内核栈之后马上保存到内核栈上。只有一个CPU，并且这期间中断是关闭的
+static USER_STACK_POINTER: AtomicU64 = AtomicU64::new(0);
+// 伪造中断栈帧时使用的段选择子，SYSRET本身不读取它们
+static USER_CODE_SELECTOR: AtomicU64 = AtomicU64::new(0);
+static USER_DATA_SELECTOR: AtomicU64 = AtomicU64::new(0);
+
+// 在interrupts::init之后调用
+pub fn init() {
+    let selectors = interrupts::selectors();
+    let kernel_code = selectors.kernel_code.value() as u64;
+    let kernel_data = selectors.kernel_data.value() as u64;
+    let user_data = selectors.user_data.value() as u64;
+    let user_code = selectors.user_code.value() as u64;
+    // SYSCALL加载CS = STAR[47:32]，SS = STAR[47:32] + 8；
+    // SYSRET加载SS = STAR[63:48] + 8，CS = STAR[63:48] + 16
+    assert!(
+        kernel_data == kernel_code + 8,
+        "kernel data must follow kernel code"
+    );
+    assert!(
+        user_code == user_data + 8,
+        "user code must follow user data"
+    );
+    let sysret_base = user_data - 8;
+
+    USER_CODE_SELECTOR.store(user_code, Ordering::SeqCst);
+    USER_DATA_SELECTOR.store(user_data, Ordering::SeqCst);
+    x86_64_control::enable_syscall_extensions();
+    wrmsr(IA32_STAR, (sysret_base << 48) | (kernel_code << 32));
+    wrmsr(
+        IA32_LSTAR,
+        syscall_entry as extern "C" fn() -> ! as usize as u64,
+    );
+    wrmsr(IA32_FMASK, SYSCALL_FLAGS_MASK);
+}
+
+// SYSCALL把返回地址放在rcx，RFLAGS放在r11，不切换栈。
+// 在内核栈上按handler_with_context!的布局保存寄存器，和int 0x80共用dispatch
+#[unsafe(naked)]
+extern "C" fn syscall_entry() -> ! {
+    naked_asm!(
+        "mov [rip + {user_rsp}], rsp",
+        "mov rsp, [rip + {kernel_rsp}]",
+
+        // 和中断相同的栈帧
+        "push qword ptr [rip + {user_ss}]",
+        "push qword ptr [rip + {user_rsp}]",
+        "push r11",
+        "push qword ptr [rip + {user_cs}]",
+        "push rcx",
+
+        "push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push rbp
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15",
+
+        "mov rdi, rsp",
+        "call {handler}",
+
+        "pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rbp
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax",
+
+        // 从栈帧中恢复rip、RFLAGS和用户栈，handler返回时中断已经关闭
+        "pop rcx",
+        "add rsp, 8",
+        "pop r11",
+        "pop rsp",
+        "sysretq",
+        user_rsp = sym USER_STACK_POINTER,
+        kernel_rsp = sym KERNEL_STACK_TOP,
+        user_ss = sym USER_DATA_SELECTOR,
+        user_cs = sym USER_CODE_SELECTOR,
+        handler = sym syscall_handler,
+    );
+}
+
+extern "C" fn syscall_handler(context: *mut InterruptContext) {
+    let context = unsafe { &mut *context };
+    syscall::dispatch(context);
+    // 返回地址不是规范地址时SYSRET会在Ring0产生#GP
+    if context.instruction_pointer >= USER_SPACE_END as u64 {
+        println!(
+            "\nuser task {} killed: bad return address {:#x}",
+            task::current_name(),
+            context.instruction_pointer
+        );
+        x86_64_control::interrupts::enable();
+        process::exit(KILLED_STATUS);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    interrupts::InterruptContext,
+    memory::{MEMORY_CONTROLLER, PAGE_SIZE, USER_SPACE_END, USER_SPACE_START, paging::EntryFlags},
+    print, serial_print,
+    task::{
+        self,
+        process::{self, Handle, MMAP_BASE, ProcessError, ProcessId},
+    },
+    utils::x86_64_control::interrupts,
+};
+
+mod entry;
+
+pub use entry::init;
+
+// 系统调用号放在rax中，参数依次放在rdi, rsi, rdx, r10, r8, r9中，返回值放在rax中。
+// 出错时返回负的错误码
+pub const SYS_WRITE: usize = 0;
+pub const SYS_EXIT: usize = 1;
+pub const SYS_YIELD: usize = 2;
+pub const SYS_MMAP: usize = 3;
+pub const SYS_GETPID: usize = 4;
+pub const SYS_SLEEP: usize = 5;
+pub const SYS_FORK: usize = 6;
+pub const SYS_WAIT: usize = 7;
+
+const SYSCALL_COUNT: usize = 8;
+
+// 新进程默认打开的句柄
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+// wait的pid参数为-1时等待任意一个子进程
+pub const WAIT_ANY: usize = usize::MAX;
+
+// mmap的prot参数，页总是可读的
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+// write一次最多复制这么多字节到内核中，剩下的由用户程序再次写入
+const WRITE_BUFFER_SIZE: usize = 256;
+
+// 和Linux的errno相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum SyscallError {
+    BadFileDescriptor = 9,
+    NoChild = 10,
+    TryAgain = 11,
+    OutOfMemory = 12,
+    BadAddress = 14,
+    InvalidArgument = 22,
+    NoSuchSyscall = 38,
+}
+
+pub type SyscallResult = Result<usize, SyscallError>;
+
+// 放到rax中的返回值
+pub fn encode_result(result: SyscallResult) -> u64 {
+    match result {
+        Ok(value) => value as u64,
+        Err(error) => (-(error as isize)) as u64,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallArgs {
+    pub number: usize,
+    pub args: [usize; 6],
+}
+
+impl SyscallArgs {
+    pub fn from_context(context: &InterruptContext) -> SyscallArgs {
+        SyscallArgs {
+            number: context.rax as usize,
+            args: [
+                context.rdi as usize,
+                context.rsi as usize,
+                context.rdx as usize,
+                context.r10 as usize,
+                context.r8 as usize,
+                context.r9 as usize,
+            ],
+        }
+    }
+
+    pub fn arg(&self, index: usize) -> usize {
+        self.args[index]
+    }
+
+    // 把第index个参数指向的用户内存复制到buffer中，长度为buffer.len()。
+    // 检查和复制时都持有MemoryController的锁，同一进程的其他线程不能在这期间取消映射
+    fn copy_from_user(&self, index: usize, buffer: &mut [u8]) -> Result<(), SyscallError> {
+        let start = self.arg(index);
+        let memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        if !memory_controller.is_user_accessible(start, buffer.len(), false) {
+            return Err(SyscallError::BadAddress);
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(start as *const u8, buffer.as_mut_ptr(), buffer.len())
+        };
+        Ok(())
+    }
+}
+
+// context是进入内核时保存的用户寄存器，fork用它创建子进程
+type SyscallHandler = fn(&SyscallArgs, &InterruptContext) -> SyscallResult;
+
+// 按系统调用号排列
+const SYSCALL_TABLE: [SyscallHandler; SYSCALL_COUNT] = [
+    sys_write, sys_exit, sys_yield, sys_mmap, sys_getpid, sys_sleep, sys_fork, sys_wait,
+];
+
+// SYSCALL和int 0x80共用的分发函数，结果写回context.rax。
+// 进入时中断是关闭的，系统调用执行期间打开中断，任务可以被抢占或者阻塞
+pub fn dispatch(context: &mut InterruptContext) {
+    let args = SyscallArgs::from_context(context);
+    interrupts::enable();
+    let result = match SYSCALL_TABLE.get(args.number) {
+        Some(handler) => handler(&args, context),
+        None => Err(SyscallError::NoSuchSyscall),
+    };
+    interrupts::disable();
+    context.rax = encode_result(result);
+}
+
+// write(fd, buffer, len)，写到fd在当前进程中对应的句柄，返回写入的字节数。
+// 超过WRITE_BUFFER_SIZE时只写前面的部分
+fn sys_write(args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    let handle = process::handle(args.arg(0)).ok_or(SyscallError::BadFileDescriptor)?;
+    let len = args.arg(2);
+    let truncated = len > WRITE_BUFFER_SIZE;
+    let mut buffer = [0u8; WRITE_BUFFER_SIZE];
+    let buffer = &mut buffer[..len.min(WRITE_BUFFER_SIZE)];
+    args.copy_from_user(1, buffer)?;
+    let text = match core::str::from_utf8(buffer) {
+        Ok(text) => text,
+        // 截断处的字符不完整，只写前面完整的字符
+        Err(error) if truncated && error.error_len().is_none() => {
+            core::str::from_utf8(&buffer[..error.valid_up_to()]).unwrap()
+        }
+        Err(_) => return Err(SyscallError::InvalidArgument),
+    };
+    match handle {
+        Handle::Console => print!("{}", text),
+        Handle::Serial => serial_print!("{}", text),
+    }
+    Ok(text.len())
+}
+
+// exit(status)，只使用低32位
+fn sys_exit(args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    process::exit(args.arg(0) as i32)
+}
+
+// yield()
+fn sys_yield(_args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    task::yield_now();
+    Ok(0)
+}
+
+// mmap(address, len, prot)，映射清零的匿名页并返回起始地址。
+// address为0时由内核从当前进程的mmap_hint开始找空闲的范围，
+// 否则必须按页对齐，并且范围内没有已经映射的页
+fn sys_mmap(args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    let (address, len, prot) = (args.arg(0), args.arg(1), args.arg(2));
+    if len == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let pages = len.div_ceil(PAGE_SIZE);
+    // 比整个用户空间还大的请求不可能满足，之后计算地址也不会溢出
+    if pages > (USER_SPACE_END - USER_SPACE_START) / PAGE_SIZE {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let size = pages * PAGE_SIZE;
+    let in_user_space = address.is_multiple_of(PAGE_SIZE)
+        && address >= USER_SPACE_START
+        && address + size <= USER_SPACE_END;
+    if address != 0 && !in_user_space {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let mut flags = EntryFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= EntryFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= EntryFlags::NO_EXECUTE;
+    }
+    // 进程表的锁要在MemoryController的锁之前获取
+    let hint = process::mmap_hint();
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let start = if address == 0 {
+        // 从hint之后找不到时再从头找，中间可能有取消映射的空闲范围
+        memory_controller
+            .find_free_user_range(hint, pages)
+            .or_else(|| memory_controller.find_free_user_range(MMAP_BASE, pages))
+            .ok_or(SyscallError::OutOfMemory)?
+    } else if memory_controller.is_user_range_free(address, pages) {
+        address
+    } else {
+        return Err(SyscallError::InvalidArgument);
+    };
+    memory_controller
+        .map_user_pages(start, pages, flags)
+        .ok_or(SyscallError::OutOfMemory)?;
+    drop(memory_controller);
+    if address == 0 {
+        process::set_mmap_hint(start + size);
+    }
+    Ok(start)
+}
+
+// getpid()
+fn sys_getpid(_args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    Ok(process::current_id().as_usize())
+}
+
+// sleep(ticks)
+fn sys_sleep(args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    task::sleep(args.arg(0) as u64);
+    Ok(0)
+}
+
+// fork()，父进程得到子进程的进程号，子进程得到0
+fn sys_fork(_args: &SyscallArgs, context: &InterruptContext) -> SyscallResult {
+    match process::fork(context) {
+        Ok(child) => Ok(child.as_usize()),
+        Err(ProcessError::TooManyProcesses) => Err(SyscallError::TryAgain),
+        Err(ProcessError::OutOfMemory) => Err(SyscallError::OutOfMemory),
+        Err(ProcessError::KernelProcess) => Err(SyscallError::InvalidArgument),
+    }
+}
+
+// wait(pid, status)，等待子进程结束，返回它的进程号。
+// status不为0时把退出码作为i32写到这个地址
+fn sys_wait(args: &SyscallArgs, _context: &InterruptContext) -> SyscallResult {
+    let (pid, status) = (args.arg(0), args.arg(1));
+    let pid = (pid != WAIT_ANY).then(|| ProcessId::new(pid));
+    let writable = status == 0
+        || MEMORY_CONTROLLER.get().unwrap().lock().is_user_accessible(
+            status,
+            core::mem::size_of::<i32>(),
+            true,
+        );
+    if !writable {
+        return Err(SyscallError::BadAddress);
+    }
+    let (child, exit_status) = process::wait(pid).ok_or(SyscallError::NoChild)?;
+    if status != 0 {
+        unsafe { core::ptr::write_unaligned(status as *mut i32, exit_status) };
+    }
+    Ok(child.as_usize())
+}
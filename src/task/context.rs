@@ -0,0 +1,90 @@
+use core::arch::naked_asm;
+
+// 切换时保存在栈上的寄存器，顺序和switch_context中pop的顺序一致
+#[repr(C)]
+struct InitialFrame {
+    r15: usize,
+    r14: usize,
+    r13: usize,
+    r12: usize,
+    rbx: usize,
+    rbp: usize,
+    rflags: usize,
+    return_address: usize,
+}
+
+// 第1位是保留位，第9位打开中断，新任务可以被时钟中断抢占
+const INITIAL_RFLAGS: usize = 0x202;
+
+// 任务被切换出去时的栈指针，其余的寄存器都保存在栈上
+#[derive(Debug)]
+pub struct Context {
+    rsp: usize,
+}
+
+impl Context {
+    // 还没有运行过的当前任务，第一次切换出去时才保存
+    pub const fn empty() -> Context {
+        Context { rsp: 0 }
+    }
+
+    // 在stack_top下构造一个栈帧，第一次切换过来时从task_entry开始，
+    // rdi为argument，rbp为0让回溯在这里停下
+    pub fn new(stack_top: usize, entry: extern "C" fn(usize) -> !, argument: usize) -> Context {
+        let frame_address = stack_top - size_of::<InitialFrame>();
+        let frame = frame_address as *mut InitialFrame;
+        unsafe {
+            frame.write(InitialFrame {
+                r15: 0,
+                r14: 0,
+                r13: entry as usize,
+                r12: argument,
+                rbx: 0,
+                rbp: 0,
+                rflags: INITIAL_RFLAGS,
+                return_address: task_trampoline as extern "C" fn() -> ! as usize,
+            });
+        }
+        Context { rsp: frame_address }
+    }
+}
+
+// 新任务的第一条指令，把switch_context恢复的r12/r13转成调用参数
+#[unsafe(naked)]
+extern "C" fn task_trampoline() -> ! {
+    naked_asm!(
+        "mov rdi, r12",
+        "and rsp, -16", // 调用前栈按16字节对齐
+        "call r13",
+        "ud2",
+    );
+}
+
+// 保存callee-saved寄存器和rflags到当前栈，把栈指针存到old，然后切换到new的栈
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stacks(old: *mut usize, new: usize) {
+    naked_asm!(
+        "pushfq
+        push rbp
+        push rbx
+        push r12
+        push r13
+        push r14
+        push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+        popfq",
+        "ret",
+    );
+}
+
+// 返回时已经又切换回了old
+pub unsafe fn switch_context(old: *mut Context, new: *const Context) {
+    unsafe { switch_stacks(&raw mut (*old).rsp, (*new).rsp) };
+}
@@ -0,0 +1,44 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::task::executor::stream::{IrqQueue, Stream};
+
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+static SCANCODES: IrqQueue<SCANCODE_QUEUE_SIZE> = IrqQueue::new();
+
+// 由键盘中断处理程序调用
+pub fn add_scancode(scancode: u8) {
+    SCANCODES.push(scancode);
+}
+
+pub fn dropped_scancodes() -> usize {
+    SCANCODES.dropped()
+}
+
+// 键盘的扫描码，所有ScancodeStream共用一个队列，同一时间只应该有一个读者
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> ScancodeStream {
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        SCANCODES.poll_pop(cx).map(Some)
+    }
+}
+
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        SCANCODES.clear_waker();
+    }
+}
@@ -0,0 +1,192 @@
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use spin::Mutex;
+
+use crate::utils::x86_64_control::interrupts;
+
+pub mod keyboard;
+pub mod serial;
+pub mod stream;
+
+// 一个Executor中同时存在的future数量上限
+pub const MAX_FUTURES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+// 由Executor轮询的future，和内核线程不同，它没有自己的栈
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+impl Task {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, waker: &Waker) -> Poll<()> {
+        let mut context = Context::from_waker(waker);
+        self.future.as_mut().poll(&mut context)
+    }
+}
+
+// 等待轮询的任务，固定大小的环形队列。
+// 中断处理程序也会唤醒任务，所以只在关中断时持有锁，并且不申请内存
+struct ReadyQueue {
+    ids: [TaskId; MAX_FUTURES],
+    head: usize,
+    len: usize,
+}
+
+impl ReadyQueue {
+    fn push(&mut self, id: TaskId) {
+        // 每个任务最多在队列中出现一次，所以不会满
+        assert!(self.len < MAX_FUTURES, "ready queue overflow");
+        self.ids[(self.head + self.len) % MAX_FUTURES] = id;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.ids[self.head];
+        self.head = (self.head + 1) % MAX_FUTURES;
+        self.len -= 1;
+        Some(id)
+    }
+}
+
+type SharedReadyQueue = Arc<Mutex<ReadyQueue>>;
+
+struct TaskWaker {
+    id: TaskId,
+    // 已经在ReadyQueue中时不再重复加入
+    queued: AtomicBool,
+    ready: SharedReadyQueue,
+}
+
+impl TaskWaker {
+    fn wake_task(&self) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            interrupts::without_interrupts(|| self.ready.lock().push(self.id));
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    // 中断处理程序只能用wake_by_ref，释放最后一个Arc会释放内存
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    ready: SharedReadyQueue,
+    next_id: u64,
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(Mutex::new(ReadyQueue {
+                ids: [TaskId(0); MAX_FUTURES],
+                head: 0,
+                len: 0,
+            })),
+            next_id: 0,
+        }
+    }
+
+    // 新的任务在下一次run时第一次被轮询
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) -> TaskId {
+        assert!(self.tasks.len() < MAX_FUTURES, "too many futures");
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        let waker = Arc::new(TaskWaker {
+            id,
+            queued: AtomicBool::new(false),
+            ready: self.ready.clone(),
+        });
+        waker.wake_task();
+        let task = Task {
+            id,
+            future: Box::pin(future),
+            waker,
+        };
+        self.tasks.insert(id, task);
+        id
+    }
+
+    // 还没有完成的任务数量
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    // 轮询所有被唤醒的任务，直到没有任务可以继续执行，返回还没有完成的任务数量
+    pub fn run_until_idle(&mut self) -> usize {
+        while let Some(id) = interrupts::without_interrupts(|| self.ready.lock().pop()) {
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+            // 在轮询之前清除，轮询期间的唤醒会让任务再次被轮询
+            task.waker.queued.store(false, Ordering::SeqCst);
+            let waker = Waker::from(task.waker.clone());
+            if task.poll(&waker).is_ready() {
+                self.tasks.remove(&id);
+            }
+        }
+        self.tasks.len()
+    }
+
+    // 运行到所有任务完成，空闲时用hlt等待中断
+    pub fn run_until_complete(&mut self) {
+        while self.run_until_idle() > 0 {
+            self.sleep_if_idle();
+        }
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_until_idle();
+            self.sleep_if_idle();
+        }
+    }
+
+    // 检查和hlt之间不能响应中断，否则可能错过这期间的唤醒
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.ready.lock().len == 0 {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+// 让出一次，让同一个Executor中的其他任务运行
+pub fn yield_now() -> impl Future<Output = ()> {
+    let mut yielded = false;
+    core::future::poll_fn(move |cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+}
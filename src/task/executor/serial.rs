@@ -0,0 +1,44 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::task::executor::stream::{IrqQueue, Stream};
+
+const SERIAL_QUEUE_SIZE: usize = 256;
+
+static SERIAL_INPUT: IrqQueue<SERIAL_QUEUE_SIZE> = IrqQueue::new();
+
+// 由COM1的中断处理程序调用
+pub fn add_byte(byte: u8) {
+    SERIAL_INPUT.push(byte);
+}
+
+pub fn dropped_bytes() -> usize {
+    SERIAL_INPUT.dropped()
+}
+
+// COM1收到的字节，所有SerialStream共用一个队列，同一时间只应该有一个读者
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> SerialStream {
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        SERIAL_INPUT.poll_pop(cx).map(Some)
+    }
+}
+
+impl Drop for SerialStream {
+    fn drop(&mut self) {
+        SERIAL_INPUT.clear_waker();
+    }
+}
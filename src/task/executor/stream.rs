@@ -0,0 +1,128 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use spin::Mutex;
+
+use crate::utils::x86_64_control::interrupts;
+
+// 异步产生一串值，返回None表示结束
+pub trait Stream {
+    type Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+}
+
+pub trait StreamExt: Stream {
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+}
+
+impl<S: Stream + ?Sized> StreamExt for S {}
+
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+struct RingBuffer<const N: usize> {
+    bytes: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+// 中断处理程序写入、任务读取的字节队列。
+// 锁只在关中断时持有，中断处理程序中不申请也不释放内存
+pub struct IrqQueue<const N: usize> {
+    buffer: Mutex<RingBuffer<N>>,
+    waker: Mutex<Option<Waker>>,
+    // 队列满时丢弃的字节数
+    dropped: AtomicUsize,
+}
+
+impl<const N: usize> IrqQueue<N> {
+    pub const fn new() -> IrqQueue<N> {
+        IrqQueue {
+            buffer: Mutex::new(RingBuffer {
+                bytes: [0; N],
+                head: 0,
+                len: 0,
+            }),
+            waker: Mutex::new(None),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // 由中断处理程序调用
+    pub fn push(&self, byte: u8) {
+        interrupts::without_interrupts(|| {
+            let mut buffer = self.buffer.lock();
+            if buffer.len == N {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            let tail = (buffer.head + buffer.len) % N;
+            buffer.bytes[tail] = byte;
+            buffer.len += 1;
+            drop(buffer);
+            if let Some(waker) = &*self.waker.lock() {
+                waker.wake_by_ref();
+            }
+        });
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        interrupts::without_interrupts(|| {
+            let mut buffer = self.buffer.lock();
+            if buffer.len == 0 {
+                return None;
+            }
+            let byte = buffer.bytes[buffer.head];
+            buffer.head = (buffer.head + 1) % N;
+            buffer.len -= 1;
+            Some(byte)
+        })
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    // 取出一个字节，队列为空时记住waker，push时唤醒
+    pub fn poll_pop(&self, cx: &mut Context) -> Poll<u8> {
+        if let Some(byte) = self.pop() {
+            return Poll::Ready(byte);
+        }
+        interrupts::without_interrupts(|| {
+            let mut waker = self.waker.lock();
+            if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+        });
+        // 注册之前push的字节不会唤醒任务，再检查一次
+        match self.pop() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        }
+    }
+
+    // 读取的一方不再等待时调用，释放保存的waker
+    pub fn clear_waker(&self) {
+        let waker = interrupts::without_interrupts(|| self.waker.lock().take());
+        drop(waker);
+    }
+}
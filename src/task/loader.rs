@@ -0,0 +1,332 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    elf::{Elf64ProgramHeader, ElfError, ElfFile, ElfType, SegmentFlags, SegmentType},
+    memory::{
+        MEMORY_CONTROLLER, PAGE_SIZE, USER_SPACE_START,
+        paging::{Entry, EntryFlags, InactivePageTable, VirtualAddress},
+    },
+    multiboot_info,
+    task::process::{self, ProcessError, ProcessId},
+    utils::{align_down, align_up},
+};
+
+// 用户栈的最高地址，再往上的一页不映射
+pub const USER_STACK_TOP: VirtualAddress = 0x7fff_ffff_f000;
+pub const USER_STACK_PAGES: usize = 16;
+const USER_STACK_BOTTOM: VirtualAddress = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+
+// 位置无关的程序从这里开始加载
+pub const PIE_LOAD_BASE: VirtualAddress = 0x5555_5555_4000;
+
+// 参数和环境变量最多占用用户栈的一半
+const MAX_ARGUMENTS_SIZE: usize = USER_STACK_PAGES * PAGE_SIZE / 2;
+
+// 辅助向量的类型
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+// AT_RANDOM指向的随机字节数
+const RANDOM_BYTES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    // 段不在用户空间中，或者和其他段共用同一页
+    BadSegmentAddress,
+    // 入口不在可执行的段中
+    BadEntryPoint,
+    ArgumentsTooLong,
+    OutOfMemory,
+    NoSuchModule,
+    TooManyProcesses,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> LoadError {
+        LoadError::Elf(error)
+    }
+}
+
+// 加载到新页表中的程序，还没有开始运行
+pub struct UserImage {
+    page_table: InactivePageTable,
+    entry: VirtualAddress,
+    stack_pointer: VirtualAddress,
+}
+
+impl UserImage {
+    pub fn entry(&self) -> VirtualAddress {
+        self.entry
+    }
+
+    pub fn stack_pointer(&self) -> VirtualAddress {
+        self.stack_pointer
+    }
+
+    pub fn page_table(&mut self) -> &mut InactivePageTable {
+        &mut self.page_table
+    }
+
+    // 在当前进程的子进程中运行，页表归新进程所有
+    pub fn spawn(self, name: &'static str) -> Result<ProcessId, ProcessError> {
+        process::spawn(name, self)
+    }
+
+    pub(super) fn into_parts(self) -> (InactivePageTable, VirtualAddress, VirtualAddress) {
+        (self.page_table, self.entry, self.stack_pointer)
+    }
+
+    // 没有运行的程序也要释放映射的帧
+    pub fn free(mut self) {
+        free_user_frames(&mut self.page_table);
+    }
+}
+
+fn free_user_frames(page_table: &mut InactivePageTable) {
+    page_table.free_user_frames(&mut *MEMORY_CONTROLLER.get().unwrap().lock());
+}
+
+// 把静态链接或者位置无关的ELF程序加载到新的页表中，并在用户栈上准备好argv、envp和辅助向量
+pub fn load(data: &[u8], argv: &[&str], envp: &[&str]) -> Result<UserImage, LoadError> {
+    let elf = ElfFile::parse(data)?;
+    let bias = load_bias(&elf)?;
+    check_segments(&elf, bias)?;
+
+    let mut page_table = MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .new_page_table()
+        .ok_or(LoadError::OutOfMemory)?;
+    match map_image(&mut page_table, &elf, bias, argv, envp) {
+        Ok((entry, stack_pointer)) => Ok(UserImage {
+            page_table,
+            entry,
+            stack_pointer,
+        }),
+        Err(error) => {
+            free_user_frames(&mut page_table);
+            Err(error)
+        }
+    }
+}
+
+// 加载名为name的启动模块，模块命令行中的各个词作为argv
+pub fn load_module(name: &str, envp: &[&str]) -> Result<UserImage, LoadError> {
+    let module = multiboot_info::boot_info()
+        .and_then(|boot_info| boot_info.get_module(name))
+        .ok_or(LoadError::NoSuchModule)?;
+    let argv: Vec<&str> = module.command_line().split_whitespace().collect();
+    load(module.data(), &argv, envp)
+}
+
+// 启动模块的名字在启动信息中，一直有效
+pub fn spawn_module(name: &'static str) -> Result<ProcessId, LoadError> {
+    load_module(name, &[])?
+        .spawn(name)
+        .map_err(|_| LoadError::TooManyProcesses)
+}
+
+// 加载地址和文件中地址的差，按2^64取模
+fn load_bias(elf: &ElfFile) -> Result<usize, LoadError> {
+    match elf.elf_type()? {
+        ElfType::Executable => Ok(0),
+        ElfType::SharedObject => {
+            let lowest = elf
+                .load_segments()
+                .map(|segment| segment.start_address())
+                .min()
+                .ok_or(LoadError::BadSegmentAddress)?;
+            Ok(PIE_LOAD_BASE.wrapping_sub(align_down(lowest, PAGE_SIZE)))
+        }
+    }
+}
+
+// 段加上bias之后占用的页，第一页和最后一页的起始地址
+fn segment_pages(segment: &Elf64ProgramHeader, bias: usize) -> Option<(usize, usize)> {
+    let start = segment.start_address().wrapping_add(bias);
+    let end = start.checked_add(segment.mem_size())?;
+    Some((align_down(start, PAGE_SIZE), align_down(end - 1, PAGE_SIZE)))
+}
+
+fn check_segments(elf: &ElfFile, bias: usize) -> Result<(), LoadError> {
+    let segments = || elf.load_segments().filter(|segment| segment.mem_size() > 0);
+    for (index, segment) in segments().enumerate() {
+        let (first, last) = segment_pages(&segment, bias).ok_or(LoadError::BadSegmentAddress)?;
+        if first < USER_SPACE_START || last >= USER_STACK_BOTTOM - PAGE_SIZE {
+            return Err(LoadError::BadSegmentAddress);
+        }
+        // 每一页只有一种权限
+        let overlaps = segments().skip(index + 1).any(|other| {
+            segment_pages(&other, bias)
+                .is_none_or(|(other_first, other_last)| other_first <= last && first <= other_last)
+        });
+        if overlaps {
+            return Err(LoadError::BadSegmentAddress);
+        }
+    }
+
+    let entry = elf.entry_point().wrapping_add(bias);
+    let entry_is_executable = segments().any(|segment| {
+        let start = segment.start_address().wrapping_add(bias);
+        segment.flags().contains(SegmentFlags::PF_X)
+            && (start..start + segment.mem_size()).contains(&entry)
+    });
+    if !entry_is_executable {
+        return Err(LoadError::BadEntryPoint);
+    }
+    Ok(())
+}
+
+fn map_image(
+    page_table: &mut InactivePageTable,
+    elf: &ElfFile,
+    bias: usize,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(VirtualAddress, VirtualAddress), LoadError> {
+    for segment in elf.load_segments().filter(|segment| segment.mem_size() > 0) {
+        let (first, last) = segment_pages(&segment, bias).unwrap();
+        let start = segment.start_address().wrapping_add(bias);
+        let data = elf.segment_data(&segment);
+        // 页在映射前已经清零，文件中没有的部分（BSS）保持为0
+        MEMORY_CONTROLLER
+            .get()
+            .unwrap()
+            .lock()
+            .map_user_pages_in(
+                page_table,
+                first,
+                (last - first) / PAGE_SIZE + 1,
+                Entry::from_elf_segment_flags(segment.flags()),
+                |page, contents| copy_overlap(contents, page, data, start),
+            )
+            .ok_or(LoadError::OutOfMemory)?;
+    }
+
+    let entry = elf.entry_point().wrapping_add(bias);
+    let auxv = [
+        (AT_PHDR, program_headers_address(elf, bias)),
+        (AT_PHENT, Some(core::mem::size_of::<Elf64ProgramHeader>())),
+        (AT_PHNUM, Some(elf.program_header_count())),
+        (AT_PAGESZ, Some(PAGE_SIZE)),
+        // 没有动态链接器
+        (AT_BASE, Some(0)),
+        (AT_ENTRY, Some(entry)),
+    ];
+    let auxv: Vec<(usize, usize)> = auxv
+        .iter()
+        .filter_map(|&(key, value)| Some((key, value?)))
+        .collect();
+    let (stack, stack_pointer) = build_stack(argv, envp, &auxv)?;
+    MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .map_user_pages_in(
+            page_table,
+            USER_STACK_BOTTOM,
+            USER_STACK_PAGES,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            |page, contents| copy_overlap(contents, page, &stack, stack_pointer),
+        )
+        .ok_or(LoadError::OutOfMemory)?;
+    Ok((entry, stack_pointer))
+}
+
+// 把从source_start开始的source中和[page, page + PAGE_SIZE)重叠的部分复制到contents
+fn copy_overlap(contents: &mut [u8], page: VirtualAddress, source: &[u8], source_start: usize) {
+    let start = page.max(source_start);
+    let end = (page + PAGE_SIZE).min(source_start + source.len());
+    if start < end {
+        contents[start - page..end - page]
+            .copy_from_slice(&source[start - source_start..end - source_start]);
+    }
+}
+
+// 程序头在内存中的地址，有PT_PHDR时直接使用，否则找包含程序头的PT_LOAD段
+fn program_headers_address(elf: &ElfFile, bias: usize) -> Option<usize> {
+    if let Some(phdr) = elf
+        .program_headers()
+        .find(|header| header.is_type(SegmentType::Phdr))
+    {
+        return Some(phdr.start_address().wrapping_add(bias));
+    }
+    let offset = elf.header().e_phoff as usize;
+    elf.load_segments()
+        .find(|segment| segment.file_range().contains(&offset))
+        .map(|segment| {
+            (segment.start_address() + offset - segment.p_offset as usize).wrapping_add(bias)
+        })
+}
+
+// 按System V ABI构造初始的用户栈，从低到高依次是argc、argv、envp、辅助向量，
+// 最上面是字符串和随机字节。返回栈的内容和栈指针，栈指针按16字节对齐
+fn build_stack(
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(usize, usize)],
+) -> Result<(Vec<u8>, VirtualAddress), LoadError> {
+    let strings_size: usize =
+        argv.iter().chain(envp).map(|s| s.len() + 1).sum::<usize>() + RANDOM_BYTES;
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 2);
+    let size = align_up(strings_size + words * 8, 16);
+    if size > MAX_ARGUMENTS_SIZE {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+
+    let stack_pointer = USER_STACK_TOP - size;
+    let mut stack = vec![0u8; size];
+    let mut words_at = 0;
+    let mut strings_at = size - strings_size;
+
+    write_word(&mut stack, &mut words_at, argv.len());
+    for strings in [argv, envp] {
+        for s in strings {
+            let address = stack_pointer + strings_at;
+            // 字符串后面的0已经在清零的栈上了
+            write_bytes(&mut stack, &mut strings_at, s.as_bytes());
+            strings_at += 1;
+            write_word(&mut stack, &mut words_at, address);
+        }
+        write_word(&mut stack, &mut words_at, 0);
+    }
+    let random = stack_pointer + strings_at;
+    write_bytes(&mut stack, &mut strings_at, &random_bytes());
+    for &(key, value) in auxv.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        write_word(&mut stack, &mut words_at, key);
+        write_word(&mut stack, &mut words_at, value);
+    }
+    Ok((stack, stack_pointer))
+}
+
+fn write_word(stack: &mut [u8], at: &mut usize, value: usize) {
+    write_bytes(stack, at, &value.to_le_bytes());
+}
+
+fn write_bytes(stack: &mut [u8], at: &mut usize, bytes: &[u8]) {
+    stack[*at..*at + bytes.len()].copy_from_slice(bytes);
+    *at += bytes.len();
+}
+
+// 用户程序用来初始化栈保护等，没有更好的随机源，用时间戳计数器打散
+fn random_bytes() -> [u8; RANDOM_BYTES] {
+    let mut state = unsafe { core::arch::x86_64::_rdtsc() };
+    let mut bytes = [0; RANDOM_BYTES];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    bytes
+}
@@ -0,0 +1,708 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::{Mutex, Once};
+
+use crate::{
+    interrupts::{InterruptContext, kernel_stack, pit, set_kernel_stack},
+    memory::{
+        MEMORY_CONTROLLER, Stack,
+        allocator::slab_allocator::{ObjectCache, SlabBox},
+        paging::{self, VirtualAddress},
+    },
+    multiboot_info::MultibootInfo,
+    task::{
+        context::{Context, switch_context},
+        policy::{MAX_NICE, MIN_NICE, PRIORITY_LEVELS, Policies, SchedEntity, SchedulingPolicy},
+        process::{KERNEL_PID, ProcessId},
+    },
+    utils::x86_64_control::interrupts,
+};
+
+pub mod context;
+pub mod executor;
+pub mod loader;
+pub mod policy;
+pub mod process;
+pub mod sync;
+pub mod user;
+
+// 同时存在的任务数量上限，包括启动时的任务和空闲任务
+pub const MAX_TASKS: usize = 64;
+pub const TASK_STACK_PAGES: usize = 16;
+
+// 轮转调度时每个任务连续运行的时钟中断次数
+pub const QUANTUM_TICKS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    pub fn as_usize(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Ready,
+    Running,
+    // 等待其他任务或者中断调用wake
+    Blocked,
+    // 已经结束，等待其他任务回收它的栈
+    Dead,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: TaskState,
+    pub nice: i8,
+    pub priority: u8,
+    // 运行时经过的时钟中断次数
+    pub run_ticks: u64,
+    // 就绪后等待运行的时钟中断次数
+    pub wait_ticks: u64,
+    // 被切换到的次数
+    pub context_switches: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerStats {
+    pub policy: SchedulingPolicy,
+    pub tasks: usize,
+    pub context_switches: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TaskStats {
+    run_ticks: u64,
+    wait_ticks: u64,
+    context_switches: u64,
+}
+
+// 任务的入口，参数是存放返回值的位置
+type TaskEntry = Box<dyn FnOnce(&(dyn Any + Send + Sync)) + Send>;
+
+struct Task {
+    id: TaskId,
+    name: &'static str,
+    state: TaskState,
+    context: Context,
+    // 启动时的任务使用启动栈，没有Stack
+    stack: Option<Stack>,
+    // 任务在用户态时，中断和系统调用从这里开始使用内核栈
+    kernel_stack_top: VirtualAddress,
+    entry: Option<TaskEntry>,
+    // 由JoinHandle和任务共同持有，任务被回收时释放自己的引用
+    result: Option<Arc<dyn Any + Send + Sync>>,
+    // 在join中等待这个任务结束的任务
+    joiner: Option<TaskId>,
+    sched: SchedEntity,
+    stats: TaskStats,
+    // 最近一次变为就绪的时间
+    ready_since: u64,
+    // 阻塞时设置的超时时间，到了之后由时钟中断唤醒
+    wake_at: Option<u64>,
+    // 大于0时时钟中断不会切换走这个任务
+    preempt_disabled: usize,
+    // 所属的进程，页表由进程持有
+    process: ProcessId,
+    // 切换到这个任务时加载的CR3，内核线程使用内核页表
+    cr3: u64,
+    // 用户线程第一次进入用户态时恢复的寄存器
+    user_context: Option<InterruptContext>,
+}
+
+impl Task {
+    fn new(id: TaskId, name: &'static str, context: Context, stack: Option<Stack>) -> Task {
+        // 启动时的任务使用interrupts::init分配的栈
+        let kernel_stack_top = stack.as_ref().map_or_else(kernel_stack, Stack::top);
+        Task {
+            id,
+            name,
+            state: TaskState::Ready,
+            context,
+            stack,
+            kernel_stack_top,
+            entry: None,
+            result: None,
+            joiner: None,
+            sched: SchedEntity::new(id),
+            stats: TaskStats::default(),
+            ready_since: 0,
+            wake_at: None,
+            preempt_disabled: 0,
+            process: KERNEL_PID,
+            cr3: KERNEL_CR3.load(Ordering::SeqCst),
+            user_context: None,
+        }
+    }
+
+    // 调度策略通过sched中的id找到任务，两处要保持一致
+    fn set_id(&mut self, id: TaskId) {
+        self.id = id;
+        self.sched.id = id;
+    }
+
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.name,
+            state: self.state,
+            nice: self.sched.nice,
+            priority: self.sched.priority,
+            run_ticks: self.stats.run_ticks,
+            wait_ticks: self.stats.wait_ticks,
+            context_switches: self.stats.context_switches,
+        }
+    }
+}
+
+struct Scheduler {
+    tasks: [Option<SlabBox<Task>>; MAX_TASKS],
+    // 就绪的任务由当前的调度策略管理
+    policies: Policies,
+    current: TaskId,
+    // 没有其他任务可以运行时运行，不交给调度策略
+    idle: TaskId,
+    next_id: usize,
+    // 当前任务这次已经连续运行的时钟中断次数
+    slice_ticks: u64,
+    context_switches: u64,
+}
+
+// 调度器的锁也会在时钟中断中使用，只能在关中断时持有
+static SCHEDULER: Once<Mutex<Scheduler>> = Once::new();
+
+// 任务结构体都从这个缓存中分配
+static TASK_CACHE: ObjectCache<Task> = ObjectCache::new("task");
+
+// 内核线程使用的页表，也就是启动时的页表
+static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+
+fn find_task(tasks: &mut [Option<SlabBox<Task>>], id: TaskId) -> Option<&mut Task> {
+    tasks
+        .iter_mut()
+        .flatten()
+        .find(|t| t.id == id)
+        .map(|t| &mut **t)
+}
+
+impl Scheduler {
+    fn task(&self, id: TaskId) -> Option<&Task> {
+        self.tasks
+            .iter()
+            .flatten()
+            .find(|t| t.id == id)
+            .map(|t| &**t)
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> Option<&mut Task> {
+        find_task(&mut self.tasks, id)
+    }
+
+    fn current_task_mut(&mut self) -> &mut Task {
+        let current = self.current;
+        self.task_mut(current).expect("current task is missing")
+    }
+
+    fn allocate_id(&mut self) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    // queue为false时只登记，不交给调度策略
+    fn add(&mut self, task: SlabBox<Task>, queue: bool) -> Option<TaskId> {
+        let slot = self.tasks.iter_mut().find(|t| t.is_none())?;
+        let id = task.id;
+        *slot = Some(task);
+        if queue {
+            self.make_ready(id);
+        }
+        Some(id)
+    }
+
+    fn make_ready(&mut self, id: TaskId) {
+        let task = find_task(&mut self.tasks, id).expect("ready task is missing");
+        task.state = TaskState::Ready;
+        task.ready_since = pit::ticks();
+        task.wake_at = None;
+        self.policies.get().enqueue(&mut task.sched);
+    }
+
+    fn wake(&mut self, id: TaskId) {
+        if self.task(id).is_some_and(|t| t.state == TaskState::Blocked) {
+            self.make_ready(id);
+        }
+    }
+
+    // 就绪的任务要先从调度策略中取出，改完参数再放回去
+    fn update_sched<F>(&mut self, id: TaskId, f: F) -> bool
+    where
+        F: FnOnce(&mut SchedEntity),
+    {
+        let Some(task) = find_task(&mut self.tasks, id) else {
+            return false;
+        };
+        let queued = self.policies.get().dequeue(id);
+        f(&mut task.sched);
+        if queued {
+            self.policies.get().enqueue(&mut task.sched);
+        }
+        true
+    }
+
+    // 就绪的任务按原来的顺序转移到新的策略中
+    fn set_policy(&mut self, policy: SchedulingPolicy) {
+        let old = self.policies.current();
+        if old == policy {
+            return;
+        }
+        while let Some(id) = self.policies.get_policy(old).pick_next() {
+            let task = find_task(&mut self.tasks, id).expect("ready task is missing");
+            self.policies.get_policy(policy).enqueue(&mut task.sched);
+        }
+        self.policies.set_current(policy);
+    }
+
+    // 唤醒超时的任务
+    fn wake_expired(&mut self, now: u64) {
+        for i in 0..MAX_TASKS {
+            let expired = self.tasks[i].as_ref().is_some_and(|t| {
+                t.state == TaskState::Blocked && t.wake_at.is_some_and(|wake_at| wake_at <= now)
+            });
+            if expired {
+                let id = self.tasks[i].as_ref().unwrap().id;
+                self.make_ready(id);
+            }
+        }
+    }
+
+    // 时钟中断时记账，返回是否需要切换
+    fn tick(&mut self) -> bool {
+        self.wake_expired(pit::ticks());
+        self.slice_ticks += 1;
+        let is_idle = self.current == self.idle;
+        let task = find_task(&mut self.tasks, self.current).expect("current task is missing");
+        task.stats.run_ticks += 1;
+        let policy = self.policies.get();
+        if is_idle {
+            !policy.is_empty()
+        } else {
+            policy.tick(&mut task.sched, self.slice_ticks)
+        }
+    }
+
+    // 选出下一个任务，返回切换时需要的两个上下文。
+    // 任务在TASK_CACHE中，释放锁之后指针仍然有效
+    fn switch_to_next(&mut self) -> Option<(*mut Context, *const Context)> {
+        let current_state = self.current_task_mut().state;
+        let next = match self.policies.get().pick_next() {
+            Some(next) => next,
+            None if current_state == TaskState::Running => return None,
+            // 当前任务阻塞或者结束了，又没有其他任务
+            None => self.idle,
+        };
+        if next == self.current {
+            self.current_task_mut().state = TaskState::Running;
+            return None;
+        }
+
+        let (current, idle) = (self.current, self.idle);
+        if current_state == TaskState::Running {
+            if current == idle {
+                self.current_task_mut().state = TaskState::Ready;
+            } else {
+                self.make_ready(current);
+            }
+        }
+        let old = &raw mut self.current_task_mut().context;
+
+        self.current = next;
+        self.slice_ticks = 0;
+        self.context_switches += 1;
+        let next = self.current_task_mut();
+        next.state = TaskState::Running;
+        next.stats.context_switches += 1;
+        set_kernel_stack(next.kernel_stack_top);
+        paging::load_cr3(next.cr3);
+        // 空闲任务不经过调度策略，没有等待时间
+        if next.id != idle {
+            next.stats.wait_ticks += pit::ticks() - next.ready_since;
+        }
+        Some((old, &raw const next.context))
+    }
+
+    // 当前任务之外已经结束的任务
+    fn take_dead(&mut self) -> Option<SlabBox<Task>> {
+        let current = self.current;
+        self.tasks
+            .iter_mut()
+            .find(|t| {
+                t.as_ref()
+                    .is_some_and(|t| t.state == TaskState::Dead && t.id != current)
+            })?
+            .take()
+    }
+}
+
+fn scheduler() -> &'static Mutex<Scheduler> {
+    SCHEDULER.get().expect("task::init has not been called")
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Scheduler) -> R,
+{
+    interrupts::without_interrupts(|| f(&mut scheduler().lock()))
+}
+
+// 把当前的执行流登记为第一个任务，并创建空闲任务。
+// 默认使用轮转调度，可以用内核命令行中的scheduler=<name>选择
+pub fn init(boot_info: &MultibootInfo) {
+    let policy = SchedulingPolicy::from_command_line(boot_info.get_command_line())
+        .unwrap_or(SchedulingPolicy::RoundRobin);
+
+    let (main, idle) = {
+        let mut controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        let idle_stack = controller
+            .alloc_stack(4, "idle task")
+            .expect("could not allocate idle task stack");
+
+        let mut main = Task::new(TaskId(0), "main", Context::empty(), None);
+        main.state = TaskState::Running;
+        let idle_context = Context::new(idle_stack.top(), idle_task, 0);
+        let idle = Task::new(TaskId(1), "idle", idle_context, Some(idle_stack));
+        (
+            TASK_CACHE.alloc(main, &mut *controller),
+            TASK_CACHE.alloc(idle, &mut *controller),
+        )
+    };
+    let (Ok(main), Ok(idle)) = (main, idle) else {
+        panic!("could not allocate the initial tasks");
+    };
+    KERNEL_CR3.store(paging::active_cr3(), Ordering::SeqCst);
+
+    SCHEDULER.call_once(|| {
+        let mut scheduler = Scheduler {
+            tasks: [const { None }; MAX_TASKS],
+            // 预先分配好，之后调度时不再申请堆内存
+            policies: Policies::new(policy),
+            current: TaskId(0),
+            idle: TaskId(1),
+            next_id: 2,
+            slice_ticks: 0,
+            context_switches: 0,
+        };
+
+        scheduler.add(main, false);
+        scheduler.add(idle, false);
+        Mutex::new(scheduler)
+    });
+    process::init(&[TaskId(0), TaskId(1)]);
+}
+
+extern "C" fn idle_task(_argument: usize) -> ! {
+    loop {
+        yield_now();
+        // 等到下一个中断，唤醒任务的中断返回后就切换过去
+        interrupts::enable_and_hlt();
+    }
+}
+
+pub fn current_id() -> TaskId {
+    with_scheduler(|scheduler| scheduler.current)
+}
+
+pub fn current_name() -> &'static str {
+    with_scheduler(|scheduler| scheduler.current_task_mut().name)
+}
+
+pub fn task_info(id: TaskId) -> Option<TaskInfo> {
+    with_scheduler(|scheduler| scheduler.task(id).map(Task::info))
+}
+
+pub fn stats() -> SchedulerStats {
+    with_scheduler(|scheduler| SchedulerStats {
+        policy: scheduler.policies.current(),
+        tasks: scheduler.tasks.iter().flatten().count(),
+        context_switches: scheduler.context_switches,
+    })
+}
+
+pub fn policy() -> SchedulingPolicy {
+    with_scheduler(|scheduler| scheduler.policies.current())
+}
+
+pub fn set_policy(policy: SchedulingPolicy) {
+    with_scheduler(|scheduler| scheduler.set_policy(policy));
+}
+
+// nice越小分到的CPU时间越多，只影响公平调度。任务不存在时返回false
+pub fn set_nice(id: TaskId, nice: i8) -> bool {
+    assert!(
+        (MIN_NICE..=MAX_NICE).contains(&nice),
+        "nice {} out of range",
+        nice
+    );
+    with_scheduler(|scheduler| scheduler.update_sched(id, |sched| sched.nice = nice))
+}
+
+// 优先级越大越先运行，只影响固定优先级调度。任务不存在时返回false
+pub fn set_priority(id: TaskId, priority: u8) -> bool {
+    assert!(
+        (priority as usize) < PRIORITY_LEVELS,
+        "priority {} out of range",
+        priority
+    );
+    with_scheduler(|scheduler| scheduler.update_sched(id, |sched| sched.priority = priority))
+}
+
+pub struct JoinHandle<T> {
+    id: TaskId,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    // 阻塞到任务结束并被回收，任务调用exit提前结束时返回None
+    pub fn join(self) -> Option<T> {
+        loop {
+            let finished = with_scheduler(|scheduler| {
+                let current = scheduler.current;
+                match scheduler.task_mut(self.id) {
+                    None => true,
+                    // 已经结束，切换出去之后就会被回收
+                    Some(task) if task.state == TaskState::Dead => false,
+                    Some(task) => {
+                        task.joiner = Some(current);
+                        scheduler.current_task_mut().state = TaskState::Blocked;
+                        false
+                    }
+                }
+            });
+            if finished {
+                return self.result.lock().take();
+            }
+            switch_away();
+        }
+    }
+}
+
+// 创建一个内核线程，它在当前任务让出CPU或者被抢占之后才开始运行
+pub fn spawn<F, T>(name: &'static str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None::<T>));
+    let entry: TaskEntry = Box::new(move |slot| {
+        let value = f();
+        let slot = slot.downcast_ref::<Mutex<Option<T>>>().unwrap();
+        *slot.lock() = Some(value);
+    });
+
+    let stack = MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .alloc_stack(TASK_STACK_PAGES, name)
+        .expect("could not allocate task stack");
+    let context = Context::new(stack.top(), task_entry, 0);
+
+    let mut task = Task::new(TaskId(0), name, context, Some(stack));
+    task.entry = Some(entry);
+    task.result = Some(result.clone());
+    let id = add_task(task).expect("too many tasks");
+    JoinHandle { id, result }
+}
+
+// 新任务先登记到所属的进程，再交给调度器。
+// 任务表满或者任务缓存申请不到内存时释放任务的栈并返回None
+fn add_task(task: Task) -> Option<TaskId> {
+    let task = TASK_CACHE.alloc(task, &mut *MEMORY_CONTROLLER.get().unwrap().lock());
+    let mut task = match task {
+        Ok(task) => task,
+        Err(mut task) => {
+            free_task_stack(&mut task);
+            return None;
+        }
+    };
+    let id = with_scheduler(|scheduler| scheduler.allocate_id());
+    task.set_id(id);
+    process::add_thread(task.process, id);
+    let rejected = with_scheduler(|scheduler| {
+        if scheduler.tasks.iter().all(Option::is_some) {
+            return Some(task);
+        }
+        scheduler.add(task, true);
+        None
+    });
+    if let Some(mut task) = rejected {
+        free_task_stack(&mut task);
+        return None;
+    }
+    Some(id)
+}
+
+fn free_task_stack(task: &mut Task) {
+    if let Some(stack) = task.stack.take() {
+        MEMORY_CONTROLLER.get().unwrap().lock().free_stack(stack);
+    }
+}
+
+// 为用户进程创建一个线程，它使用进程的页表，从context返回Ring3。
+// 任务表满时返回None
+fn spawn_user(
+    name: &'static str,
+    process: ProcessId,
+    cr3: u64,
+    context: InterruptContext,
+) -> Option<TaskId> {
+    let stack = MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .alloc_stack(TASK_STACK_PAGES, name)?;
+    let context_switch = Context::new(stack.top(), user_task_entry, 0);
+
+    let mut task = Task::new(TaskId(0), name, context_switch, Some(stack));
+    task.process = process;
+    task.cr3 = cr3;
+    task.user_context = Some(context);
+    add_task(task)
+}
+
+// 切换到用户线程时已经加载了进程的页表
+extern "C" fn user_task_entry(_argument: usize) -> ! {
+    reap_dead_tasks();
+
+    let context =
+        with_scheduler(|scheduler| scheduler.current_task_mut().user_context.take().unwrap());
+    user::resume_user_mode(&context)
+}
+
+extern "C" fn task_entry(_argument: usize) -> ! {
+    // 新任务不是从schedule中返回的，在这里回收已经结束的任务
+    reap_dead_tasks();
+
+    let (entry, slot) = with_scheduler(|scheduler| {
+        let task = scheduler.current_task_mut();
+        let slot = &**task.result.as_ref().unwrap() as *const (dyn Any + Send + Sync);
+        (task.entry.take().unwrap(), slot)
+    });
+    // 任务被回收之前slot一直有效
+    entry(unsafe { &*slot });
+    exit()
+}
+
+pub fn yield_now() {
+    switch_away();
+}
+
+// 结束当前任务，不会返回，当前任务栈上的值不会被释放
+pub fn exit() -> ! {
+    with_scheduler(|scheduler| {
+        let task = scheduler.current_task_mut();
+        task.state = TaskState::Dead;
+        if let Some(joiner) = task.joiner.take() {
+            scheduler.wake(joiner);
+        }
+    });
+    switch_away();
+    unreachable!("dead task was scheduled again");
+}
+
+// 阻塞当前任务，直到其他任务或者中断处理程序调用wake。
+// 可能被提前唤醒，调用者要自己检查等待的条件
+pub fn block_current() {
+    prepare_to_block(None);
+    switch_away();
+}
+
+// 把当前任务标记为阻塞，deadline之后由时钟中断唤醒。
+// 调用switch_away之后才真正切换出去，在这之前被wake时不会切换
+fn prepare_to_block(deadline: Option<u64>) {
+    with_scheduler(|scheduler| {
+        let task = scheduler.current_task_mut();
+        task.state = TaskState::Blocked;
+        task.wake_at = deadline;
+    });
+}
+
+// 睡眠ticks个时钟中断
+pub fn sleep(ticks: u64) {
+    let deadline = pit::ticks() + ticks;
+    while pit::ticks() < deadline {
+        prepare_to_block(Some(deadline));
+        switch_away();
+    }
+}
+
+pub fn wake(id: TaskId) {
+    with_scheduler(|scheduler| scheduler.wake(id));
+}
+
+// 在f执行期间当前任务只在主动让出CPU时被切换走，
+// 计数属于当前任务，f中阻塞或者让出CPU不影响其他任务被抢占
+pub fn without_preemption<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    with_scheduler(|scheduler| scheduler.current_task_mut().preempt_disabled += 1);
+    let result = f();
+    with_scheduler(|scheduler| scheduler.current_task_mut().preempt_disabled -= 1);
+    result
+}
+
+// 由时钟中断调用，这时中断是关闭的。
+// 不能在这里回收任务，被中断的任务可能持有MemoryController的锁
+pub fn timer_tick() {
+    let Some(scheduler) = SCHEDULER.get() else {
+        return;
+    };
+    let preempt = {
+        let mut scheduler = scheduler.lock();
+        scheduler.tick() && scheduler.current_task_mut().preempt_disabled == 0
+    };
+    if preempt {
+        schedule();
+    }
+}
+
+// 主动切换，回到这里之后回收结束的任务
+fn switch_away() {
+    schedule();
+    reap_dead_tasks();
+}
+
+fn schedule() {
+    interrupts::without_interrupts(|| {
+        let switch = scheduler().lock().switch_to_next();
+        if let Some((old, new)) = switch {
+            // 切换回来时popfq恢复的仍然是关中断的状态
+            unsafe { switch_context(old, new) };
+        }
+    });
+}
+
+// 不能在任务自己的栈上释放它，由之后运行的任务回收
+fn reap_dead_tasks() {
+    loop {
+        let task = with_scheduler(|scheduler| scheduler.take_dead());
+        let Some(mut task) = task else {
+            return;
+        };
+        free_task_stack(&mut task);
+        process::thread_reaped(task.process, task.id);
+    }
+}
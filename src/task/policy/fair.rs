@@ -0,0 +1,112 @@
+use crate::task::{
+    MAX_TASKS, TaskId,
+    policy::{MIN_NICE, Policy, SchedEntity},
+};
+
+// nice为-20到19时的权重，相邻两级的CPU时间相差约25%，和Linux相同
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+const NICE_0_WEIGHT: u64 = 1024;
+
+// nice为0的任务运行一个时钟中断增加的vruntime
+const NICE_0_TICK: u64 = 1024;
+
+// 当前任务的vruntime比最小的超出这么多时才切换，避免频繁切换
+const GRANULARITY: u64 = NICE_0_TICK;
+
+pub fn nice_to_weight(nice: i8) -> u64 {
+    NICE_TO_WEIGHT[(nice - MIN_NICE) as usize]
+}
+
+#[derive(Clone, Copy)]
+struct ReadyEntry {
+    id: TaskId,
+    vruntime: u64,
+    // 按就绪的顺序打破vruntime相同的情况
+    sequence: u64,
+}
+
+// 按权重分配CPU时间：每次运行vruntime最小的任务，
+// vruntime按运行时间除以权重增长，所以每个任务都会轮到
+pub struct FairPolicy {
+    ready: [Option<ReadyEntry>; MAX_TASKS],
+    // 单调增加，唤醒的任务从这里开始，不能用睡眠期间积累的时间抢占其他任务
+    min_vruntime: u64,
+    next_sequence: u64,
+}
+
+impl FairPolicy {
+    pub fn new() -> FairPolicy {
+        FairPolicy {
+            ready: [None; MAX_TASKS],
+            min_vruntime: 0,
+            next_sequence: 0,
+        }
+    }
+
+    fn leftmost(&self) -> Option<(usize, ReadyEntry)> {
+        self.ready
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| entry.map(|entry| (i, entry)))
+            .min_by_key(|(_, entry)| (entry.vruntime, entry.sequence))
+    }
+
+    fn update_min_vruntime(&mut self, current: Option<u64>) {
+        let leftmost = self.leftmost().map(|(_, entry)| entry.vruntime);
+        let Some(candidate) = current.into_iter().chain(leftmost).min() else {
+            return;
+        };
+        self.min_vruntime = self.min_vruntime.max(candidate);
+    }
+}
+
+impl Policy for FairPolicy {
+    fn enqueue(&mut self, entity: &mut SchedEntity) {
+        entity.vruntime = entity
+            .vruntime
+            .max(self.min_vruntime.saturating_sub(GRANULARITY));
+        let entry = ReadyEntry {
+            id: entity.id,
+            vruntime: entity.vruntime,
+            sequence: self.next_sequence,
+        };
+        self.next_sequence += 1;
+        let slot = self
+            .ready
+            .iter_mut()
+            .find(|e| e.is_none())
+            .expect("too many ready tasks");
+        *slot = Some(entry);
+    }
+
+    fn dequeue(&mut self, id: TaskId) -> bool {
+        let slot = self
+            .ready
+            .iter_mut()
+            .find(|e| e.is_some_and(|e| e.id == id));
+        slot.and_then(|slot| slot.take()).is_some()
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        let (i, entry) = self.leftmost()?;
+        self.ready[i] = None;
+        self.update_min_vruntime(Some(entry.vruntime));
+        Some(entry.id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.iter().all(Option::is_none)
+    }
+
+    fn tick(&mut self, current: &mut SchedEntity, _slice_ticks: u64) -> bool {
+        current.vruntime += NICE_0_TICK * NICE_0_WEIGHT / nice_to_weight(current.nice);
+        self.update_min_vruntime(Some(current.vruntime));
+        self.leftmost()
+            .is_some_and(|(_, entry)| current.vruntime > entry.vruntime + GRANULARITY)
+    }
+}
@@ -0,0 +1,59 @@
+use alloc::collections::VecDeque;
+
+use crate::task::{
+    MAX_TASKS, QUANTUM_TICKS, TaskId,
+    policy::{PRIORITY_LEVELS, Policy, SchedEntity},
+};
+
+// 总是运行优先级最高的就绪任务，同一优先级之间轮流运行。
+// 高优先级的任务一直就绪时，低优先级的任务得不到运行
+pub struct FixedPriorityPolicy {
+    queues: [VecDeque<TaskId>; PRIORITY_LEVELS],
+}
+
+impl FixedPriorityPolicy {
+    pub fn new() -> FixedPriorityPolicy {
+        FixedPriorityPolicy {
+            queues: core::array::from_fn(|_| VecDeque::with_capacity(MAX_TASKS)),
+        }
+    }
+
+    // 就绪任务中最高的优先级
+    fn highest_ready(&self) -> Option<usize> {
+        self.queues.iter().rposition(|queue| !queue.is_empty())
+    }
+}
+
+impl Policy for FixedPriorityPolicy {
+    fn enqueue(&mut self, entity: &mut SchedEntity) {
+        self.queues[entity.priority as usize].push_back(entity.id);
+    }
+
+    fn dequeue(&mut self, id: TaskId) -> bool {
+        for queue in self.queues.iter_mut() {
+            if let Some(i) = queue.iter().position(|&t| t == id) {
+                queue.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        let level = self.highest_ready()?;
+        self.queues[level].pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.highest_ready().is_none()
+    }
+
+    fn tick(&mut self, current: &mut SchedEntity, slice_ticks: u64) -> bool {
+        let current = current.priority as usize;
+        match self.highest_ready() {
+            Some(level) if level > current => true,
+            Some(level) if level == current => slice_ticks >= QUANTUM_TICKS,
+            _ => false,
+        }
+    }
+}
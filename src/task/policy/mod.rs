@@ -0,0 +1,132 @@
+use crate::task::TaskId;
+
+pub mod fair;
+pub mod fixed_priority;
+pub mod round_robin;
+
+pub use self::{
+    fair::FairPolicy, fixed_priority::FixedPriorityPolicy, round_robin::RoundRobinPolicy,
+};
+
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+
+// 优先级越大越先运行
+pub const PRIORITY_LEVELS: usize = 8;
+pub const DEFAULT_PRIORITY: u8 = 4;
+
+// 调度策略需要的任务参数，保存在任务中
+#[derive(Debug, Clone, Copy)]
+pub struct SchedEntity {
+    pub id: TaskId,
+    pub nice: i8,
+    pub priority: u8,
+    // 按nice换算后的运行时间，只由FairPolicy使用
+    pub vruntime: u64,
+}
+
+impl SchedEntity {
+    pub fn new(id: TaskId) -> SchedEntity {
+        SchedEntity {
+            id,
+            nice: 0,
+            priority: DEFAULT_PRIORITY,
+            vruntime: 0,
+        }
+    }
+}
+
+// 调度策略只管理就绪的任务，正在运行的任务和空闲任务不在其中。
+// 在时钟中断中也会调用，实现不能申请堆内存
+pub trait Policy {
+    // 任务变为就绪状态
+    fn enqueue(&mut self, entity: &mut SchedEntity);
+
+    // 把就绪的任务移出，参数改变或者切换策略时使用
+    fn dequeue(&mut self, id: TaskId) -> bool;
+
+    // 取出下一个要运行的任务
+    fn pick_next(&mut self) -> Option<TaskId>;
+
+    fn is_empty(&self) -> bool;
+
+    // 当前任务又运行了一个时钟中断，slice_ticks是它这次连续运行的时间，
+    // 返回是否应该切换到其他任务
+    fn tick(&mut self, current: &mut SchedEntity, slice_ticks: u64) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SchedulingPolicy {
+    RoundRobin,
+    FixedPriority,
+    Fair,
+}
+
+pub const SCHEDULING_POLICIES: [SchedulingPolicy; 3] = [
+    SchedulingPolicy::RoundRobin,
+    SchedulingPolicy::FixedPriority,
+    SchedulingPolicy::Fair,
+];
+
+impl SchedulingPolicy {
+    pub fn from_name(name: &str) -> Option<SchedulingPolicy> {
+        SCHEDULING_POLICIES.into_iter().find(|p| p.name() == name)
+    }
+
+    // 内核命令行中的scheduler=<name>
+    pub fn from_command_line(command_line: &str) -> Option<SchedulingPolicy> {
+        command_line
+            .split_whitespace()
+            .find_map(|arg| arg.strip_prefix("scheduler="))
+            .and_then(SchedulingPolicy::from_name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedulingPolicy::RoundRobin => "round_robin",
+            SchedulingPolicy::FixedPriority => "fixed_priority",
+            SchedulingPolicy::Fair => "fair",
+        }
+    }
+}
+
+// 所有策略都预先创建好，切换策略时不需要申请内存
+pub struct Policies {
+    current: SchedulingPolicy,
+    round_robin: RoundRobinPolicy,
+    fixed_priority: FixedPriorityPolicy,
+    fair: FairPolicy,
+}
+
+impl Policies {
+    pub fn new(current: SchedulingPolicy) -> Policies {
+        Policies {
+            current,
+            round_robin: RoundRobinPolicy::new(),
+            fixed_priority: FixedPriorityPolicy::new(),
+            fair: FairPolicy::new(),
+        }
+    }
+
+    pub fn current(&self) -> SchedulingPolicy {
+        self.current
+    }
+
+    pub fn get(&mut self) -> &mut dyn Policy {
+        self.get_policy(self.current)
+    }
+
+    pub fn get_policy(&mut self, policy: SchedulingPolicy) -> &mut dyn Policy {
+        match policy {
+            SchedulingPolicy::RoundRobin => &mut self.round_robin,
+            SchedulingPolicy::FixedPriority => &mut self.fixed_priority,
+            SchedulingPolicy::Fair => &mut self.fair,
+        }
+    }
+
+    // 调用前要把就绪的任务从原来的策略中转移出来
+    pub fn set_current(&mut self, policy: SchedulingPolicy) {
+        self.current = policy;
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::collections::VecDeque;
+
+use crate::task::{
+    MAX_TASKS, QUANTUM_TICKS, TaskId,
+    policy::{Policy, SchedEntity},
+};
+
+// 按就绪的顺序轮流运行，每次最多运行QUANTUM_TICKS个时钟中断
+pub struct RoundRobinPolicy {
+    queue: VecDeque<TaskId>,
+}
+
+impl RoundRobinPolicy {
+    pub fn new() -> RoundRobinPolicy {
+        RoundRobinPolicy {
+            queue: VecDeque::with_capacity(MAX_TASKS),
+        }
+    }
+}
+
+impl Policy for RoundRobinPolicy {
+    fn enqueue(&mut self, entity: &mut SchedEntity) {
+        self.queue.push_back(entity.id);
+    }
+
+    fn dequeue(&mut self, id: TaskId) -> bool {
+        let position = self.queue.iter().position(|&t| t == id);
+        position.and_then(|i| self.queue.remove(i)).is_some()
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        self.queue.pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn tick(&mut self, _current: &mut SchedEntity, slice_ticks: u64) -> bool {
+        slice_ticks >= QUANTUM_TICKS && !self.queue.is_empty()
+    }
+}
@@ -0,0 +1,413 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    interrupts::InterruptContext,
+    memory::{
+        MEMORY_CONTROLLER,
+        allocator::slab_allocator::{ObjectCache, SlabBox},
+        paging::{InactivePageTable, VirtualAddress},
+    },
+    task::{self, TaskId, loader::UserImage, sync::WaitQueue},
+};
+
+// 同时存在的进程数量上限，包括还没有被回收的僵尸进程
+pub const MAX_PROCESSES: usize = 32;
+// 每个进程最多打开的句柄数量
+pub const MAX_HANDLES: usize = 16;
+
+// 内核线程都属于内核进程，它没有自己的页表，也不会退出
+pub const KERNEL_PID: ProcessId = ProcessId(0);
+
+// 因为异常被结束的进程的退出码
+pub const KILLED_STATUS: i32 = -1;
+
+// mmap的地址为0时从这里开始找空闲的范围
+pub const MMAP_BASE: VirtualAddress = 0x1000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProcessId(usize);
+
+impl ProcessId {
+    pub const fn new(id: usize) -> ProcessId {
+        ProcessId(id)
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Credentials {
+    pub const ROOT: Credentials = Credentials { uid: 0, gid: 0 };
+}
+
+// 文件描述符指向的对象
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handle {
+    // 屏幕
+    Console,
+    // 串口
+    Serial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    // 所有线程都已经结束，等待父进程用wait取走退出码
+    Zombie(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    TooManyProcesses,
+    OutOfMemory,
+    // 内核进程没有用户地址空间，不能fork
+    KernelProcess,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub id: ProcessId,
+    pub parent: Option<ProcessId>,
+    pub name: &'static str,
+    pub state: ProcessState,
+    pub threads: usize,
+    pub credentials: Credentials,
+}
+
+struct Process {
+    id: ProcessId,
+    // 父进程结束后变为None，这样的进程退出时直接回收
+    parent: Option<ProcessId>,
+    name: &'static str,
+    state: ProcessState,
+    // 用户地址空间，最后一个线程被回收时释放
+    page_table: Option<SlabBox<InactivePageTable>>,
+    threads: Vec<TaskId>,
+    handles: [Option<Handle>; MAX_HANDLES],
+    credentials: Credentials,
+    // 最近一次exit的参数
+    exit_status: i32,
+    // 下一次mmap由内核选择地址时从这里开始找，只在映射成功后前移
+    mmap_hint: VirtualAddress,
+}
+
+impl Process {
+    fn info(&self) -> ProcessInfo {
+        ProcessInfo {
+            id: self.id,
+            parent: self.parent,
+            name: self.name,
+            state: self.state,
+            threads: self.threads.len(),
+            credentials: self.credentials,
+        }
+    }
+}
+
+struct ProcessTable {
+    processes: BTreeMap<ProcessId, Process>,
+    next_id: usize,
+}
+
+impl ProcessTable {
+    fn get_mut(&mut self, id: ProcessId) -> &mut Process {
+        self.processes.get_mut(&id).expect("process is missing")
+    }
+
+    // 子进程继承父进程的名字、句柄、身份和mmap的起始地址
+    fn create_child(
+        &mut self,
+        parent: ProcessId,
+        name: &'static str,
+        page_table: SlabBox<InactivePageTable>,
+    ) -> Result<ProcessId, SlabBox<InactivePageTable>> {
+        if self.processes.len() >= MAX_PROCESSES {
+            return Err(page_table);
+        }
+        let (handles, credentials, mmap_hint) = {
+            let parent = &self.processes[&parent];
+            (parent.handles, parent.credentials, parent.mmap_hint)
+        };
+        let id = ProcessId(self.next_id);
+        self.next_id += 1;
+        self.processes.insert(
+            id,
+            Process {
+                id,
+                parent: Some(parent),
+                name,
+                state: ProcessState::Running,
+                page_table: Some(page_table),
+                threads: Vec::new(),
+                handles,
+                credentials,
+                exit_status: 0,
+                mmap_hint,
+            },
+        );
+        Ok(id)
+    }
+
+    // 结束的子进程直接回收，其余的子进程不再有父进程
+    fn orphan_children(&mut self, parent: ProcessId) {
+        self.processes.retain(|_, process| {
+            if process.parent != Some(parent) {
+                return true;
+            }
+            process.parent = None;
+            process.state == ProcessState::Running
+        });
+    }
+}
+
+// 进程的页表信息都从这个缓存中分配
+static PAGE_TABLE_CACHE: ObjectCache<InactivePageTable> = ObjectCache::new("page_table");
+
+// 有进程变为僵尸时加1，wait用它判断检查进程表之后是否又有进程结束
+static EXITED: AtomicUsize = AtomicUsize::new(0);
+// 在wait中等待子进程结束的线程。进程表插入时会移动Process，所以队列不放在Process中
+static CHILD_EXITED: WaitQueue = WaitQueue::new();
+
+// 只在任务中使用，不需要关中断。通过with_processes访问
+static PROCESSES: Mutex<ProcessTable> = Mutex::new(ProcessTable {
+    processes: BTreeMap::new(),
+    next_id: 1,
+});
+
+// 持有锁时不能被抢占，否则优先级更高的任务会一直自旋等待被抢占的任务
+fn with_processes<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessTable) -> R,
+{
+    task::without_preemption(|| f(&mut PROCESSES.lock()))
+}
+
+// 创建内核进程，启动时的任务和空闲任务都属于它。由task::init调用
+pub(super) fn init(threads: &[TaskId]) {
+    let mut handles = [None; MAX_HANDLES];
+    handles[1] = Some(Handle::Console);
+    handles[2] = Some(Handle::Serial);
+    let kernel = Process {
+        id: KERNEL_PID,
+        parent: None,
+        name: "kernel",
+        state: ProcessState::Running,
+        page_table: None,
+        threads: threads.to_vec(),
+        handles,
+        credentials: Credentials::ROOT,
+        exit_status: 0,
+        mmap_hint: MMAP_BASE,
+    };
+    with_processes(|table| table.processes.insert(KERNEL_PID, kernel));
+}
+
+// 新线程在交给调度器之前登记到进程中
+pub(super) fn add_thread(id: ProcessId, thread: TaskId) {
+    with_processes(|table| table.get_mut(id).threads.push(thread));
+}
+
+// 由回收任务的一方调用，这时线程的栈和页表都不再使用。
+// 最后一个线程被回收后进程变为僵尸，并释放它的地址空间
+pub(super) fn thread_reaped(id: ProcessId, thread: TaskId) {
+    let page_table = with_processes(|table| {
+        let process = table.get_mut(id);
+        process.threads.retain(|&t| t != thread);
+        if id == KERNEL_PID || !process.threads.is_empty() {
+            return None;
+        }
+        process.state = ProcessState::Zombie(process.exit_status);
+        process.handles = [None; MAX_HANDLES];
+        let page_table = process.page_table.take().map(SlabBox::into_inner);
+        let parent = process.parent;
+
+        table.orphan_children(id);
+        if parent.is_none() {
+            table.processes.remove(&id);
+        }
+        Some(page_table)
+    });
+    let Some(page_table) = page_table else {
+        return;
+    };
+    // 先改状态再唤醒，等待的一方重新检查进程表
+    EXITED.fetch_add(1, Ordering::SeqCst);
+    CHILD_EXITED.notify_all();
+    if let Some(page_table) = page_table {
+        free_address_space(page_table);
+    }
+}
+
+// 页表在丢弃时还要锁住MemoryController，不能在持有它的锁时丢弃
+fn free_address_space(mut page_table: InactivePageTable) {
+    page_table.free_user_frames(&mut *MEMORY_CONTROLLER.get().unwrap().lock());
+}
+
+pub fn current_id() -> ProcessId {
+    task::with_scheduler(|scheduler| scheduler.current_task_mut().process)
+}
+
+pub fn process_info(id: ProcessId) -> Option<ProcessInfo> {
+    with_processes(|table| table.processes.get(&id).map(Process::info))
+}
+
+pub fn credentials() -> Credentials {
+    let id = current_id();
+    with_processes(|table| table.get_mut(id).credentials)
+}
+
+// 当前进程中fd对应的句柄
+pub fn handle(fd: usize) -> Option<Handle> {
+    let id = current_id();
+    with_processes(|table| *table.get_mut(id).handles.get(fd)?)
+}
+
+pub fn mmap_hint() -> VirtualAddress {
+    let id = current_id();
+    with_processes(|table| table.get_mut(id).mmap_hint)
+}
+
+pub fn set_mmap_hint(address: VirtualAddress) {
+    let id = current_id();
+    with_processes(|table| table.get_mut(id).mmap_hint = address);
+}
+
+// 关闭fd，没有打开时返回false
+pub fn close(fd: usize) -> bool {
+    let id = current_id();
+    with_processes(|table| {
+        table
+            .get_mut(id)
+            .handles
+            .get_mut(fd)
+            .and_then(Option::take)
+            .is_some()
+    })
+}
+
+// 在新的进程中运行加载好的程序，新进程是当前进程的子进程
+pub fn spawn(name: &'static str, image: UserImage) -> Result<ProcessId, ProcessError> {
+    let (page_table, entry, stack_pointer) = image.into_parts();
+    let context = InterruptContext {
+        instruction_pointer: entry as u64,
+        stack_pointer: stack_pointer as u64,
+        ..Default::default()
+    };
+    start(name, page_table, context)
+}
+
+// 复制当前进程，子进程从context返回用户态，返回值为0。
+// 用户空间的页在父子进程之间写时复制，context是当前线程进入内核时保存的寄存器
+pub fn fork(context: &InterruptContext) -> Result<ProcessId, ProcessError> {
+    let id = current_id();
+    if id == KERNEL_PID {
+        return Err(ProcessError::KernelProcess);
+    }
+    let name = with_processes(|table| table.get_mut(id).name);
+
+    let (page_table, shared) = {
+        let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        let mut page_table = memory_controller
+            .new_page_table()
+            .ok_or(ProcessError::OutOfMemory)?;
+        let shared = memory_controller
+            .share_user_pages(&mut page_table)
+            .is_some();
+        (page_table, shared)
+    };
+    if !shared {
+        free_address_space(page_table);
+        return Err(ProcessError::OutOfMemory);
+    }
+
+    let mut context = context.clone();
+    context.rax = 0;
+    start(name, page_table, context)
+}
+
+// 创建当前进程的子进程和它的第一个线程，失败时释放page_table
+fn start(
+    name: &'static str,
+    page_table: InactivePageTable,
+    context: InterruptContext,
+) -> Result<ProcessId, ProcessError> {
+    let cr3 = page_table.cr3_value();
+    let page_table =
+        PAGE_TABLE_CACHE.alloc(page_table, &mut *MEMORY_CONTROLLER.get().unwrap().lock());
+    let page_table = match page_table {
+        Ok(page_table) => page_table,
+        Err(page_table) => {
+            free_address_space(page_table);
+            return Err(ProcessError::OutOfMemory);
+        }
+    };
+    let parent = current_id();
+    let id = with_processes(|table| table.create_child(parent, name, page_table));
+    let id = match id {
+        Ok(id) => id,
+        Err(page_table) => {
+            free_address_space(SlabBox::into_inner(page_table));
+            return Err(ProcessError::TooManyProcesses);
+        }
+    };
+    if task::spawn_user(name, id, cr3, context).is_none() {
+        let process = with_processes(|table| table.processes.remove(&id).unwrap());
+        free_address_space(SlabBox::into_inner(process.page_table.unwrap()));
+        return Err(ProcessError::TooManyProcesses);
+    }
+    Ok(id)
+}
+
+// 结束当前线程，status作为进程的退出码。
+// 进程在最后一个线程被回收后变为僵尸，内核进程不会退出
+pub fn exit(status: i32) -> ! {
+    let id = current_id();
+    with_processes(|table| table.get_mut(id).exit_status = status);
+    task::exit()
+}
+
+// 等待子进程结束并回收它，返回它的进程号和退出码。
+// pid为None时等待任意一个子进程，没有符合条件的子进程时返回None
+pub fn wait(pid: Option<ProcessId>) -> Option<(ProcessId, i32)> {
+    let id = current_id();
+    loop {
+        // 在检查进程表之前读取，之后结束的子进程会让计数变化，不会错过唤醒
+        let exited = EXITED.load(Ordering::SeqCst);
+        let result = with_processes(|table| {
+            let mut found = false;
+            let mut zombie = None;
+            for child in table
+                .processes
+                .values()
+                .filter(|p| p.parent == Some(id) && pid.is_none_or(|pid| p.id == pid))
+            {
+                found = true;
+                if let ProcessState::Zombie(status) = child.state {
+                    zombie = Some((child.id, status));
+                    break;
+                }
+            }
+            if let Some((child, _)) = zombie {
+                table.processes.remove(&child);
+                return Some(zombie);
+            }
+            (!found).then_some(None)
+        });
+        // 有结束的子进程或者没有符合条件的子进程
+        if let Some(result) = result {
+            return result;
+        }
+        // 不能持有PROCESSES的锁阻塞，否则唤醒的一方拿不到锁
+        CHILD_EXITED.wait_until(|| EXITED.load(Ordering::SeqCst) != exited, None);
+    }
+}
@@ -0,0 +1,74 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    interrupts::pit,
+    task::sync::{MutexGuard, WaitQueue},
+};
+
+// 和sync::Mutex一起使用的条件变量，可能被提前唤醒，调用者要检查条件
+pub struct Condvar {
+    // 每次notify加1，等待的任务看到变化就返回
+    sequence: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            sequence: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    // 释放锁并阻塞，被唤醒后重新加锁
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_timeout(guard, None).0
+    }
+
+    // 超时返回的第二个值为true
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<u64>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex();
+        // 在释放锁之前读取，释放之后的notify不会被错过
+        let sequence = self.sequence.load(Ordering::SeqCst);
+        drop(guard);
+        let notified = self
+            .waiters
+            .wait_until(|| self.sequence.load(Ordering::SeqCst) != sequence, timeout);
+        (mutex.lock(), !notified)
+    }
+
+    // 等到condition不成立，超时返回的第二个值为true
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+        timeout: Option<u64>,
+    ) -> (MutexGuard<'a, T>, bool)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let deadline = timeout.map(|ticks| pit::ticks() + ticks);
+        while condition(&mut guard) {
+            let remaining = deadline.map(|deadline| deadline.saturating_sub(pit::ticks()));
+            if remaining == Some(0) {
+                return (guard, true);
+            }
+            guard = self.wait_timeout(guard, remaining).0;
+        }
+        (guard, false)
+    }
+
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.waiters.notify_all();
+    }
+}
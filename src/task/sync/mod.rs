@@ -0,0 +1,16 @@
+// 会让任务睡眠的同步原语，超时都以时钟中断为单位。
+// 只有WaitQueue::notify和Semaphore::release可以在中断处理程序中调用
+
+pub mod condvar;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod wait_queue;
+
+pub use self::{
+    condvar::Condvar,
+    mutex::{Mutex, MutexGuard},
+    rwlock::RwLock,
+    semaphore::Semaphore,
+    wait_queue::WaitQueue,
+};
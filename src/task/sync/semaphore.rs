@@ -0,0 +1,58 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{interrupts::pit, task::sync::WaitQueue};
+
+// 计数信号量。release不会阻塞，中断处理程序可以用它通知等待的任务
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.waiters
+                .wait_until(|| self.count.load(Ordering::SeqCst) > 0, None);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    // 最多等待timeout个时钟中断，超时返回false
+    pub fn acquire_timeout(&self, timeout: u64) -> bool {
+        let deadline = pit::ticks() + timeout;
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+            let remaining = deadline.saturating_sub(pit::ticks());
+            if remaining == 0 {
+                return false;
+            }
+            self.waiters
+                .wait_until(|| self.count.load(Ordering::SeqCst) > 0, Some(remaining));
+        }
+    }
+
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,126 @@
+use spin::Mutex;
+
+use crate::{
+    interrupts::pit,
+    task::{self, MAX_TASKS, TaskId},
+    utils::x86_64_control::interrupts,
+};
+
+// 按等待顺序排列的任务，每个任务同时只在一个队列中等待，所以不会超过MAX_TASKS
+struct Waiters {
+    ids: [Option<TaskId>; MAX_TASKS],
+    len: usize,
+}
+
+impl Waiters {
+    fn push(&mut self, id: TaskId) {
+        assert!(self.len < MAX_TASKS, "too many waiters");
+        self.ids[self.len] = Some(id);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.ids[0].take();
+        self.ids[..self.len].rotate_left(1);
+        self.len -= 1;
+        id
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        let Some(i) = self.ids[..self.len].iter().position(|&t| t == Some(id)) else {
+            return false;
+        };
+        self.ids[i] = None;
+        self.ids[i..self.len].rotate_left(1);
+        self.len -= 1;
+        true
+    }
+}
+
+// 等待某个条件的任务。不使用堆内存，中断处理程序也可以调用notify
+pub struct WaitQueue {
+    // 只在关中断时持有
+    waiters: Mutex<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: Mutex::new(Waiters {
+                ids: [None; MAX_TASKS],
+                len: 0,
+            }),
+        }
+    }
+
+    // 阻塞到condition成立，或者超过timeout个时钟中断。返回condition最后的结果。
+    // condition在关中断并持有队列的锁时调用，不能阻塞；
+    // 改变条件的一方要先改变条件，再调用notify，这样不会错过唤醒
+    pub fn wait_until<F>(&self, mut condition: F, timeout: Option<u64>) -> bool
+    where
+        F: FnMut() -> bool,
+    {
+        let deadline = timeout.map(|ticks| pit::ticks() + ticks);
+        let id = task::current_id();
+        loop {
+            let done = interrupts::without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if condition() {
+                    return Some(true);
+                }
+                if deadline.is_some_and(|deadline| pit::ticks() >= deadline) {
+                    return Some(false);
+                }
+                waiters.push(id);
+                task::prepare_to_block(deadline);
+                None
+            });
+            if let Some(result) = done {
+                return result;
+            }
+
+            task::switch_away();
+            // 超时醒来时还在队列中
+            interrupts::without_interrupts(|| self.waiters.lock().remove(id));
+        }
+    }
+
+    // 唤醒等待最久的任务，没有等待的任务时返回false
+    pub fn notify_one(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            // 持有锁时唤醒，被唤醒的任务在释放锁之前不会运行
+            match waiters.pop() {
+                Some(id) => {
+                    task::wake(id);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    // 唤醒所有等待的任务，返回唤醒的数量
+    pub fn notify_all(&self) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            let mut count = 0;
+            while let Some(id) = waiters.pop() {
+                task::wake(id);
+                count += 1;
+            }
+            count
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        interrupts::without_interrupts(|| self.waiters.lock().len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
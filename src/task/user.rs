@@ -0,0 +1,92 @@
+use core::arch::asm;
+
+use crate::{
+    interrupts::{self, InterruptContext},
+    memory::paging::VirtualAddress,
+};
+
+// 第1位是保留位，第9位打开中断，用户态总是可以被中断
+const USER_RFLAGS: u64 = 0x202;
+// 用户态可以改变的状态位：CF、PF、AF、ZF、SF、DF和OF
+const USER_STATUS_FLAGS: u64 = 0xcd5;
+
+// 用iretq切换到Ring3，从entry开始执行，栈指针为stack。
+// 不会返回，之后当前任务只能通过中断或者系统调用回到内核，
+// 使用的内核栈从当前任务的栈顶开始，这个函数之前的栈帧都会被覆盖
+pub fn enter_user_mode(entry: VirtualAddress, stack: VirtualAddress) -> ! {
+    let selectors = interrupts::selectors();
+    let code = selectors.user_code.value() as u64;
+    let data = selectors.user_data.value() as u64;
+    unsafe {
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "push {data}",   // ss
+            "push {stack}",  // rsp
+            "push {rflags}", // rflags
+            "push {code}",   // cs
+            "push {entry}",  // rip
+            // 不把内核的寄存器内容留给用户程序
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack,
+            rflags = in(reg) USER_RFLAGS,
+            code = in(reg) code,
+            entry = in(reg) entry,
+            options(noreturn),
+        );
+    }
+}
+
+// 从context恢复所有通用寄存器，用iretq回到Ring3，比如fork出的子进程。
+// 段选择子总是使用用户段，RFLAGS中只保留状态位，并打开中断
+pub fn resume_user_mode(context: &InterruptContext) -> ! {
+    let selectors = interrupts::selectors();
+    let data = selectors.user_data.value() as u64;
+    let mut frame = context.clone();
+    frame.code_segment = selectors.user_code.value() as u64;
+    frame.stack_segment = data;
+    frame.cpu_flags = (frame.cpu_flags & USER_STATUS_FLAGS) | USER_RFLAGS;
+    unsafe {
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            // frame的布局和handler_with_context!保存的栈帧相同
+            "mov rsp, {frame}",
+            "pop r15
+            pop r14
+            pop r13
+            pop r12
+            pop r11
+            pop r10
+            pop r9
+            pop r8
+            pop rbp
+            pop rdi
+            pop rsi
+            pop rdx
+            pop rcx
+            pop rbx
+            pop rax",
+            "iretq",
+            data = in(reg) data,
+            frame = in(reg) &frame,
+            options(noreturn),
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use alloc::{vec, vec::Vec};
+use core::mem::size_of;
+
+use crate::{
+    elf::{Elf64Header, Elf64ProgramHeader, SegmentFlags, SegmentType},
+    memory::PAGE_SIZE,
+};
+
+// 测试用的x86_64 ELF文件，文件头和程序头按elf模块中的结构写入
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+// 文件头和count个程序头的大小，测试程序的代码紧接在它们之后
+pub const fn headers_size(count: usize) -> usize {
+    size_of::<Elf64Header>() + count * size_of::<Elf64ProgramHeader>()
+}
+
+// 一个PT_LOAD段，按页对齐
+pub struct Segment {
+    pub flags: SegmentFlags,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub file_size: usize,
+    pub mem_size: usize,
+}
+
+pub fn put(file: &mut [u8], offset: usize, bytes: &[u8]) {
+    file[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+// 程序头紧跟在文件头之后，contents中是(文件偏移, 内容)
+pub fn build_elf(
+    elf_type: u16,
+    entry: usize,
+    segments: &[Segment],
+    contents: &[(usize, &[u8])],
+) -> Vec<u8> {
+    let size = contents
+        .iter()
+        .map(|(offset, bytes)| offset + bytes.len())
+        .fold(headers_size(segments.len()), usize::max);
+    let mut file = vec![0u8; size];
+
+    let mut e_ident = [0u8; 16];
+    put(&mut e_ident, 0, &[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+    let header = Elf64Header {
+        e_ident,
+        e_type: elf_type,
+        e_machine: 62,
+        e_version: 1,
+        e_entry: entry as u64,
+        e_phoff: size_of::<Elf64Header>() as u64,
+        e_shoff: 0,
+        e_flags: 0,
+        e_ehsize: size_of::<Elf64Header>() as u16,
+        e_phentsize: size_of::<Elf64ProgramHeader>() as u16,
+        e_phnum: segments.len() as u16,
+        e_shentsize: 0,
+        e_shnum: 0,
+        e_shstrndx: 0,
+    };
+    unsafe { (file.as_mut_ptr() as *mut Elf64Header).write_unaligned(header) };
+
+    for (i, segment) in segments.iter().enumerate() {
+        let program_header = Elf64ProgramHeader {
+            p_type: SegmentType::Load as u32,
+            p_flags: segment.flags.bits(),
+            p_offset: segment.offset as u64,
+            p_vaddr: segment.vaddr as u64,
+            p_paddr: segment.vaddr as u64,
+            p_filesz: segment.file_size as u64,
+            p_memsz: segment.mem_size as u64,
+            p_align: PAGE_SIZE as u64,
+        };
+        let at = headers_size(i);
+        unsafe {
+            (file[at..].as_mut_ptr() as *mut Elf64ProgramHeader).write_unaligned(program_header)
+        };
+    }
+
+    for (offset, bytes) in contents {
+        put(&mut file, *offset, bytes);
+    }
+    file
+}
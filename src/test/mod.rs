@@ -1,3 +1,11 @@
+pub mod elf_builder;
 pub mod test_allocator;
 pub mod test_exceptions;
+pub mod test_executor;
+pub mod test_loader;
 pub mod test_paging;
+pub mod test_process;
+pub mod test_sync;
+pub mod test_syscall;
+pub mod test_task;
+pub mod test_user;
@@ -1,5 +1,25 @@
-use crate::{expect_eq, memory::allocator::HEAP_SIZE, utils::test_frameworks::TestResult};
-use alloc::{boxed::Box, vec::Vec};
+use crate::{
+    expect_eq,
+    memory::{
+        FrameAllocator, MEMORY_CONTROLLER, PAGE_SIZE,
+        allocator::{
+            AllocatorBackend, HEAP_ALLOCATOR, HEAP_SIZE,
+            debug_allocator::DebugAllocator,
+            linked_list_allocator::FitStrategy,
+            select_backend,
+            slab_allocator::{ObjectCache, SlabBox, SlabCache},
+            stats::size_class,
+            tracking,
+        },
+        paging::phys_to_virt,
+    },
+    utils::test_frameworks::TestResult,
+};
+use alloc::{
+    alloc::{GlobalAlloc, Layout},
+    boxed::Box,
+    vec::Vec,
+};
 
 pub fn simple_allocation() -> TestResult {
     let heap_value_1 = Box::new(41);
@@ -37,3 +57,314 @@ pub fn many_boxes_long_lived() -> TestResult {
     expect_eq!(*long_lived, 1); // new
     TestResult::Passed
 }
+
+// 在指定的分配器后端上运行测试，结束后切换回原来的后端
+fn with_backend(backend: AllocatorBackend, test: impl FnOnce() -> TestResult) -> TestResult {
+    let previous = match select_backend(backend) {
+        Some(previous) => previous,
+        None => return TestResult::Failed("could not initialize the allocator backend"),
+    };
+    let result = test();
+    select_backend(previous);
+    result
+}
+
+// 同一组测试在每个后端上运行，bump分配器在有存活分配时不能复用内存，跳过long_lived
+fn allocator_suite(backend: AllocatorBackend) -> TestResult {
+    with_backend(backend, || {
+        let tests: &[fn() -> TestResult] = match backend {
+            AllocatorBackend::Bump => &[simple_allocation, large_vec, many_boxes, allocator_stats],
+            _ => &[
+                simple_allocation,
+                large_vec,
+                many_boxes,
+                many_boxes_long_lived,
+                allocator_stats,
+            ],
+        };
+        for test in tests {
+            if let TestResult::Failed(msg) = test() {
+                return TestResult::Failed(msg);
+            }
+        }
+        TestResult::Passed
+    })
+}
+
+pub fn bump_backend() -> TestResult {
+    allocator_suite(AllocatorBackend::Bump)
+}
+
+pub fn linked_list_backend() -> TestResult {
+    allocator_suite(AllocatorBackend::LinkedList)
+}
+
+pub fn fixed_size_block_backend() -> TestResult {
+    allocator_suite(AllocatorBackend::FixedSizeBlock)
+}
+
+pub fn slab_backend() -> TestResult {
+    if let TestResult::Failed(msg) = allocator_suite(AllocatorBackend::Slab) {
+        return TestResult::Failed(msg);
+    }
+    with_backend(AllocatorBackend::Slab, slab_owns)
+}
+
+// 直接映射中不属于任何slab的帧不能被当成slab分配
+fn slab_owns() -> TestResult {
+    let value = Box::new(7u64);
+    let address = &*value as *const u64 as usize;
+    expect_eq!(HEAP_ALLOCATOR.slab().lock().owns(address), true);
+
+    let frame = MEMORY_CONTROLLER.get().unwrap().lock().allocate_frame();
+    let Some(frame) = frame else {
+        return TestResult::Failed("out of frames");
+    };
+    let owned = HEAP_ALLOCATOR
+        .slab()
+        .lock()
+        .owns(phys_to_virt(frame.start_address()));
+    MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .deallocate_frame(frame);
+    expect_eq!(owned, false);
+    TestResult::Passed
+}
+
+// 超过初始堆大小的分配会扩展堆，释放后末尾的扩展区被归还
+pub fn heap_growth() -> TestResult {
+    with_backend(AllocatorBackend::FixedSizeBlock, check_heap_growth)
+}
+
+fn check_heap_growth() -> TestResult {
+    let heap = HEAP_ALLOCATOR.fixed_size_block();
+    let initial_size = heap.lock().heap_size();
+
+    let mut vec: Vec<u8> = Vec::with_capacity(2 * HEAP_SIZE);
+    vec.resize(2 * HEAP_SIZE, 0xab);
+    expect_eq!(vec.iter().all(|&b| b == 0xab), true);
+    let grown = heap.lock().heap_size() > initial_size;
+    expect_eq!(grown, true);
+
+    drop(vec);
+    expect_eq!(heap.lock().heap_size(), initial_size);
+    TestResult::Passed
+}
+
+pub fn heap_growth_small_blocks() -> TestResult {
+    with_backend(
+        AllocatorBackend::FixedSizeBlock,
+        check_heap_growth_small_blocks,
+    )
+}
+
+// 只用小块分配也能让堆扩展到2 MiB以上，全部释放后扩展区都还回去
+fn check_heap_growth_small_blocks() -> TestResult {
+    const TOTAL_SIZE: usize = 3 * 1024 * 1024;
+    let heap = HEAP_ALLOCATOR.fixed_size_block();
+    let initial_size = heap.lock().heap_size();
+
+    let count = TOTAL_SIZE / size_of::<[u64; 32]>();
+    let mut boxes = Vec::with_capacity(count);
+    for i in 0..count {
+        boxes.push(Box::new([i as u64; 32]));
+    }
+    let intact = boxes.iter().enumerate().all(|(i, b)| b[31] == i as u64);
+    expect_eq!(intact, true);
+    let grown = heap.lock().heap_size() > initial_size + 2 * 1024 * 1024;
+    expect_eq!(grown, true, "heap did not grow past 2 MiB");
+
+    drop(boxes);
+    expect_eq!(heap.lock().heap_size(), initial_size);
+    TestResult::Passed
+}
+
+const SLAB_OBJECT_MAGIC: u64 = 0x5eed_cafe;
+
+fn construct_slab_object(object: *mut u8) {
+    unsafe { (object as *mut [u64; 12]).write([SLAB_OBJECT_MAGIC; 12]) };
+}
+
+static TEST_CACHE: spin::Mutex<SlabCache> = spin::Mutex::new(SlabCache::new(
+    "test_objects",
+    core::mem::size_of::<[u64; 12]>(),
+    core::mem::align_of::<[u64; 12]>(),
+    Some(construct_slab_object),
+));
+
+pub fn slab_cache() -> TestResult {
+    let mut cache = TEST_CACHE.lock();
+
+    // 比一个slab多分配一个对象，需要两个slab。
+    // 持有MemoryController的锁时不能再申请堆内存，所以先分配好Vec
+    let count = cache.objects_per_slab() + 1;
+    let mut objects = Vec::with_capacity(count);
+    let mut controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    for _ in 0..count {
+        let object = cache.alloc(&mut *controller).unwrap();
+        let value = unsafe { (object.as_ptr() as *const [u64; 12]).read() };
+        expect_eq!(value, [SLAB_OBJECT_MAGIC; 12]);
+        objects.push(object);
+    }
+    expect_eq!(cache.objects_in_use(), count);
+    expect_eq!(cache.slab_counts(), (1, 1, 0));
+
+    for object in objects.drain(..) {
+        unsafe { cache.free(object) };
+    }
+    expect_eq!(cache.objects_in_use(), 0);
+    expect_eq!(cache.slab_counts(), (0, 0, 2));
+
+    expect_eq!(cache.reclaim(&mut *controller), 2);
+    expect_eq!(cache.slab_counts(), (0, 0, 0));
+    TestResult::Passed
+}
+
+static TEST_OBJECT_CACHE: ObjectCache<[u64; 12]> = ObjectCache::new("test_values");
+
+pub fn object_cache() -> TestResult {
+    let value = {
+        let mut controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        TEST_OBJECT_CACHE.alloc([7; 12], &mut *controller)
+    };
+    let Ok(mut value) = value else {
+        return TestResult::Failed("object cache is out of memory");
+    };
+    expect_eq!(*value, [7; 12]);
+    expect_eq!(TEST_OBJECT_CACHE.objects_in_use(), 1);
+    value[0] = 8;
+
+    // 取出对象后槽位回到缓存
+    let inner = SlabBox::into_inner(value);
+    expect_eq!(inner[0], 8);
+    expect_eq!(TEST_OBJECT_CACHE.objects_in_use(), 0);
+    expect_eq!(TEST_OBJECT_CACHE.slab_counts(), (0, 0, 1));
+    TestResult::Passed
+}
+
+pub fn allocator_stats() -> TestResult {
+    let before = HEAP_ALLOCATOR.stats();
+    let value = Box::new([0u8; 64]);
+    let during = HEAP_ALLOCATOR.stats();
+    expect_eq!(during.bytes_in_use, before.bytes_in_use + 64);
+    expect_eq!(during.allocations, before.allocations + 1);
+    expect_eq!(
+        during.live_by_size_class[size_class(64)],
+        before.live_by_size_class[size_class(64)] + 1
+    );
+    let peak = during.peak_bytes_in_use >= during.bytes_in_use;
+    expect_eq!(peak, true);
+
+    drop(value);
+    let after = HEAP_ALLOCATOR.stats();
+    expect_eq!(after.bytes_in_use, before.bytes_in_use);
+    expect_eq!(after.live_allocations(), before.live_allocations());
+    TestResult::Passed
+}
+
+pub fn allocation_tracking() -> TestResult {
+    if !cfg!(feature = "alloc_tracking") {
+        return TestResult::Passed;
+    }
+    let mark = tracking::mark();
+    let value = Box::new(7u64);
+    expect_eq!(tracking::leaks_since(mark), 1);
+    drop(value);
+    expect_eq!(tracking::leaks_since(mark), 0);
+    TestResult::Passed
+}
+
+// 以任意顺序释放之后空闲链表会重新合并
+pub fn linked_list_coalescing() -> TestResult {
+    with_backend(AllocatorBackend::LinkedList, || {
+        let before = HEAP_ALLOCATOR.stats();
+        let mut boxes: Vec<Option<Box<[u8; 256]>>> =
+            (0..16).map(|_| Some(Box::new([0; 256]))).collect();
+        for index in (1..16).step_by(2).chain((0..16).step_by(2)) {
+            boxes[index] = None;
+        }
+        drop(boxes);
+
+        let after = HEAP_ALLOCATOR.stats();
+        expect_eq!(after.free_bytes, before.free_bytes);
+        let coalesced = after.largest_free_block >= before.largest_free_block;
+        expect_eq!(coalesced, true);
+        TestResult::Passed
+    })
+}
+
+pub fn linked_list_best_fit() -> TestResult {
+    with_backend(AllocatorBackend::LinkedList, || {
+        let large = Box::new([0u8; 1024]);
+        let separator_1 = Box::new([0u8; 64]);
+        let small = Box::new([0u8; 256]);
+        let separator_2 = Box::new([0u8; 64]);
+        let small_address = &*small as *const [u8; 256] as usize;
+        drop(large);
+        drop(small);
+
+        // 首次适配会用前面较大的空洞，最佳适配选择刚好够用的空洞
+        HEAP_ALLOCATOR
+            .linked_list()
+            .lock()
+            .set_strategy(FitStrategy::BestFit);
+        let value = Box::new([0u8; 200]);
+        HEAP_ALLOCATOR
+            .linked_list()
+            .lock()
+            .set_strategy(FitStrategy::FirstFit);
+        expect_eq!(&*value as *const [u8; 200] as usize, small_address);
+
+        drop((value, separator_1, separator_2));
+        TestResult::Passed
+    })
+}
+
+pub fn linked_list_realloc_in_place() -> TestResult {
+    with_backend(AllocatorBackend::LinkedList, || {
+        let mut vec: Vec<u8> = Vec::with_capacity(64);
+        let address = vec.as_ptr();
+        vec.reserve_exact(1024);
+        expect_eq!(vec.as_ptr(), address);
+        vec.shrink_to(128);
+        expect_eq!(vec.as_ptr(), address);
+        TestResult::Passed
+    })
+}
+
+static DEBUG_HEAP: DebugAllocator = DebugAllocator::new(&HEAP_ALLOCATOR);
+
+// 每种损坏都应该被发现一次，并通过串口报告
+pub fn heap_corruption_detection() -> TestResult {
+    DEBUG_HEAP.enable(true);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let errors = DEBUG_HEAP.errors();
+
+    let overflow = unsafe { DEBUG_HEAP.alloc(layout) };
+    unsafe {
+        overflow.add(layout.size()).write(0);
+        DEBUG_HEAP.dealloc(overflow, layout);
+    }
+    expect_eq!(DEBUG_HEAP.errors(), errors + 1);
+
+    unsafe { DEBUG_HEAP.dealloc(overflow, layout) };
+    expect_eq!(DEBUG_HEAP.errors(), errors + 2);
+
+    let use_after_free = unsafe { DEBUG_HEAP.alloc(layout) };
+    unsafe {
+        DEBUG_HEAP.dealloc(use_after_free, layout);
+        use_after_free.write(0);
+    }
+    DEBUG_HEAP.flush_quarantine();
+    expect_eq!(DEBUG_HEAP.errors(), errors + 3);
+
+    // 大的分配紧挨着后面的保护页
+    let large = Layout::from_size_align(2 * PAGE_SIZE, 8).unwrap();
+    let guarded = unsafe { DEBUG_HEAP.alloc(large) };
+    expect_eq!((guarded as usize + large.size()) % PAGE_SIZE, 0);
+    unsafe { DEBUG_HEAP.dealloc(guarded, large) };
+    expect_eq!(DEBUG_HEAP.errors(), errors + 3);
+    TestResult::Passed
+}
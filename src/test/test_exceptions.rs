@@ -1,5 +1,10 @@
+use crate::interrupts::{
+    self, DOUBLE_FAULT_VECTOR, MACHINE_CHECK_VECTOR, NMI_VECTOR, PAGE_FAULT_VECTOR,
+};
+use crate::memory::{PAGE_SIZE, stack_allocator};
 use crate::utils::test_frameworks::TestResult;
 use crate::utils::x86_64_control;
+use crate::expect_eq;
 use core::arch::asm;
 
 pub fn divide_by_zero() -> TestResult {
@@ -29,3 +34,31 @@ pub fn breakpoint() -> TestResult {
     x86_64_control::software_interrupt::<3>();
     TestResult::Passed
 }
+
+pub fn interrupt_stacks() -> TestResult {
+    // 向量和专用栈的页数
+    let stacks = [
+        (NMI_VECTOR, 1),
+        (DOUBLE_FAULT_VECTOR, 1),
+        (MACHINE_CHECK_VECTOR, 1),
+        (PAGE_FAULT_VECTOR, 4),
+    ];
+    for (i, &(vector, pages)) in stacks.iter().enumerate() {
+        let top = match interrupts::interrupt_stack_top(vector) {
+            Some(top) => top,
+            None => return TestResult::Failed("critical exception has no IST stack"),
+        };
+        // 栈的下方是保护页
+        expect_eq!(
+            stack_allocator::guard_page_owner(top - (pages + 1) * PAGE_SIZE).is_some(),
+            true,
+            "IST stack has no guard page"
+        );
+        for &(other, _) in &stacks[i + 1..] {
+            let shared = interrupts::interrupt_stack_top(other) == Some(top);
+            expect_eq!(shared, false, "IST stack is shared between vectors");
+        }
+    }
+    expect_eq!(interrupts::interrupt_stack_top(3), None);
+    TestResult::Passed
+}
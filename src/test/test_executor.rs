@@ -0,0 +1,84 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use crate::{
+    expect_eq,
+    task::{
+        self,
+        executor::{
+            self, Executor, keyboard,
+            keyboard::ScancodeStream,
+            serial::{self, SerialStream},
+            stream::StreamExt,
+        },
+    },
+    utils::test_frameworks::TestResult,
+};
+
+pub fn executor_runs_futures() -> TestResult {
+    let log = Arc::new(Mutex::new(Vec::with_capacity(6)));
+    let mut executor = Executor::new();
+    for tag in ['a', 'b'] {
+        let log = log.clone();
+        executor.spawn(async move {
+            for i in 0..3 {
+                log.lock().push((tag, i));
+                executor::yield_now().await;
+            }
+        });
+    }
+    expect_eq!(log.lock().len(), 0, "future ran before the executor");
+
+    expect_eq!(executor.run_until_idle(), 0);
+    expect_eq!(
+        log.lock()[..],
+        [('a', 0), ('b', 0), ('a', 1), ('b', 1), ('a', 2), ('b', 2)],
+        "futures did not run in turn"
+    );
+    TestResult::Passed
+}
+
+pub fn interrupt_streams() -> TestResult {
+    let scancodes = Arc::new(Mutex::new(Vec::with_capacity(3)));
+    let bytes = Arc::new(Mutex::new(Vec::with_capacity(2)));
+    let mut executor = Executor::new();
+    {
+        let scancodes = scancodes.clone();
+        executor.spawn(async move {
+            let mut stream = ScancodeStream::new();
+            for _ in 0..3 {
+                let scancode = stream.next().await.unwrap();
+                scancodes.lock().push(scancode);
+            }
+        });
+    }
+    {
+        let bytes = bytes.clone();
+        executor.spawn(async move {
+            let mut stream = SerialStream::new();
+            for _ in 0..2 {
+                let byte = stream.next().await.unwrap();
+                bytes.lock().push(byte);
+            }
+        });
+    }
+    expect_eq!(executor.run_until_idle(), 2, "streams produced data early");
+
+    // 代替键盘和串口的中断处理程序写入数据，执行器在这期间用hlt等待
+    let feeder = task::spawn("irq feeder", || {
+        for scancode in [0x1e, 0x9e, 0x30] {
+            task::sleep(1);
+            keyboard::add_scancode(scancode);
+        }
+        serial::add_byte(b'o');
+        serial::add_byte(b'k');
+    });
+    executor.run_until_complete();
+    feeder.join();
+
+    expect_eq!(scancodes.lock()[..], [0x1e, 0x9e, 0x30]);
+    expect_eq!(bytes.lock()[..], *b"ok");
+    expect_eq!(keyboard::dropped_scancodes(), 0);
+    TestResult::Passed
+}
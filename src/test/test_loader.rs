@@ -0,0 +1,248 @@
+use alloc::vec::Vec;
+use core::mem::offset_of;
+
+use crate::{
+    elf::{Elf64Header, Elf64ProgramHeader, ElfError, ElfFile, SegmentFlags},
+    expect_eq,
+    memory::{
+        MEMORY_CONTROLLER, PAGE_SIZE,
+        paging::{EntryFlags, Page, phys_to_virt},
+    },
+    task::{
+        loader::{
+            self, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHNUM, AT_RANDOM, LoadError,
+            PIE_LOAD_BASE, UserImage,
+        },
+        process,
+    },
+    test::elf_builder::{self, ET_DYN, ET_EXEC, Segment, headers_size, put},
+    utils::test_frameworks::TestResult,
+};
+
+const STATIC_BASE: usize = 0x40_0000;
+// 两个程序头之后是代码
+const CODE_OFFSET: usize = headers_size(2);
+const DATA_OFFSET: usize = 0x100;
+// 数据段和代码段不在同一页，页内偏移和文件偏移相同
+const DATA_VADDR: usize = 0x20_1100;
+const DATA_MEM_SIZE: usize = 0x2000;
+const DATA_MAGIC: u64 = 0x1122_3344_5566_7788;
+
+// argc为2并且BSS为0时exit(0)，否则exit(1)
+const PROGRAM: [u8; 44] = [
+    0x48, 0x8b, 0x04, 0x24, // mov rax, [rsp]
+    0x48, 0x83, 0xf8, 0x02, // cmp rax, 2
+    0x75, 0x16, // jne fail
+    0x48, 0x8b, 0x04, 0x25, 0x08, 0x11, 0x60, 0x00, // mov rax, [0x601108]
+    0x48, 0x85, 0xc0, // test rax, rax
+    0x75, 0x09, // jne fail
+    0x31, 0xff, // xor edi, edi
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+    0xbf, 0x01, 0x00, 0x00, 0x00, // fail: mov edi, 1
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+];
+
+const ARGV: [&str; 2] = ["prog", "-v"];
+const ENVP: [&str; 1] = ["HOME=/"];
+
+// 一个可读可执行的代码段和一个带BSS的可写数据段
+fn build_elf(elf_type: u16, base: usize) -> Vec<u8> {
+    let text_size = CODE_OFFSET + PROGRAM.len();
+    let segments = [
+        Segment {
+            flags: SegmentFlags::PF_R | SegmentFlags::PF_X,
+            offset: 0,
+            vaddr: base,
+            file_size: text_size,
+            mem_size: text_size,
+        },
+        Segment {
+            flags: SegmentFlags::PF_R | SegmentFlags::PF_W,
+            offset: DATA_OFFSET,
+            vaddr: base + DATA_VADDR,
+            file_size: 8,
+            mem_size: DATA_MEM_SIZE,
+        },
+    ];
+    elf_builder::build_elf(
+        elf_type,
+        base + CODE_OFFSET,
+        &segments,
+        &[
+            (CODE_OFFSET, &PROGRAM),
+            (DATA_OFFSET, &DATA_MAGIC.to_le_bytes()),
+        ],
+    )
+}
+
+// 通过直接映射读取不活动页表中的用户内存，同时返回页的标志
+fn read_user(image: &mut UserImage, address: usize) -> Option<(u64, EntryFlags)> {
+    let mut result = None;
+    MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .with_page_table(image.page_table(), |mapper, _| {
+            let flags = mapper.page_flags(Page::containing_address(address));
+            result = flags
+                .zip(mapper.translate(address))
+                .map(|(flags, physical)| {
+                    let value =
+                        unsafe { core::ptr::read_unaligned(phys_to_virt(physical) as *const u64) };
+                    (value, flags)
+                });
+        });
+    result
+}
+
+fn read_word(image: &mut UserImage, address: usize) -> u64 {
+    read_user(image, address).map_or(u64::MAX, |(value, _)| value)
+}
+
+fn read_string(image: &mut UserImage, address: usize, len: usize) -> Vec<u8> {
+    (0..=len)
+        .map(|i| read_word(image, address + i) as u8)
+        .collect()
+}
+
+pub fn elf_validation() -> TestResult {
+    let file = build_elf(ET_EXEC, STATIC_BASE);
+    expect_eq!(ElfFile::parse(&file).is_ok(), true);
+    expect_eq!(ElfFile::parse(&file[..32]).err(), Some(ElfError::TooShort));
+
+    let mut bad = file.clone();
+    bad[0] = 0;
+    expect_eq!(ElfFile::parse(&bad).err(), Some(ElfError::BadMagic));
+
+    let mut bad = file.clone();
+    put(
+        &mut bad,
+        offset_of!(Elf64Header, e_machine),
+        &3u16.to_le_bytes(),
+    );
+    expect_eq!(
+        ElfFile::parse(&bad).err(),
+        Some(ElfError::UnsupportedMachine)
+    );
+
+    // 数据段超出文件
+    let mut bad = file.clone();
+    put(
+        &mut bad,
+        headers_size(1) + offset_of!(Elf64ProgramHeader, p_filesz),
+        &0x1000u64.to_le_bytes(),
+    );
+    expect_eq!(ElfFile::parse(&bad).err(), Some(ElfError::BadSegment));
+
+    // 入口在不可执行的数据段中
+    let mut bad = file.clone();
+    put(
+        &mut bad,
+        offset_of!(Elf64Header, e_entry),
+        &((STATIC_BASE + DATA_VADDR) as u64).to_le_bytes(),
+    );
+    expect_eq!(
+        loader::load(&bad, &ARGV, &ENVP).err(),
+        Some(LoadError::BadEntryPoint)
+    );
+
+    let low = build_elf(ET_EXEC, PAGE_SIZE);
+    expect_eq!(
+        loader::load(&low, &ARGV, &ENVP).err(),
+        Some(LoadError::BadSegmentAddress)
+    );
+
+    expect_eq!(
+        loader::load_module("no such module", &[]).err(),
+        Some(LoadError::NoSuchModule)
+    );
+    TestResult::Passed
+}
+
+pub fn elf_loading() -> TestResult {
+    let file = build_elf(ET_EXEC, STATIC_BASE);
+    let mut image = loader::load(&file, &ARGV, &ENVP).unwrap();
+    expect_eq!(image.entry(), STATIC_BASE + CODE_OFFSET);
+
+    let (magic, text_flags) = read_user(&mut image, STATIC_BASE).unwrap();
+    expect_eq!(magic, u64::from_le_bytes(file[..8].try_into().unwrap()));
+    let text_ok = text_flags.contains(EntryFlags::USER_ACCESSIBLE)
+        && !text_flags.intersects(EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE);
+    expect_eq!(text_ok, true, "wrong text segment flags");
+
+    let data = STATIC_BASE + DATA_VADDR;
+    let (value, data_flags) = read_user(&mut image, data).unwrap();
+    expect_eq!(value, DATA_MAGIC);
+    let data_ok = data_flags.contains(EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE);
+    expect_eq!(data_ok, true, "wrong data segment flags");
+    expect_eq!(read_word(&mut image, data - 8), 0, "bytes before the data");
+    expect_eq!(read_word(&mut image, data + 8), 0, "bss is not zeroed");
+    let bss_end = data + DATA_MEM_SIZE - 8;
+    expect_eq!(read_word(&mut image, bss_end), 0, "bss is not mapped");
+
+    // argc, argv, envp, auxv
+    let sp = image.stack_pointer();
+    expect_eq!(sp % 16, 0);
+    expect_eq!(read_word(&mut image, sp), 2);
+    for (i, arg) in ARGV.iter().chain(&ENVP).enumerate() {
+        // argv和envp之间隔着一个NULL
+        let slot = sp + 8 + 8 * (i + i / ARGV.len());
+        let address = read_word(&mut image, slot) as usize;
+        let string = read_string(&mut image, address, arg.len());
+        expect_eq!(string[..arg.len()], *arg.as_bytes());
+        expect_eq!(string[arg.len()], 0);
+    }
+    expect_eq!(read_word(&mut image, sp + 8 * 3), 0);
+    expect_eq!(read_word(&mut image, sp + 8 * 5), 0);
+
+    let mut auxv = [None; AT_RANDOM + 1];
+    let mut at = sp + 8 * 6;
+    loop {
+        let key = read_word(&mut image, at) as usize;
+        if key == AT_NULL || key >= auxv.len() {
+            break;
+        }
+        auxv[key] = Some(read_word(&mut image, at + 8) as usize);
+        at += 16;
+    }
+    expect_eq!(auxv[AT_ENTRY], Some(image.entry()));
+    expect_eq!(auxv[AT_PHDR], Some(STATIC_BASE + headers_size(0)));
+    expect_eq!(auxv[AT_PHNUM], Some(2));
+    expect_eq!(auxv[AT_PAGESZ], Some(PAGE_SIZE));
+    let random_on_stack = auxv[AT_RANDOM].is_some_and(|address| address > sp);
+    expect_eq!(random_on_stack, true);
+    image.free();
+
+    let pie = build_elf(ET_DYN, 0);
+    let mut image = loader::load(&pie, &ARGV, &ENVP).unwrap();
+    expect_eq!(image.entry(), PIE_LOAD_BASE + CODE_OFFSET);
+    expect_eq!(
+        read_word(&mut image, PIE_LOAD_BASE + DATA_VADDR),
+        DATA_MAGIC
+    );
+    image.free();
+    TestResult::Passed
+}
+
+pub fn elf_program_runs() -> TestResult {
+    let file = build_elf(ET_EXEC, STATIC_BASE);
+    let pid = loader::load(&file, &ARGV, &ENVP)
+        .unwrap()
+        .spawn("elf program")
+        .unwrap();
+    expect_eq!(process::wait(Some(pid)), Some((pid, 0)), "program failed");
+
+    // argc不是2时以1退出
+    let pid = loader::load(&file, &ARGV[..1], &ENVP)
+        .unwrap()
+        .spawn("elf program")
+        .unwrap();
+    expect_eq!(
+        process::wait(Some(pid)),
+        Some((pid, 1)),
+        "wrong exit status"
+    );
+    TestResult::Passed
+}
@@ -1,15 +1,24 @@
+use alloc::vec::Vec;
+
 use crate::{
+    expect_eq,
     memory::{
-        FrameAllocator,
+        FrameAllocator, MEMORY_CONTROLLER, PAGE_SIZE,
+        address_space::AddressSpace,
         area_frame_allocator::AreaFrameAllocator,
-        paging::{ActivePageTable, EntryFlags, Page},
+        paging::{
+            ActivePageTable, EntryFlags, Page, pcid::KERNEL_PCID, phys_to_virt, virt_to_phys,
+        },
+        region::{Backing, RegionKind},
+        stack_allocator,
     },
     multiboot_info::MultibootInfo,
     serial_println,
+    utils::test_frameworks::TestResult,
 };
 
 pub fn test_paging(multiboot_information_address: usize) {
-    let boot_info = MultibootInfo::new(multiboot_information_address);
+    let boot_info = MultibootInfo::new(phys_to_virt(multiboot_information_address));
 
     let address_sections = boot_info.get_multiboot_address_section();
 
@@ -43,7 +52,7 @@ pub fn test_paging(multiboot_information_address: usize) {
 pub fn test_remap_the_kernel(multiboot_information_address: usize) {
     use crate::{memory::paging::remap_the_kernel, println, utils::x86_64_control};
 
-    let boot_info = MultibootInfo::new(multiboot_information_address);
+    let boot_info = MultibootInfo::new(phys_to_virt(multiboot_information_address));
 
     let address_sections = boot_info.get_multiboot_address_section();
 
@@ -69,3 +78,233 @@ pub fn test_remap_the_kernel(multiboot_information_address: usize) {
 
     println!("It did not crash!");
 }
+
+static DIRECT_MAP_PROBE: u64 = 0xdead_beef_cafe_babe;
+
+pub fn direct_map() -> TestResult {
+    let virtual_address = &DIRECT_MAP_PROBE as *const u64 as usize;
+    let physical_address = ActivePageTable::new()
+        .translate(virtual_address)
+        .expect("kernel data is not mapped");
+
+    let alias = phys_to_virt(physical_address);
+    expect_eq!(virt_to_phys(alias), physical_address);
+    expect_eq!(
+        ActivePageTable::new().translate(alias),
+        Some(physical_address)
+    );
+    expect_eq!(unsafe { *(alias as *const u64) }, DIRECT_MAP_PROBE);
+    TestResult::Passed
+}
+
+pub fn demand_paging() -> TestResult {
+    // 一个不会被其他代码使用的虚拟地址
+    let start = 0xffff_e000_0000_0000;
+    let size = 4 * PAGE_SIZE;
+
+    {
+        let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        if memory_controller
+            .map_anonymous(start, size, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)
+            .is_err()
+        {
+            return TestResult::Failed("could not register anonymous region");
+        }
+    }
+
+    expect_eq!(ActivePageTable::new().translate(start), None);
+
+    let slice = unsafe { core::slice::from_raw_parts_mut(start as *mut u64, size / 8) };
+    expect_eq!(slice[0], 0, "demand mapped page is not zeroed");
+    slice[size / 8 - 1] = 42;
+    expect_eq!(slice[size / 8 - 1], 42);
+    expect_eq!(ActivePageTable::new().translate(start).is_some(), true);
+    TestResult::Passed
+}
+
+// 只记录区域，不映射任何页
+static TEST_SPACE: spin::Mutex<AddressSpace> = spin::Mutex::new(AddressSpace::new(
+    0xffff_f000_0000_0000,
+    0xffff_f000_0010_0000,
+));
+
+pub fn address_space_split_merge() -> TestResult {
+    let mut space = TEST_SPACE.lock();
+    let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+
+    let a = space
+        .allocate(2 * PAGE_SIZE, flags, RegionKind::Anonymous, Backing::Demand)
+        .unwrap();
+    let b = space
+        .allocate(PAGE_SIZE, flags, RegionKind::Anonymous, Backing::Demand)
+        .unwrap();
+    expect_eq!(b, a + 2 * PAGE_SIZE);
+    expect_eq!(space.iter().count(), 1, "adjacent regions were not merged");
+
+    space
+        .protect(a + PAGE_SIZE, PAGE_SIZE, EntryFlags::NO_EXECUTE)
+        .unwrap();
+    expect_eq!(space.iter().count(), 3, "region was not split");
+    expect_eq!(
+        space.find(a + PAGE_SIZE).map(|r| r.flags()),
+        Some(EntryFlags::NO_EXECUTE)
+    );
+
+    space.protect(a + PAGE_SIZE, PAGE_SIZE, flags).unwrap();
+    expect_eq!(space.iter().count(), 1, "regions were not merged back");
+
+    space.remove(a + PAGE_SIZE, PAGE_SIZE).unwrap();
+    expect_eq!(space.iter().count(), 2);
+    expect_eq!(space.find(a + PAGE_SIZE).is_none(), true);
+    expect_eq!(
+        space.find_free_gap(PAGE_SIZE, PAGE_SIZE),
+        Some(a + PAGE_SIZE)
+    );
+    expect_eq!(
+        space.find_free_gap(2 * PAGE_SIZE, PAGE_SIZE),
+        Some(b + PAGE_SIZE)
+    );
+
+    space.remove(a, 3 * PAGE_SIZE).unwrap();
+    TestResult::Passed
+}
+
+pub fn stack_reuse() -> TestResult {
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let stack = memory_controller
+        .alloc_stack(2, "test stack")
+        .expect("could not allocate stack");
+    let (top, guard_page) = (stack.top(), stack.guard_page());
+    expect_eq!(stack.size_in_pages(), 2);
+    expect_eq!(
+        stack_allocator::guard_page_owner(guard_page + 8),
+        Some("test stack")
+    );
+    expect_eq!(ActivePageTable::new().translate(guard_page), None);
+
+    memory_controller.free_stack(stack);
+    expect_eq!(stack_allocator::guard_page_owner(guard_page), None);
+
+    let stack = memory_controller
+        .alloc_stack(2, "reused stack")
+        .expect("could not allocate stack");
+    expect_eq!(stack.top(), top, "freed stack was not reused");
+    expect_eq!(
+        stack_allocator::guard_page_owner(guard_page),
+        Some("reused stack")
+    );
+    memory_controller.free_stack(stack);
+    TestResult::Passed
+}
+
+// 保护页登记满了时分配失败，不会留下保留的地址范围
+pub fn stack_guards_full() -> TestResult {
+    // 持有MemoryController的锁时堆不能扩展，先申请好
+    let mut stacks = Vec::with_capacity(256);
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let mut regions = memory_controller.kernel_space().iter().count();
+    while stacks.len() < stacks.capacity() {
+        regions = memory_controller.kernel_space().iter().count();
+        match memory_controller.alloc_lazy_stack(1, "filler stack") {
+            Some(stack) => stacks.push(stack),
+            None => break,
+        }
+    }
+    let full = stacks.len() < stacks.capacity();
+    let leaked = memory_controller.kernel_space().iter().count() != regions;
+    for stack in stacks {
+        memory_controller.free_stack(stack);
+    }
+    expect_eq!(full, true, "stack guard table never filled up");
+    expect_eq!(leaked, false, "failed allocation left regions behind");
+    TestResult::Passed
+}
+
+pub fn page_table_lifecycle() -> TestResult {
+    let user_page = Page::containing_address(0x40_0000);
+    let kernel_page = Page::containing_address(page_table_lifecycle as fn() -> TestResult as usize);
+
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let mut table = memory_controller
+        .new_page_table()
+        .expect("could not allocate page table");
+    let p4_address = table.p4_frame().start_address();
+
+    let mut kernel_mapped = false;
+    let mut user_mappings = 0;
+    memory_controller.with_page_table(&mut table, |mapper, allocator| {
+        kernel_mapped = mapper.mappings().any(|(page, _, _)| page == kernel_page);
+        mapper.map(user_page, EntryFlags::USER_ACCESSIBLE, allocator);
+        user_mappings = mapper
+            .mappings()
+            .filter(|(page, _, flags)| {
+                *page == user_page && flags.contains(EntryFlags::USER_ACCESSIBLE)
+            })
+            .count();
+        mapper.unmap(user_page, allocator);
+    });
+    expect_eq!(kernel_mapped, true, "kernel is not mapped in the new table");
+    expect_eq!(user_mappings, 1);
+    expect_eq!(ActivePageTable::new().translate_page(user_page), None);
+
+    // 丢弃时需要MEMORY_CONTROLLER的锁，释放的帧最先被重新分配
+    drop(memory_controller);
+    drop(table);
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let frame = memory_controller.allocate_frame().unwrap();
+    expect_eq!(frame.start_address(), p4_address, "P4 frame was not freed");
+    memory_controller.deallocate_frame(frame);
+    TestResult::Passed
+}
+
+pub fn page_table_dump() -> TestResult {
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let mut table = memory_controller
+        .new_page_table()
+        .expect("could not allocate page table");
+    memory_controller.with_page_table(&mut table, |mapper, allocator| {
+        mapper.map(
+            Page::containing_address(0x40_0000),
+            EntryFlags::USER_ACCESSIBLE,
+            allocator,
+        );
+    });
+    memory_controller.dump_page_table(&mut table);
+    memory_controller.with_page_table(&mut table, |mapper, allocator| {
+        mapper.unmap(Page::containing_address(0x40_0000), allocator);
+    });
+    drop(memory_controller);
+    TestResult::Passed
+}
+
+pub fn pcid_allocation() -> TestResult {
+    use crate::utils::x86_64_control;
+
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    let first = memory_controller.new_page_table().unwrap();
+    let second = memory_controller.new_page_table().unwrap();
+    drop(memory_controller);
+
+    if !x86_64_control::pcid_enabled() {
+        expect_eq!(first.pcid(), KERNEL_PCID);
+        expect_eq!(second.pcid(), KERNEL_PCID);
+        return TestResult::Passed;
+    }
+
+    let kernel_pcid_reused = first.pcid() == KERNEL_PCID;
+    let allocated_twice = first.pcid() == second.pcid();
+    expect_eq!(kernel_pcid_reused, false, "kernel PCID was reused");
+    expect_eq!(allocated_twice, false, "PCID was allocated twice");
+
+    // 释放后最小的空闲PCID会被重新分配
+    let pcid = first.pcid();
+    drop(first);
+    let third = MEMORY_CONTROLLER
+        .get()
+        .unwrap()
+        .lock()
+        .new_page_table()
+        .unwrap();
+    expect_eq!(third.pcid(), pcid, "freed PCID was not reused");
+    TestResult::Passed
+}
@@ -0,0 +1,186 @@
+use alloc::vec::Vec;
+
+use crate::{
+    elf::SegmentFlags,
+    expect_eq,
+    interrupts::{InterruptContext, pit},
+    memory::{MEMORY_CONTROLLER, shared_frames::SHARED_FRAMES},
+    task::{
+        self, loader,
+        process::{self, Credentials, Handle, KERNEL_PID, ProcessError, ProcessId},
+    },
+    test::elf_builder::{ET_EXEC, Segment, build_elf, headers_size},
+    utils::test_frameworks::TestResult,
+};
+
+const BASE: usize = 0x40_0000;
+// 文件头和一个程序头之后是代码
+const CODE_OFFSET: usize = headers_size(1);
+
+// exit(3)
+const EXIT_PROGRAM: [u8; 12] = [
+    0xbf, 0x03, 0x00, 0x00, 0x00, // mov edi, 3
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+];
+
+// 子进程exit(7)，父进程等待它之后退出，
+// 退出码的低8位是子进程的退出码加1，其余位是wait返回的子进程号
+const FORK_PROGRAM: [u8; 55] = [
+    0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_FORK
+    0x0f, 0x05, // syscall
+    0x48, 0x85, 0xc0, // test rax, rax
+    0x75, 0x0c, // jnz parent
+    0xbf, 0x07, 0x00, 0x00, 0x00, // mov edi, 7
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+    0x48, 0x89, 0xc7, // parent: mov rdi, rax
+    0x48, 0x89, 0xe6, // mov rsi, rsp
+    0xb8, 0x07, 0x00, 0x00, 0x00, // mov eax, SYS_WAIT
+    0x0f, 0x05, // syscall
+    0x8b, 0x3c, 0x24, // mov edi, [rsp]
+    0x83, 0xc7, 0x01, // add edi, 1
+    0xc1, 0xe0, 0x08, // shl eax, 8
+    0x09, 0xc7, // or edi, eax
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+];
+
+// fork之前在栈上写入5，子进程睡眠20个时钟中断后把它加2作为退出码，
+// 父进程以子进程的退出码乘10再加上自己看到的值退出，写时复制正确时为75
+const COW_PROGRAM: [u8; 77] = [
+    0xc7, 0x04, 0x24, 0x05, 0x00, 0x00, 0x00, // mov dword [rsp], 5
+    0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_FORK
+    0x0f, 0x05, // syscall
+    0x48, 0x85, 0xc0, // test rax, rax
+    0x75, 0x1a, // jnz parent
+    0xbf, 0x14, 0x00, 0x00, 0x00, // mov edi, 20
+    0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, SYS_SLEEP
+    0x0f, 0x05, // syscall
+    0x83, 0x04, 0x24, 0x02, // add dword [rsp], 2
+    0x8b, 0x3c, 0x24, // mov edi, [rsp]
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+    0x48, 0x89, 0xc7, // parent: mov rdi, rax
+    0x48, 0x8d, 0x74, 0x24, 0x08, // lea rsi, [rsp + 8]
+    0xb8, 0x07, 0x00, 0x00, 0x00, // mov eax, SYS_WAIT
+    0x0f, 0x05, // syscall
+    0x8b, 0x7c, 0x24, 0x08, // mov edi, [rsp + 8]
+    0x6b, 0xff, 0x0a, // imul edi, edi, 10
+    0x03, 0x3c, 0x24, // add edi, [rsp]
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0f, 0x05, // syscall
+];
+
+// 只有一个可读可写可执行段的静态程序
+fn build_program(code: &[u8]) -> Vec<u8> {
+    let size = CODE_OFFSET + code.len();
+    let segment = Segment {
+        flags: SegmentFlags::all(),
+        offset: 0,
+        vaddr: BASE,
+        file_size: size,
+        mem_size: size,
+    };
+    build_elf(
+        ET_EXEC,
+        BASE + CODE_OFFSET,
+        &[segment],
+        &[(CODE_OFFSET, code)],
+    )
+}
+
+pub fn process_table() -> TestResult {
+    let kernel = process::process_info(KERNEL_PID).unwrap();
+    expect_eq!(kernel.parent, None);
+    expect_eq!(process::current_id(), KERNEL_PID);
+    expect_eq!(process::handle(1), Some(Handle::Console));
+    expect_eq!(process::handle(2), Some(Handle::Serial));
+    expect_eq!(process::handle(0), None);
+
+    let file = build_program(&EXIT_PROGRAM);
+    let pid = loader::load(&file, &["exit"], &[])
+        .unwrap()
+        .spawn("exit program")
+        .unwrap();
+    // 退出之后在被wait回收之前信息仍然保留
+    let info = process::process_info(pid).unwrap();
+    expect_eq!(info.parent, Some(KERNEL_PID));
+    expect_eq!(info.name, "exit program");
+    expect_eq!(info.credentials, Credentials::ROOT);
+
+    expect_eq!(process::wait(None), Some((pid, 3)));
+    expect_eq!(
+        process::process_info(pid).is_none(),
+        true,
+        "zombie not reaped"
+    );
+    expect_eq!(process::wait(Some(pid)), None);
+    expect_eq!(process::wait(None), None, "unexpected child");
+
+    let fork = process::fork(&InterruptContext::default());
+    expect_eq!(fork, Err(ProcessError::KernelProcess));
+    TestResult::Passed
+}
+
+pub fn process_fork_and_wait() -> TestResult {
+    let file = build_program(&FORK_PROGRAM);
+    let pid = loader::load(&file, &["fork"], &[])
+        .unwrap()
+        .spawn("fork program")
+        .unwrap();
+    let result = process::wait(Some(pid));
+    // 子进程的退出码经过父进程的wait传回来
+    expect_eq!(
+        result.map(|(id, status)| (id, status & 0xff)),
+        Some((pid, 8))
+    );
+
+    // 子进程已经被父进程的wait回收
+    let child = ProcessId::new(result.map_or(0, |(_, status)| status as usize >> 8));
+    let is_parent = child == pid;
+    expect_eq!(is_parent, false, "parent reported itself as the child");
+    expect_eq!(
+        process::process_info(child).is_none(),
+        true,
+        "child not reaped"
+    );
+    TestResult::Passed
+}
+
+// SHARED_FRAMES只在持有MemoryController的锁时使用
+fn shared_frames() -> usize {
+    let _controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    SHARED_FRAMES.lock().len()
+}
+
+pub fn process_copy_on_write() -> TestResult {
+    let shared_before = shared_frames();
+    let file = build_program(&COW_PROGRAM);
+    let pid = loader::load(&file, &["cow"], &[])
+        .unwrap()
+        .spawn("cow program")
+        .unwrap();
+
+    // 子进程睡眠时父子进程共享所有的页
+    let deadline = pit::ticks() + 10 * pit::TIMER_HZ;
+    let mut shared_during = shared_before;
+    while shared_during == shared_before && pit::ticks() < deadline {
+        task::yield_now();
+        shared_during = shared_frames();
+    }
+    let shared = shared_during > shared_before;
+    expect_eq!(
+        shared,
+        true,
+        "fork copied the pages instead of sharing them"
+    );
+
+    // 子进程的写入没有影响父进程
+    expect_eq!(process::wait(Some(pid)), Some((pid, 75)));
+
+    // 两个进程的页都释放之后不再有共享的帧
+    let shared_after = shared_frames();
+    expect_eq!(shared_after, shared_before, "shared frames leaked");
+    TestResult::Passed
+}
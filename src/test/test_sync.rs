@@ -0,0 +1,153 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    expect_eq,
+    interrupts::pit,
+    task::{
+        self,
+        sync::{Condvar, Mutex, RwLock, Semaphore, WaitQueue},
+    },
+    utils::test_frameworks::TestResult,
+};
+
+pub fn wait_queue_timeout() -> TestResult {
+    let queue = Arc::new(WaitQueue::new());
+    let start = pit::ticks();
+    let result = queue.wait_until(|| false, Some(3));
+    let elapsed = pit::ticks() - start;
+    expect_eq!(result, false);
+    let waited = elapsed >= 3;
+    expect_eq!(waited, true, "returned before the timeout");
+    expect_eq!(queue.len(), 0, "timed out task left in the queue");
+
+    let start = pit::ticks();
+    task::sleep(2);
+    let slept = pit::ticks() - start >= 2;
+    expect_eq!(slept, true, "sleep returned early");
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let handle = {
+        let queue = queue.clone();
+        let flag = flag.clone();
+        task::spawn("notifier", move || {
+            task::sleep(1);
+            flag.store(true, Ordering::SeqCst);
+            queue.notify_all()
+        })
+    };
+    let result = queue.wait_until(|| flag.load(Ordering::SeqCst), None);
+    expect_eq!(result, true);
+    expect_eq!(handle.join(), Some(1), "waiter was not in the queue");
+    TestResult::Passed
+}
+
+pub fn sleeping_mutex() -> TestResult {
+    let counter = Arc::new(Mutex::new(0));
+    let spawn_worker = |name| {
+        let counter = counter.clone();
+        task::spawn(name, move || {
+            for _ in 0..10 {
+                let mut guard = counter.lock();
+                let value = *guard;
+                // 持有锁时让出CPU，其他任务只能阻塞等待
+                task::yield_now();
+                *guard = value + 1;
+            }
+        })
+    };
+    let handles = [
+        spawn_worker("mutex worker a"),
+        spawn_worker("mutex worker b"),
+        spawn_worker("mutex worker c"),
+    ];
+    for handle in handles {
+        handle.join();
+    }
+    expect_eq!(*counter.lock(), 30, "lost an update");
+
+    let guard = counter.lock();
+    let timed_out = counter.try_lock_for(2).is_none();
+    expect_eq!(timed_out, true, "locked the mutex twice");
+    drop(guard);
+    let locked = counter.try_lock_for(2).is_some();
+    expect_eq!(locked, true);
+    TestResult::Passed
+}
+
+pub fn semaphore_handoff() -> TestResult {
+    let semaphore = Arc::new(Semaphore::new(0));
+    expect_eq!(semaphore.acquire_timeout(2), false);
+
+    let handle = {
+        let semaphore = semaphore.clone();
+        task::spawn("consumer", move || {
+            for _ in 0..3 {
+                semaphore.acquire();
+            }
+            semaphore.count()
+        })
+    };
+    for _ in 0..3 {
+        task::sleep(1);
+        semaphore.release();
+    }
+    expect_eq!(handle.join(), Some(0));
+    TestResult::Passed
+}
+
+pub fn condvar_notify() -> TestResult {
+    let state = Arc::new((Mutex::new(0), Condvar::new()));
+    let handle = {
+        let state = state.clone();
+        task::spawn("condvar waiter", move || {
+            let (value, condvar) = &*state;
+            let (guard, timed_out) = condvar.wait_while(value.lock(), |v| *v < 3, None);
+            (*guard, timed_out)
+        })
+    };
+
+    let (value, condvar) = &*state;
+    for _ in 0..3 {
+        task::sleep(1);
+        *value.lock() += 1;
+        condvar.notify_one();
+    }
+    expect_eq!(handle.join(), Some((3, false)));
+
+    let (_, timed_out) = condvar.wait_timeout(value.lock(), Some(2));
+    expect_eq!(timed_out, true);
+    TestResult::Passed
+}
+
+pub fn rwlock_readers_and_writer() -> TestResult {
+    let lock = Arc::new(RwLock::new(0));
+    let written = Arc::new(AtomicBool::new(false));
+
+    let read = lock.read();
+    let second_read = lock.try_read().is_some();
+    expect_eq!(second_read, true, "readers excluded each other");
+
+    let handle = {
+        let lock = lock.clone();
+        let written = written.clone();
+        task::spawn("writer", move || {
+            *lock.write() = 1;
+            written.store(true, Ordering::SeqCst);
+        })
+    };
+    task::sleep(2);
+    expect_eq!(
+        written.load(Ordering::SeqCst),
+        false,
+        "writer ran while read locked"
+    );
+    // 写者在等待，新的读者不能进入
+    let blocked = lock.try_read().is_none();
+    expect_eq!(blocked, true, "reader overtook a waiting writer");
+    drop(read);
+
+    handle.join();
+    expect_eq!(*lock.read(), 1);
+    TestResult::Passed
+}
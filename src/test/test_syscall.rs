@@ -0,0 +1,139 @@
+use crate::{
+    expect_eq,
+    interrupts::InterruptContext,
+    memory::{MEMORY_CONTROLLER, PAGE_SIZE, paging::EntryFlags},
+    syscall::{self, PROT_READ, PROT_WRITE, SYS_GETPID, SYS_MMAP, SyscallError, encode_result},
+    task::{self, process, user},
+    utils::{test_frameworks::TestResult, x86_64_control::interrupts},
+};
+
+const USER_CODE: usize = 0x40_0000;
+const USER_STACK: usize = 0x80_0000;
+
+// 每次系统调用之后把rax压栈，最后用exit结束
+const USER_PROGRAM: [u8; 85] = [
+    0xb8, 0x04, 0x00, 0x00, 0x00, // mov eax, SYS_GETPID
+    0x0f, 0x05, // syscall
+    0x50, // push rax
+    0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE
+    0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, STDOUT
+    0x48, 0x8d, 0x35, 0x2a, 0x00, 0x00, 0x00, // lea rsi, [rip + message]
+    0xba, 0x12, 0x00, 0x00, 0x00, // mov edx, 18
+    0xcd, 0x80, // int 0x80
+    0x50, // push rax
+    0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE
+    0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, STDOUT
+    0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 0x1000，不在用户空间
+    0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    0x0f, 0x05, // syscall
+    0x50, // push rax
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x31, 0xff, // xor edi, edi
+    0x0f, 0x05, // syscall
+    0x0f, 0x0b, // ud2
+    // message
+    b'h', b'e', b'l', b'l', b'o', b' ', b'f', b'r', b'o', b'm', b' ', b'r', b'i', b'n', b'g', b' ',
+    b'3', b'\n',
+];
+
+fn stack_slot(index: usize) -> u64 {
+    unsafe { *((USER_STACK + PAGE_SIZE - 8 * (index + 1)) as *const u64) }
+}
+
+pub fn user_syscalls() -> TestResult {
+    {
+        let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        memory_controller
+            .map_user_pages(USER_CODE, 1, EntryFlags::WRITABLE)
+            .unwrap();
+        memory_controller
+            .map_user_pages(USER_STACK, 1, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)
+            .unwrap();
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            USER_PROGRAM.as_ptr(),
+            USER_CODE as *mut u8,
+            USER_PROGRAM.len(),
+        );
+    }
+
+    let handle = task::spawn::<_, ()>("syscall program", || {
+        user::enter_user_mode(USER_CODE, USER_STACK + PAGE_SIZE)
+    });
+    expect_eq!(handle.join(), None, "user task returned a value");
+
+    // 内核线程进入用户态时仍然属于内核进程
+    let pid = process::KERNEL_PID.as_usize() as u64;
+    expect_eq!(stack_slot(0), pid, "getpid");
+    expect_eq!(stack_slot(1), 18, "write through int 0x80");
+    let bad_address = encode_result(Err(SyscallError::BadAddress));
+    expect_eq!(stack_slot(2), bad_address, "write from a kernel address");
+
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    memory_controller.unmap_user_pages(USER_CODE, 1);
+    memory_controller.unmap_user_pages(USER_STACK, 1);
+    TestResult::Passed
+}
+
+// 在内核中直接调用分发函数，dispatch返回时关闭了中断
+fn dispatch(number: usize, args: [usize; 3]) -> u64 {
+    let mut context = InterruptContext {
+        rax: number as u64,
+        rdi: args[0] as u64,
+        rsi: args[1] as u64,
+        rdx: args[2] as u64,
+        ..Default::default()
+    };
+    syscall::dispatch(&mut context);
+    interrupts::enable();
+    context.rax
+}
+
+pub fn syscall_dispatch() -> TestResult {
+    expect_eq!(
+        dispatch(SYS_GETPID, [0; 3]),
+        process::current_id().as_usize() as u64
+    );
+    expect_eq!(
+        dispatch(99, [0; 3]),
+        encode_result(Err(SyscallError::NoSuchSyscall))
+    );
+
+    let address = dispatch(SYS_MMAP, [0, PAGE_SIZE + 1, PROT_READ | PROT_WRITE]) as usize;
+    let accessible =
+        MEMORY_CONTROLLER
+            .get()
+            .unwrap()
+            .lock()
+            .is_user_accessible(address, 2 * PAGE_SIZE, true);
+    expect_eq!(accessible, true, "mmap did not map two writable pages");
+    expect_eq!(
+        dispatch(SYS_MMAP, [address, PAGE_SIZE, PROT_READ]),
+        encode_result(Err(SyscallError::InvalidArgument)),
+        "mmap over an existing mapping"
+    );
+    expect_eq!(
+        dispatch(SYS_MMAP, [address + 1, PAGE_SIZE, PROT_READ]),
+        encode_result(Err(SyscallError::InvalidArgument)),
+        "unaligned mmap"
+    );
+
+    // 失败的请求不移动起始地址，之后的mmap接着上一次成功的映射
+    expect_eq!(
+        dispatch(SYS_MMAP, [0, 1 << 63, PROT_READ]),
+        encode_result(Err(SyscallError::InvalidArgument)),
+        "mmap larger than user space"
+    );
+    let next = dispatch(SYS_MMAP, [0, PAGE_SIZE, PROT_READ]) as usize;
+    expect_eq!(
+        next,
+        address + 2 * PAGE_SIZE,
+        "mmap hint moved by a failed call"
+    );
+
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    memory_controller.unmap_user_pages(address, 2);
+    memory_controller.unmap_user_pages(next, 1);
+    TestResult::Passed
+}
@@ -0,0 +1,199 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{
+    expect_eq,
+    interrupts::pit,
+    task::{self, policy::SchedulingPolicy},
+    utils::test_frameworks::TestResult,
+};
+
+pub fn kernel_threads() -> TestResult {
+    let log = Arc::new(Mutex::new(Vec::with_capacity(8)));
+
+    let spawn_worker = |name, tag| {
+        let log = log.clone();
+        // 只在yield_now时切换，才能得到固定的顺序
+        task::spawn(name, move || {
+            task::without_preemption(|| {
+                for i in 0..3 {
+                    log.lock().push((tag, i));
+                    task::yield_now();
+                }
+            });
+            tag
+        })
+    };
+    let (a, b, ran_early) = task::without_preemption(|| {
+        let a = spawn_worker("worker a", 'a');
+        let b = spawn_worker("worker b", 'b');
+        (a, b, log.lock().len())
+    });
+    expect_eq!(ran_early, 0, "thread ran before the spawner yielded");
+
+    let (a, b) = (a.join(), b.join());
+    expect_eq!(a, Some('a'));
+    expect_eq!(b, Some('b'));
+    expect_eq!(
+        log.lock()[..],
+        [('a', 0), ('b', 0), ('a', 1), ('b', 1), ('a', 2), ('b', 2)],
+        "threads did not run in turn"
+    );
+    TestResult::Passed
+}
+
+pub fn thread_exit() -> TestResult {
+    let handle = task::spawn("exiting thread", || -> usize { task::exit() });
+    expect_eq!(handle.join(), None);
+
+    let handle = task::spawn("next thread", task::current_id);
+    let id = handle.id();
+    expect_eq!(handle.join(), Some(id));
+    expect_eq!(task::current_name(), "main");
+    TestResult::Passed
+}
+
+pub fn preemption() -> TestResult {
+    let started = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(AtomicBool::new(false));
+    let handle = {
+        let started = started.clone();
+        let stop = stop.clone();
+        task::spawn("spinner", move || {
+            started.store(true, Ordering::SeqCst);
+            while !stop.load(Ordering::SeqCst) {
+                hint::spin_loop();
+            }
+        })
+    };
+
+    // 两个任务都不让出CPU，只有时钟中断能让它们轮流运行
+    while !started.load(Ordering::SeqCst) {
+        hint::spin_loop();
+    }
+    while task::task_info(handle.id()).unwrap().run_ticks == 0 {
+        hint::spin_loop();
+    }
+    stop.store(true, Ordering::SeqCst);
+
+    let id = handle.id();
+    expect_eq!(handle.join(), Some(()));
+    let reaped = task::task_info(id).is_none();
+    expect_eq!(reaped, true, "finished thread was not reaped");
+    TestResult::Passed
+}
+
+pub fn block_and_wake() -> TestResult {
+    let woken = Arc::new(AtomicBool::new(false));
+    let handle = {
+        let woken = woken.clone();
+        task::spawn("sleeper", move || {
+            task::block_current();
+            woken.load(Ordering::SeqCst)
+        })
+    };
+    let id = handle.id();
+
+    while task::task_info(id).unwrap().state != task::TaskState::Blocked {
+        task::yield_now();
+    }
+    // 阻塞的任务不在运行队列中，让出CPU也不会运行它
+    task::yield_now();
+    expect_eq!(task::task_info(id).unwrap().state, task::TaskState::Blocked);
+
+    woken.store(true, Ordering::SeqCst);
+    task::wake(id);
+    expect_eq!(handle.join(), Some(true));
+    TestResult::Passed
+}
+
+pub fn fixed_priority_policy() -> TestResult {
+    let old_policy = task::policy();
+    task::set_policy(SchedulingPolicy::FixedPriority);
+
+    let log = Arc::new(Mutex::new(Vec::with_capacity(2)));
+    let spawn_worker = |name, tag, priority| {
+        let log = log.clone();
+        let handle = task::spawn(name, move || log.lock().push(tag));
+        task::set_priority(handle.id(), priority);
+        handle
+    };
+    // 先创建的任务优先级低，设置好优先级之前不能运行
+    let (low, high) = task::without_preemption(|| {
+        (
+            spawn_worker("low priority", 'l', 1),
+            spawn_worker("high priority", 'h', 6),
+        )
+    });
+    low.join();
+    high.join();
+    task::set_policy(old_policy);
+
+    expect_eq!(log.lock()[..], ['h', 'l'], "lower priority task ran first");
+    TestResult::Passed
+}
+
+pub fn fair_policy_starvation_freedom() -> TestResult {
+    let old_policy = task::policy();
+    task::set_policy(SchedulingPolicy::Fair);
+    let switches = task::stats().context_switches;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let spawn_spinner = |name, nice| {
+        let stop = stop.clone();
+        let handle = task::spawn(name, move || {
+            while !stop.load(Ordering::SeqCst) {
+                hint::spin_loop();
+            }
+        });
+        task::set_nice(handle.id(), nice);
+        handle
+    };
+    let (heavy, light) = task::without_preemption(|| {
+        (
+            spawn_spinner("heavy spinner", -5),
+            spawn_spinner("light spinner", 5),
+        )
+    });
+
+    // 权重小的任务也要在限定时间内分到CPU
+    let deadline = pit::ticks() + 10 * pit::TIMER_HZ;
+    while task::task_info(light.id()).unwrap().run_ticks < 3 && pit::ticks() < deadline {
+        task::yield_now();
+    }
+    stop.store(true, Ordering::SeqCst);
+    let heavy_info = task::task_info(heavy.id()).unwrap();
+    let light_info = task::task_info(light.id()).unwrap();
+    heavy.join();
+    light.join();
+    let stats = task::stats();
+    task::set_policy(old_policy);
+
+    let starved = light_info.run_ticks < 3;
+    expect_eq!(starved, false, "light task starved");
+    let weighted = heavy_info.run_ticks > light_info.run_ticks;
+    expect_eq!(weighted, true, "nice did not change the CPU share");
+    expect_eq!(light_info.nice, 5);
+    let switched = light_info.context_switches > 0 && stats.context_switches > switches;
+    expect_eq!(switched, true, "context switches were not counted");
+    expect_eq!(stats.policy, SchedulingPolicy::Fair);
+    TestResult::Passed
+}
+
+pub fn scheduling_policy_names() -> TestResult {
+    expect_eq!(
+        SchedulingPolicy::from_command_line("heap_debug scheduler=fair"),
+        Some(SchedulingPolicy::Fair)
+    );
+    expect_eq!(
+        SchedulingPolicy::from_name("fixed_priority"),
+        Some(SchedulingPolicy::FixedPriority)
+    );
+    expect_eq!(SchedulingPolicy::from_name("lottery"), None);
+    TestResult::Passed
+}
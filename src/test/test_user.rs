@@ -0,0 +1,46 @@
+use crate::{
+    expect_eq, interrupts,
+    memory::{MEMORY_CONTROLLER, PAGE_SIZE, paging::EntryFlags},
+    task::{self, user},
+    utils::test_frameworks::TestResult,
+};
+
+const USER_CODE: usize = 0x40_0000;
+const USER_STACK: usize = 0x80_0000;
+
+// mov eax, cs; push rax; hlt
+// hlt是特权指令，在Ring3执行会产生#GP，任务被结束
+const USER_PROGRAM: [u8; 4] = [0x8c, 0xc8, 0x50, 0xf4];
+
+pub fn user_mode() -> TestResult {
+    {
+        let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+        memory_controller
+            .map_user_pages(USER_CODE, 1, EntryFlags::WRITABLE)
+            .unwrap();
+        memory_controller
+            .map_user_pages(USER_STACK, 1, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)
+            .unwrap();
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            USER_PROGRAM.as_ptr(),
+            USER_CODE as *mut u8,
+            USER_PROGRAM.len(),
+        );
+    }
+
+    let handle = task::spawn::<_, ()>("user program", || {
+        user::enter_user_mode(USER_CODE, USER_STACK + PAGE_SIZE)
+    });
+    expect_eq!(handle.join(), None, "user task was not killed");
+
+    let code_segment = unsafe { *((USER_STACK + PAGE_SIZE - 8) as *const u64) };
+    let expected = interrupts::selectors().user_code.value() as u64;
+    expect_eq!(code_segment, expected, "user code did not run in ring 3");
+
+    let mut memory_controller = MEMORY_CONTROLLER.get().unwrap().lock();
+    memory_controller.unmap_user_pages(USER_CODE, 1);
+    memory_controller.unmap_user_pages(USER_STACK, 1);
+    TestResult::Passed
+}
@@ -1,4 +1,4 @@
-use crate::{serial_print, serial_println};
+use crate::{memory::allocator::tracking, serial_print, serial_println};
 use core::panic::PanicInfo;
 
 #[derive(Debug)]
@@ -75,13 +75,23 @@ pub fn test_main() {
 
     for test in TEST_REGISTRY.iter() {
         serial_print!("{}\t", test.name);
-        match (test.func)() {
+        let mark = tracking::mark();
+        let result = (test.func)();
+        // 测试结束后还没有释放的分配都算作泄漏
+        let result = match result {
+            TestResult::Passed if tracking::leaks_since(mark) > 0 => {
+                TestResult::Failed("memory leaked, see the allocations below")
+            }
+            result => result,
+        };
+        match result {
             TestResult::Passed => {
                 serial_println!("[PASSED]\t");
                 passed += 1;
             }
             TestResult::Failed(msg) => {
                 serial_println!("[FAILED]: {}", msg);
+                tracking::report_leaks_since(mark);
                 failed += 1;
             }
         }
@@ -0,0 +1,18 @@
+use core::arch::x86_64::__cpuid_count;
+
+// CPUID.01H:ECX
+const PCID: u32 = 1 << 17;
+// CPUID.(EAX=07H, ECX=0):EBX
+const INVPCID: u32 = 1 << 10;
+
+fn max_leaf() -> u32 {
+    __cpuid_count(0, 0).eax
+}
+
+pub fn has_pcid() -> bool {
+    __cpuid_count(1, 0).ecx & PCID != 0
+}
+
+pub fn has_invpcid() -> bool {
+    max_leaf() >= 7 && __cpuid_count(7, 0).ebx & INVPCID != 0
+}
@@ -0,0 +1,29 @@
+use core::arch::asm;
+
+pub const PHYSICAL_ADDRESS_EXTENSION: u64 = 1 << 5;
+pub const PAGE_GLOBAL_ENABLE: u64 = 1 << 7;
+pub const PCID_ENABLE: u64 = 1 << 17;
+
+#[inline]
+pub fn read_cr4() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!(
+            "mov {}, cr4",
+            out(reg) value,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    value
+}
+
+#[inline]
+pub fn write_cr4(val: u64) {
+    unsafe {
+        asm!(
+            "mov cr4, {}",
+            in(reg) val,
+            options(nostack, preserves_flags)
+        );
+    }
+}
@@ -11,7 +11,9 @@ impl Gdt {
         }
     }
 
+    // 返回的选择子的RPL和描述符的DPL相同
     pub fn add_entry(&mut self, entry: Descriptor) -> SegmentSelector {
+        let rpl = entry.privilege_level();
         let index = match entry {
             Descriptor::UserSegment(value) => self.push(value),
             Descriptor::SystemSegment(value_low, value_high) => {
@@ -21,7 +23,7 @@ impl Gdt {
             }
         };
 
-        SegmentSelector::new(index as u16, PrivilegeLevel::Ring0)
+        SegmentSelector::new(index as u16, rpl)
     }
 
     fn push(&mut self, value: u64) -> usize {
@@ -61,9 +63,11 @@ use crate::utils::x86_64_control::segmentation::{
 };
 bitflags! {
     struct DescriptorFlags: u64 {
+        const WRITABLE          = 1 << 41;
         const CONFORMING        = 1 << 42;
         const EXECUTABLE        = 1 << 43;
         const USER_SEGMENT      = 1 << 44;
+        const DPL_RING_3        = 3 << 45;
         const PRESENT           = 1 << 47;
         const LONG_MODE         = 1 << 53;
     }
@@ -78,6 +82,40 @@ impl Descriptor {
         Descriptor::UserSegment(flags.bits())
     }
 
+    pub fn kernel_data_segment() -> Descriptor {
+        let flags =
+            DescriptorFlags::USER_SEGMENT | DescriptorFlags::PRESENT | DescriptorFlags::WRITABLE;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    pub fn user_code_segment() -> Descriptor {
+        let flags = DescriptorFlags::USER_SEGMENT
+            | DescriptorFlags::PRESENT
+            | DescriptorFlags::EXECUTABLE
+            | DescriptorFlags::LONG_MODE
+            | DescriptorFlags::DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    pub fn user_data_segment() -> Descriptor {
+        let flags = DescriptorFlags::USER_SEGMENT
+            | DescriptorFlags::PRESENT
+            | DescriptorFlags::WRITABLE
+            | DescriptorFlags::DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    // 描述符的DPL，系统段只在Ring0使用
+    pub fn privilege_level(&self) -> PrivilegeLevel {
+        match self {
+            Descriptor::UserSegment(value) => {
+                let dpl = DescriptorFlags::from_bits_truncate(*value) & DescriptorFlags::DPL_RING_3;
+                PrivilegeLevel::from_u16((dpl.bits() >> 45) as u16)
+            }
+            Descriptor::SystemSegment(..) => PrivilegeLevel::Ring0,
+        }
+    }
+
     pub fn tss_segment(tss: &'static TaskStateSegment) -> Descriptor {
         use bit_field::BitField;
         use core::mem::size_of;
@@ -0,0 +1,51 @@
+use core::arch::asm;
+
+const INTERRUPT_FLAG: u64 = 1 << 9;
+
+#[inline]
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags & INTERRUPT_FLAG != 0
+}
+
+#[inline]
+pub fn enable() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+#[inline]
+pub fn disable() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+// 打开中断并等待下一个中断，两条指令之间不会响应中断
+#[inline]
+pub fn enable_and_hlt() {
+    unsafe { asm!("sti; hlt", options(nomem, nostack)) };
+}
+
+// 停机直到下一个中断，之后继续停机。用于无法恢复的异常，这时中断已经关闭
+pub fn hlt_loop() -> ! {
+    loop {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+// 关中断执行f，之后恢复原来的状态。持有中断处理程序也会用到的锁时使用
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let enabled = are_enabled();
+    if enabled {
+        disable();
+    }
+    let result = f();
+    if enabled {
+        enable();
+    }
+    result
+}
@@ -1,12 +1,16 @@
 use crate::utils::x86_64_control::{
     cr0::{WRITE_PROTECT, read_cr0, write_cr0},
+    cr4::{PCID_ENABLE, read_cr4, write_cr4},
     msr::{IA32_EFER, rdmsr, wrmsr},
 };
 
+pub mod cpuid;
 pub mod cr0;
 pub mod cr2;
 pub mod cr3;
+pub mod cr4;
 pub mod gdt;
+pub mod interrupts;
 pub mod msr;
 pub mod segmentation;
 pub mod tlb;
@@ -17,13 +21,43 @@ pub fn enable_nxe_bit() {
     wrmsr(IA32_EFER, efer | nxe_bit);
 }
 
+// 打开后才能使用SYSCALL/SYSRET指令
+pub fn enable_syscall_extensions() {
+    let sce_bit = 1 << 0;
+    let efer = rdmsr(IA32_EFER);
+    wrmsr(IA32_EFER, efer | sce_bit);
+}
+
 pub fn enable_write_protect_bit() {
     let value = read_cr0();
     write_cr0(value | WRITE_PROTECT);
 }
 
+// CR3的低12位作为PCID，切换页表时可以保留其他地址空间的TLB项。
+// 打开时CR3的低12位必须为0
+pub fn enable_pcid() -> bool {
+    if !cpuid::has_pcid() {
+        return false;
+    }
+    write_cr4(read_cr4() | PCID_ENABLE);
+    true
+}
+
+pub fn pcid_enabled() -> bool {
+    read_cr4() & PCID_ENABLE != 0
+}
+
 pub fn software_interrupt<const N: u8>() {
     unsafe {
         core::arch::asm!("int {}", const N, options(nomem, nostack));
     }
 }
+
+#[inline(always)]
+pub fn read_rbp() -> u64 {
+    let value: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
@@ -1,6 +1,13 @@
 use core::arch::asm;
 
 pub const IA32_EFER: u32 = 0xc0000080;
+// SYSCALL/SYSRET使用的段选择子
+pub const IA32_STAR: u32 = 0xc0000081;
+// SYSCALL的入口地址
+pub const IA32_LSTAR: u32 = 0xc0000082;
+// SYSCALL时要清除的RFLAGS位
+pub const IA32_FMASK: u32 = 0xc0000084;
+
 #[inline]
 pub fn wrmsr(msr: u32, value: u64) {
     let low = value as u32;
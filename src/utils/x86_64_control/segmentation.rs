@@ -92,6 +92,11 @@ impl SegmentSelector {
         SegmentSelector(0)
     }
 
+    #[inline]
+    pub const fn value(self) -> u16 {
+        self.0
+    }
+
     #[inline]
     pub fn index(self) -> u16 {
         (self.0 >> 3) & 0x1FFF
@@ -122,13 +127,16 @@ pub unsafe fn lidt(idt: &Dtr) {
     }
 }
 
+// TSS中interrupt_stack_table的项数
+pub const IST_ENTRIES: usize = 7;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed(4))]
 pub struct TaskStateSegment {
     reserved_1: u32,
     pub privilege_stack_table: [VirtualAddress; 3],
     reserved_2: u64,
-    pub interrupt_stack_table: [VirtualAddress; 7],
+    pub interrupt_stack_table: [VirtualAddress; IST_ENTRIES],
     reserved_3: u64,
     reserved_4: u16,
     pub iomap_base: u16,
@@ -139,7 +147,7 @@ impl TaskStateSegment {
     pub const fn new() -> TaskStateSegment {
         TaskStateSegment {
             privilege_stack_table: [0; 3],
-            interrupt_stack_table: [0; 7],
+            interrupt_stack_table: [0; IST_ENTRIES],
             iomap_base: size_of::<TaskStateSegment>() as u16,
             reserved_1: 0,
             reserved_2: 0,
@@ -147,6 +155,39 @@ impl TaskStateSegment {
             reserved_4: 0,
         }
     }
+
+    // index是interrupt_stack_table的下标，对应IDT中的IST编号index + 1
+    pub fn set_interrupt_stack(&mut self, index: usize, stack_top: VirtualAddress) {
+        assert!(
+            index < IST_ENTRIES,
+            "IST index {} out of range 0..{}",
+            index,
+            IST_ENTRIES
+        );
+        self.interrupt_stack_table[index] = stack_top;
+    }
+
+    pub fn interrupt_stack(&self, index: usize) -> VirtualAddress {
+        assert!(index < IST_ENTRIES, "IST index {} out of range", index);
+        self.interrupt_stack_table[index]
+    }
+
+    // 从更低特权级进入level时使用的栈，没有进入Ring3的切换
+    pub fn set_privilege_stack(&mut self, level: PrivilegeLevel, stack_top: VirtualAddress) {
+        assert!(
+            !matches!(level, PrivilegeLevel::Ring3),
+            "no privilege stack for ring 3"
+        );
+        self.privilege_stack_table[level as usize] = stack_top;
+    }
+
+    pub fn privilege_stack(&self, level: PrivilegeLevel) -> VirtualAddress {
+        assert!(
+            !matches!(level, PrivilegeLevel::Ring3),
+            "no privilege stack for ring 3"
+        );
+        self.privilege_stack_table[level as usize]
+    }
 }
 
 #[inline]
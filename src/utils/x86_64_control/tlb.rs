@@ -1,6 +1,14 @@
 use core::arch::asm;
 
-use crate::utils::x86_64_control::cr3::{read_cr3, write_cr3};
+use spin::Lazy;
+
+use crate::utils::x86_64_control::{
+    cpuid,
+    cr3::{read_cr3, write_cr3},
+    cr4::{PAGE_GLOBAL_ENABLE, read_cr4, write_cr4},
+};
+
+static INVPCID_SUPPORTED: Lazy<bool> = Lazy::new(cpuid::has_invpcid);
 
 #[inline]
 pub fn tlb_flush(addr: u64) {
@@ -13,7 +21,85 @@ pub fn tlb_flush(addr: u64) {
     }
 }
 
+// 打开PCID后只刷新当前PCID的非全局项
 pub fn tlb_flush_all() {
     let value = read_cr3();
     write_cr3(value);
 }
+
+#[repr(u64)]
+#[derive(Debug, Clone, Copy)]
+enum InvpcidType {
+    Address = 0,
+    SingleContext = 1,
+    AllContextsIncludingGlobal = 2,
+    AllContexts = 3,
+}
+
+#[repr(C)]
+struct InvpcidDescriptor {
+    pcid: u64,
+    address: u64,
+}
+
+fn invpcid(kind: InvpcidType, pcid: u16, address: u64) {
+    let descriptor = InvpcidDescriptor {
+        pcid: pcid as u64,
+        address,
+    };
+    unsafe {
+        asm!(
+            "invpcid {}, [{}]",
+            in(reg) kind as u64,
+            in(reg) &descriptor,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+// 修改CR4.PGE会刷新所有PCID的全部TLB项，没有INVPCID时用它代替
+fn flush_everything() {
+    let value = read_cr4();
+    write_cr4(value ^ PAGE_GLOBAL_ENABLE);
+    write_cr4(value);
+}
+
+pub fn invpcid_supported() -> bool {
+    *INVPCID_SUPPORTED
+}
+
+// 刷新pcid中address所在页的TLB项
+pub fn tlb_flush_pcid_address(pcid: u16, address: u64) {
+    if invpcid_supported() {
+        invpcid(InvpcidType::Address, pcid, address);
+    } else {
+        flush_everything();
+    }
+}
+
+// 刷新pcid的所有非全局TLB项
+pub fn tlb_flush_pcid(pcid: u16) {
+    if invpcid_supported() {
+        invpcid(InvpcidType::SingleContext, pcid, 0);
+    } else {
+        flush_everything();
+    }
+}
+
+// 刷新所有PCID的非全局TLB项
+pub fn tlb_flush_all_contexts() {
+    if invpcid_supported() {
+        invpcid(InvpcidType::AllContexts, 0, 0);
+    } else {
+        flush_everything();
+    }
+}
+
+// 刷新所有PCID的全部TLB项，包括全局页
+pub fn tlb_flush_everything() {
+    if invpcid_supported() {
+        invpcid(InvpcidType::AllContextsIncludingGlobal, 0, 0);
+    } else {
+        flush_everything();
+    }
+}
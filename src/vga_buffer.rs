@@ -156,7 +156,10 @@ impl fmt::Write for Writer {
 
 use spin::Mutex;
 
-const VGA_ADRESS: u32 = 0xb8000;
+use crate::memory::paging::PHYS_MEM_OFFSET;
+
+// VGA文本缓冲区通过物理内存直接映射访问
+const VGA_ADRESS: usize = PHYS_MEM_OFFSET + 0xb8000;
 
 pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
     column_position: 0,